@@ -1,4 +1,4 @@
-/**
+/*
  * RecoReco
  * Copyright (C) 2018 Sebastian Schelter
  *
@@ -16,16 +16,35 @@
  * along with this program. If not, see <http://www.gnu.org/licenses/>.
  */
 
-extern crate csv;
 extern crate recoreco;
 extern crate getopts;
+extern crate rayon;
 
-use std::error::Error;
+use std::cmp::Ordering;
 use std::env;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Read;
+use std::process;
+use std::time::Instant;
 use getopts::Options;
 
-use recoreco::io;
+use recoreco::io::{decompressed, Column, InteractionReader, InteractionReaderBuilder};
+use recoreco::io::MalformedLines;
+use recoreco::config::IndicatorConfig;
+use recoreco::dedup;
+use recoreco::dedup::{Deduplication, Deduplicator};
+use recoreco::error::Error;
+use recoreco::evaluation;
+use recoreco::evaluation::{Metrics, Split};
+use recoreco::snapshot;
+use recoreco::model::IndicatorModel;
+use recoreco::measures;
+use recoreco::measures::{Measure, Polarity};
+use recoreco::recommend::Recommender;
+use recoreco::server;
 use recoreco::stats::{DataDictionary, Renaming};
+use recoreco::weights::Weighting;
 
 fn main() {
 
@@ -33,13 +52,124 @@ fn main() {
     let program = args[0].clone();
 
     let mut opts = Options::new();
-    opts.optopt("i", "inputfile", "Input file name (required). The input consists of interactions \
-        between user and items. The input file must contain a user and item pair per line, \
-        separated by a tab.", "PATH");
+    opts.optopt("i", "inputfile", "Input file name (optional, input will be read from stdin in a \
+        single pass if omitted or '-'). The input consists of interactions between user and \
+        items. The input file must contain a user and item pair per line, separated by a tab. \
+        Input compressed with gzip or zstd is decompressed on the fly.", "PATH");
+    opts.optopt("", "delimiter", "Character which separates the columns of the input (optional, \
+        defaults to a tab).", "CHAR");
+    opts.optflag("", "headers", "The first line of the input holds the names of the columns \
+        (optional).");
+    opts.optopt("", "quote", "Quote character for columns which contain the delimiter (optional, \
+        defaults to \").", "CHAR");
+    opts.optflag("", "no-quoting", "Do not treat quote characters specially (optional).");
+    opts.optopt("", "user-column", "Column of the input which holds the user (optional, defaults \
+        to 1). Columns are either numbered from 1 or referred to by their name in the header \
+        line.", "COLUMN");
+    opts.optopt("", "item-column", "Column of the input which holds the item (optional, defaults \
+        to 2).", "COLUMN");
+    opts.optopt("", "weight-column", "Column of the input which holds the weight of an \
+        interaction for the weighting (optional, defaults to 3).", "COLUMN");
+    opts.optopt("", "timestamp-column", "Column of the input which holds the numeric timestamp of \
+        an interaction (optional, only for the evaluate mode). If supplied, the last interactions \
+        of a user are determined by their timestamps instead of their position in the input.",
+        "COLUMN");
+    opts.optopt("", "malformed-lines", "How to handle lines of the input which do not hold a valid \
+        interaction (optional, defaults to lenient). One of strict to abort at the first \
+        malformed line, or lenient to skip malformed lines and report how many were skipped.",
+        "POLICY");
+    opts.optopt("", "rejects-file", "File name for the malformed lines which were skipped in \
+        lenient mode (optional).", "PATH");
+    opts.optopt("", "max-rejected", "Maximum fraction of malformed lines per inputfile in lenient \
+        mode (optional, defaults to 0.5). We abort with a non-zero exit code if more lines are \
+        malformed, e.g., because of a wrong delimiter.", "FRACTION");
     opts.optopt("o", "outputfile", "Output file name (optional, output will be written to stdout \
         by default).", "PATH");
     opts.optopt("n", "num-indicators", "Number of indicators to compute per item (optional, \
         defaults to 10).", "NUMBER");
+    opts.optopt("s", "seed", "Seed for the random downsampling of the interactions (optional, \
+        runs with the same seed and input produce the same output).", "NUMBER");
+    opts.optopt("", "max-interactions-per-user", "Maximum number of interactions to account for \
+        per user (optional, defaults to 500). We keep a uniform sample of the interactions of \
+        users with more interactions.", "NUMBER");
+    opts.optopt("", "max-interactions-per-item", "Maximum number of interactions to account for \
+        per item (optional, defaults to 500). Further interactions with an item are ignored.",
+        "NUMBER");
+    opts.optopt("m", "measure", "Association measure to rank the indicators by (optional, defaults \
+        to llr). One of llr, signed-llr, jaccard, cosine, pmi, npmi, lift or \
+        conditional-probability.", "NAME");
+    opts.optflag("p", "positive-only", "Only select items which cooccur more often than expected \
+        as indicators (optional).");
+    opts.optopt("", "min-score", "Minimum association score of an indicator (optional). Items can \
+        have fewer indicators than requested if the evidence is weak.", "NUMBER");
+    opts.optopt("", "max-p-value", "Maximum p-value of an indicator under the chi-squared \
+        distribution with one degree of freedom (optional, only for the llr and signed-llr \
+        measures), e.g., 0.001.", "NUMBER");
+    opts.optopt("", "min-cooccurrences", "Minimum number of cooccurrences of an indicator with an \
+        item (optional, defaults to 1).", "NUMBER");
+    opts.optopt("", "negative-outputfile", "Output file name for negative indicators (optional). \
+        If supplied, we additionally write the items which cooccur much less often than expected \
+        with each item to this file, e.g., for 'do not recommend together' rules.", "PATH");
+    opts.optopt("c", "checkpoint", "Snapshot file name (optional). The training state will be \
+        written to this file, so that a later run can resume from it.", "PATH");
+    opts.optopt("r", "resume", "Snapshot file name (optional). Restores the training state from \
+        this file and continues training with the interactions from the inputfile. The number of \
+        indicators, the maximum numbers of interactions per user and item, the seed and the \
+        weighting are taken from the snapshot.", "PATH");
+    opts.optopt("x", "secondary-inputfile", "Input file name for a second type of interactions \
+        (optional), e.g., views if the inputfile holds purchases. If supplied, we compute \
+        cross-indicators, which hold the items from the inputfile that are highly associated \
+        with each item from this file. Both files must share the same users.", "PATH");
+    opts.optopt("", "action-column", "Column of the input which holds the type of an interaction \
+        (optional), e.g., view or purchase. If supplied, we compute cross-indicators from the \
+        interactions of the 'secondary-action' to the interactions of the 'primary-action' in \
+        the inputfile, and ignore the interactions of other types.", "COLUMN");
+    opts.optopt("", "primary-action", "Type of the interactions in the action column to compute \
+        cross-indicators for, e.g., purchase (required with 'action-column').", "NAME");
+    opts.optopt("", "secondary-action", "Type of the interactions in the action column which \
+        indicate the primary ones, e.g., view (required with 'action-column').", "NAME");
+    opts.optopt("w", "weighting", "How to account for the weights of the interactions in the \
+        third column of the input (optional, weights are ignored by default). One of \
+        threshold:<weight> to only keep interactions with at least this weight, log-priority to \
+        prefer interactions with larger weights when replacing interactions in the histories of \
+        users with more than the maximum number of interactions, or top-k:<number> to only keep \
+        the interactions with the highest weights per user. The weights do not scale the \
+        cooccurrence counts.", "NAME");
+    opts.optopt("d", "dedup", "How to deduplicate repeated interactions of a user with the same \
+        item (optional, defaults to first). One of first to skip repetitions of items which are \
+        already in the downsampled history of a user, once to count the pair once with the \
+        highest weight of its interactions, or weight to count it once with the sum of the \
+        weights of its interactions, e.g., play counts for the \
+        log-priority weighting. The combined weights decide which interactions are sampled, so \
+        once and weight require the log-priority or top-k weighting. Only first is supported in \
+        a single pass.", "NAME");
+    opts.optopt("t", "threads", "Number of threads to use (optional, defaults to the number of \
+        cores). If supplied, the items are split into as many ranges, whose cooccurrences are \
+        counted in parallel after downsampling, which gives the same results as a sequential \
+        run.",
+        "NUMBER");
+    opts.optopt("", "memory-budget", "Memory budget for the partial cooccurrence counts in \
+        megabytes (optional). If supplied, we first downsample all interactions and then count \
+        their cooccurrences, partial counts which exceed the budget are spilled to disk and \
+        merged into a compact matrix afterwards. The downsampled interactions and the compact \
+        matrix are not covered by the budget.", "MB");
+    opts.optopt("", "partitions", "Number of item partitions (optional). If supplied, we first \
+        downsample all interactions and then count and rank the cooccurrences of one range of \
+        items at a time, so that only a fraction of the cooccurrence matrix is held in memory.",
+        "NUMBER");
+    opts.optopt("", "num-recommendations", "Number of items to recommend per user in the recommend \
+        mode, or number k of recommendations to compute the metrics@k for in the evaluate mode \
+        (optional, defaults to 10).", "NUMBER");
+    opts.optflag("", "include-seen", "Also recommend items which a user already interacted with \
+        in the recommend mode (optional).");
+    opts.optopt("", "address", "Address to answer HTTP requests at in the serve mode (optional, \
+        defaults to 127.0.0.1:8080).", "HOST:PORT");
+    opts.optopt("", "split", "How to hold out test interactions per user in the evaluate mode \
+        (optional, defaults to random:0.2). One of random:<fraction> to hold out a random \
+        fraction of the interactions of every user, or last:<number> to hold out the last \
+        interactions of every user from the inputfile.", "NAME");
+    opts.optflag("1", "single-pass", "Read the inputfile only once (optional). Users and items are \
+        indexed on first sight instead of in a separate pass over the input.");
     opts.optflag("h", "help", "Print this help menu");
 
     let matches = match opts.parse(&args[1..]) {
@@ -54,28 +184,507 @@ fn main() {
         return print_usage_and_exit(&program, &opts, None);
     }
 
-    if !matches.opt_present("i") {
-        return print_usage_and_exit(
-            &program,
-            &opts,
-            Some("Please specify an inputfile via --inputfile."),
-        );
-    }
-
-    let interactions_path = matches.opt_str("i").unwrap();
+    let interactions_path = matches.opt_str("i").unwrap_or_else(|| String::from(STDIN));
+    // Stdin can only be consumed once
+    let single_pass = matches.opt_present("1") || interactions_path == STDIN;
     let indicators_path = matches.opt_str("o");
+    let checkpoint_path = matches.opt_str("c");
+    let resume_path = matches.opt_str("r");
 
     let k: usize = match matches.opt_get_default("n", 10) {
         Ok(k) => k,
         Err(failure) => {
-            let hint = format!("Problem with option 'n': {}", failure.to_string());
+            let hint = format!("Problem with option 'n': {}", failure);
+            return print_usage_and_exit(&program, &opts, Some(&hint));
+        }
+    };
+
+    let seed: u64 = match matches.opt_get_default("s", recoreco::DEFAULT_SEED) {
+        Ok(seed) => seed,
+        Err(failure) => {
+            let hint = format!("Problem with option 's': {}", failure);
+            return print_usage_and_exit(&program, &opts, Some(&hint));
+        }
+    };
+
+    let measure: Option<Measure> = match matches.opt_get("m") {
+        Ok(measure) => measure,
+        Err(failure) => {
+            let hint = format!("Problem with option 'm': {}", failure);
+            return print_usage_and_exit(&program, &opts, Some(&hint));
+        }
+    };
+
+    let polarity = if matches.opt_present("p") { Polarity::Positive } else { Polarity::Any };
+
+    let mut min_score: f64 = match matches.opt_get_default("min-score", f64::NEG_INFINITY) {
+        Ok(min_score) => min_score,
+        Err(failure) => {
+            let hint = format!("Problem with option 'min-score': {}", failure);
+            return print_usage_and_exit(&program, &opts, Some(&hint));
+        }
+    };
+
+    match matches.opt_get::<f64>("max-p-value") {
+        Ok(None) => {},
+        Ok(Some(max_p_value)) if max_p_value > 0.0 && max_p_value <= 1.0 => {
+            let min_llr = measures::llr_for_p_value(max_p_value);
+            let min_score_for_p_value = match measure {
+                None | Some(Measure::LogLikelihoodRatio) => min_llr,
+                Some(Measure::SignedRootLogLikelihoodRatio) => min_llr.sqrt(),
+                Some(_) => {
+                    let hint = "Option 'max-p-value' requires the llr or signed-llr measure";
+                    return print_usage_and_exit(&program, &opts, Some(hint));
+                }
+            };
+            min_score = min_score.max(min_score_for_p_value);
+        },
+        Ok(Some(_)) => {
+            let hint = "Option 'max-p-value' must be in (0, 1]";
+            return print_usage_and_exit(&program, &opts, Some(hint));
+        },
+        Err(failure) => {
+            let hint = format!("Problem with option 'max-p-value': {}", failure);
+            return print_usage_and_exit(&program, &opts, Some(&hint));
+        }
+    }
+
+    let min_cooccurrences: u32 = match matches.opt_get_default("min-cooccurrences", 1) {
+        Ok(min_cooccurrences) => min_cooccurrences,
+        Err(failure) => {
+            let hint = format!("Problem with option 'min-cooccurrences': {}", failure);
+            return print_usage_and_exit(&program, &opts, Some(&hint));
+        }
+    };
+    let negative_indicators_path = matches.opt_str("negative-outputfile");
+
+    let num_threads: Option<usize> = match matches.opt_get("t") {
+        Ok(Some(0)) => {
+            let hint = "Option 't' requires at least one thread";
+            return print_usage_and_exit(&program, &opts, Some(hint));
+        },
+        Ok(num_threads) => num_threads,
+        Err(failure) => {
+            let hint = format!("Problem with option 't': {}", failure);
+            return print_usage_and_exit(&program, &opts, Some(&hint));
+        }
+    };
+
+    let max_interactions_per_user: u32 = match matches.opt_get_default(
+        "max-interactions-per-user",
+        recoreco::config::DEFAULT_MAX_INTERACTIONS_PER_USER,
+    ) {
+        Ok(max_interactions_per_user) => max_interactions_per_user,
+        Err(failure) => {
+            let hint = format!("Problem with option 'max-interactions-per-user': {}", failure);
+            return print_usage_and_exit(&program, &opts, Some(&hint));
+        }
+    };
+
+    let max_interactions_per_item: u32 = match matches.opt_get_default(
+        "max-interactions-per-item",
+        recoreco::config::DEFAULT_MAX_INTERACTIONS_PER_ITEM,
+    ) {
+        Ok(max_interactions_per_item) => max_interactions_per_item,
+        Err(failure) => {
+            let hint = format!("Problem with option 'max-interactions-per-item': {}", failure);
             return print_usage_and_exit(&program, &opts, Some(&hint));
         }
     };
 
-    compute_indicators(&interactions_path, k, indicators_path).unwrap();
+    let mut config = IndicatorConfig::builder();
+    config
+        .num_indicators_per_item(k)
+        .max_interactions_per_user(max_interactions_per_user)
+        .max_interactions_per_item(max_interactions_per_item)
+        .seed(seed)
+        .polarity(polarity)
+        .min_score(min_score)
+        .min_cooccurrences(min_cooccurrences);
+    if let Some(measure) = measure {
+        config.measure(measure);
+    }
+    if let Some(num_threads) = num_threads {
+        config.num_threads(num_threads);
+    }
+
+    let config = match config.build() {
+        Ok(config) => config,
+        Err(failure) => {
+            let hint = failure.to_string();
+            return print_usage_and_exit(&program, &opts, Some(&hint));
+        }
+    };
+
+    let memory_budget: Option<usize> = match matches.opt_get::<usize>("memory-budget") {
+        Ok(Some(0)) => {
+            let hint = "Option 'memory-budget' requires at least one megabyte";
+            return print_usage_and_exit(&program, &opts, Some(hint));
+        },
+        Ok(Some(_)) if num_threads.is_some() => {
+            let hint = "Options 'memory-budget' and 't' cannot be combined";
+            return print_usage_and_exit(&program, &opts, Some(hint));
+        },
+        Ok(None) => None,
+        Ok(Some(megabytes)) => match megabytes.checked_mul(1024 * 1024) {
+            Some(memory_budget) => Some(memory_budget),
+            None => {
+                let hint = format!("Option 'memory-budget' must not exceed {} megabytes",
+                    usize::MAX / (1024 * 1024));
+                return print_usage_and_exit(&program, &opts, Some(&hint));
+            }
+        },
+        Err(failure) => {
+            let hint = format!("Problem with option 'memory-budget': {}", failure);
+            return print_usage_and_exit(&program, &opts, Some(&hint));
+        }
+    };
+
+    let item_partitions: Option<usize> = match matches.opt_get::<usize>("partitions") {
+        Ok(Some(0)) => {
+            let hint = "Option 'partitions' requires at least one partition";
+            return print_usage_and_exit(&program, &opts, Some(hint));
+        },
+        Ok(Some(_)) if num_threads.is_some() || memory_budget.is_some() => {
+            let hint = "Option 'partitions' cannot be combined with 't' or 'memory-budget'";
+            return print_usage_and_exit(&program, &opts, Some(hint));
+        },
+        Ok(item_partitions) => item_partitions,
+        Err(failure) => {
+            let hint = format!("Problem with option 'partitions': {}", failure);
+            return print_usage_and_exit(&program, &opts, Some(&hint));
+        }
+    };
+
+    let weighting: Weighting = match matches.opt_get_default("w", Weighting::Unweighted) {
+        Ok(Weighting::Unweighted) => Weighting::Unweighted,
+        Ok(_) if num_threads.is_some() || memory_budget.is_some() || item_partitions.is_some() => {
+            let hint = "Option 'w' cannot be combined with 't', 'memory-budget' or 'partitions'";
+            return print_usage_and_exit(&program, &opts, Some(hint));
+        },
+        Ok(weighting) => weighting,
+        Err(failure) => {
+            let hint = format!("Problem with option 'w': {}", failure);
+            return print_usage_and_exit(&program, &opts, Some(&hint));
+        }
+    };
+
+    let deduplication = match matches.opt_get_default("d", Deduplication::KeepFirst) {
+        Ok(deduplication) if deduplication.requires_two_passes() && single_pass => {
+            let hint = format!(
+                "Option 'd' with {} requires two passes over an inputfile",
+                deduplication,
+            );
+            return print_usage_and_exit(&program, &opts, Some(&hint));
+        },
+        Ok(deduplication) if deduplication.requires_two_passes() && !weighting.is_prioritised() => {
+            let hint = format!(
+                "Option 'd' with {} requires the log-priority or top-k weighting, which sample by \
+                    the combined weights",
+                deduplication,
+            );
+            return print_usage_and_exit(&program, &opts, Some(&hint));
+        },
+        Ok(deduplication) => deduplication,
+        Err(failure) => {
+            let hint = format!("Problem with option 'd': {}", failure);
+            return print_usage_and_exit(&program, &opts, Some(&hint));
+        }
+    };
+
+    let format = match input_format(&matches) {
+        Ok(format) => format,
+        Err(hint) => return print_usage_and_exit(&program, &opts, Some(&hint)),
+    };
+
+    let max_rejected_fraction = match matches.opt_get_default("max-rejected", 0.5) {
+        Ok(fraction) if (0.0..=1.0).contains(&fraction) => fraction,
+        Ok(_) => {
+            let hint = "Option 'max-rejected' must be in [0, 1]";
+            return print_usage_and_exit(&program, &opts, Some(hint));
+        },
+        Err(failure) => {
+            let hint = format!("Problem with option 'max-rejected': {}", failure);
+            return print_usage_and_exit(&program, &opts, Some(&hint));
+        }
+    };
+
+    let rejects_path = matches.opt_str("rejects-file");
+    if let Some(ref path) = rejects_path {
+        // Every inputfile appends its rejected lines to this file
+        if let Err(failure) = File::create(path) {
+            let message = format!("Cannot create the rejects file {}: {}", path, failure);
+            return exit_on_failure(Err(io::Error::new(failure.kind(), message).into()));
+        }
+    }
+
+    let input = Input { format, rejects_path, max_rejected_fraction };
+
+    let mode = matches.free.first().map(String::as_str);
+
+    let cross_input = match (
+        matches.opt_str("x"),
+        matches.opt_present("action-column"),
+        matches.opt_str("primary-action"),
+        matches.opt_str("secondary-action"),
+    ) {
+        (None, false, None, None) => None,
+        (Some(secondary_path), false, None, None) => Some(CrossInput::Files {
+            primary_path: interactions_path.clone(),
+            secondary_path,
+        }),
+        (None, true, Some(primary_action), Some(secondary_action)) => Some(CrossInput::Actions {
+            path: interactions_path.clone(),
+            primary_action,
+            secondary_action,
+        }),
+        _ => {
+            let hint = "Cross-indicators require either the 'x' option, or the 'action-column', \
+                'primary-action' and 'secondary-action' options";
+            return print_usage_and_exit(&program, &opts, Some(hint));
+        },
+    };
+
+    if mode != Some(RECOMMEND) && mode != Some(EVALUATE)
+        && matches.opt_present("num-recommendations") {
+        let hint = "Option 'num-recommendations' requires the recommend or evaluate mode";
+        return print_usage_and_exit(&program, &opts, Some(hint));
+    }
+
+    if mode != Some(RECOMMEND) && matches.opt_present("include-seen") {
+        let hint = "Option 'include-seen' requires the recommend mode";
+        return print_usage_and_exit(&program, &opts, Some(hint));
+    }
+
+    if mode != Some(EVALUATE) && (matches.opt_present("split")
+        || matches.opt_present("timestamp-column")) {
+        let hint = "Options 'split' and 'timestamp-column' require the evaluate mode";
+        return print_usage_and_exit(&program, &opts, Some(hint));
+    }
+
+    if mode != Some(SERVE) && matches.opt_present("address") {
+        let hint = "Option 'address' requires the serve mode";
+        return print_usage_and_exit(&program, &opts, Some(hint));
+    }
+
+    let recommending = match mode {
+        None | Some(SERVE) | Some(EVALUATE) => None,
+        Some(RECOMMEND) => {
+            if interactions_path == STDIN || cross_input.is_some() {
+                let hint = "The recommend mode requires an inputfile and cannot be combined with \
+                    cross-indicators";
+                return print_usage_and_exit(&program, &opts, Some(hint));
+            }
+
+            let num_recommendations = match matches.opt_get_default("num-recommendations", 10) {
+                Ok(num_recommendations) => num_recommendations,
+                Err(failure) => {
+                    let hint = format!("Problem with option 'num-recommendations': {}", failure);
+                    return print_usage_and_exit(&program, &opts, Some(&hint));
+                }
+            };
+            let exclude_seen = !matches.opt_present("include-seen");
+
+            Some(Recommending { num_recommendations, exclude_seen })
+        },
+        Some(mode) => {
+            let hint = format!(
+                "Unknown mode '{}', expected {}, {} or {}",
+                mode,
+                RECOMMEND,
+                SERVE,
+                EVALUATE,
+            );
+            return print_usage_and_exit(&program, &opts, Some(&hint));
+        },
+    };
+
+    let serving_address = if mode == Some(SERVE) {
+        if indicators_path.is_none() || cross_input.is_some() {
+            let hint = "The serve mode requires an outputfile to serve the indicators from, and \
+                cannot be combined with cross-indicators";
+            return print_usage_and_exit(&program, &opts, Some(hint));
+        }
+        Some(matches.opt_str("address").unwrap_or_else(|| String::from(DEFAULT_ADDRESS)))
+    } else {
+        None
+    };
+
+    if let Some(num_threads) = num_threads {
+        let thread_pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .build_global();
+
+        if let Err(failure) = thread_pool {
+            let message = format!("Cannot start {} threads: {}", num_threads, failure);
+            return exit_on_failure(Err(io::Error::other(message).into()));
+        }
+    }
+
+    if mode == Some(EVALUATE) {
+
+        if indicators_path.is_some() || cross_input.is_some() || checkpoint_path.is_some()
+            || resume_path.is_some() || negative_indicators_path.is_some()
+            || weighting != Weighting::Unweighted {
+            let hint = "The evaluate mode only reports metrics, and cannot be combined with \
+                outputfiles, cross-indicators, snapshots or weights";
+            return print_usage_and_exit(&program, &opts, Some(hint));
+        }
+
+        let split: Split = match matches.opt_get_default("split", Split::RandomFraction(0.2)) {
+            Ok(split) => split,
+            Err(failure) => {
+                let hint = format!("Problem with option 'split': {}", failure);
+                return print_usage_and_exit(&program, &opts, Some(&hint));
+            }
+        };
+
+        let num_recommendations = match matches.opt_get_default("num-recommendations", 10) {
+            Ok(num_recommendations) => num_recommendations,
+            Err(failure) => {
+                let hint = format!("Problem with option 'num-recommendations': {}", failure);
+                return print_usage_and_exit(&program, &opts, Some(&hint));
+            }
+        };
+
+        exit_on_failure(evaluate_indicators(
+            &interactions_path,
+            &input,
+            &config,
+            Counting { num_partitions: num_threads.unwrap_or(1), memory_budget, item_partitions },
+            split,
+            num_recommendations,
+        ));
+
+        return;
+    }
+
+    if let Some(cross_input) = cross_input {
+
+        if interactions_path == STDIN || single_pass || checkpoint_path.is_some()
+            || resume_path.is_some() || weighting != Weighting::Unweighted {
+            return print_usage_and_exit(
+                &program,
+                &opts,
+                Some("Cross-indicators require an inputfile and cannot be computed in a single \
+                    pass, from a snapshot or with weights."),
+            );
+        }
+
+        exit_on_failure(compute_cross_indicators(
+            &cross_input,
+            &input,
+            &config,
+            indicators_path,
+            negative_indicators_path,
+        ));
+
+        return;
+    }
+
+    // Without an inputfile, the serve mode only serves previously computed indicators
+    if serving_address.is_none() || matches.opt_present("i") {
+        exit_on_failure(compute_indicators(
+            &interactions_path,
+            &input,
+            single_pass,
+            &config,
+            Counting { num_partitions: num_threads.unwrap_or(1), memory_budget, item_partitions },
+            weighting,
+            deduplication,
+            recommending,
+            indicators_path.clone(),
+            negative_indicators_path,
+            checkpoint_path,
+            resume_path,
+        ));
+    }
+
+    if let (Some(address), Some(path)) = (serving_address, indicators_path) {
+        exit_on_failure(serve_indicators(&address, &path));
+    }
+}
+
+/// Reads the options which describe the format of the inputfiles, returns a hint for invalid ones
+fn input_format(matches: &getopts::Matches) -> Result<InteractionReaderBuilder, String> {
+
+    let malformed_lines = matches
+        .opt_get_default("malformed-lines", MalformedLines::Lenient)
+        .map_err(|failure| format!("Problem with option 'malformed-lines': {}", failure))?;
+
+    let single_byte = |name: &str| -> Result<Option<u8>, String> {
+        match matches.opt_str(name).as_deref() {
+            None => Ok(None),
+            Some("\\t") | Some("tab") => Ok(Some(b'\t')),
+            Some(value) if value.len() == 1 => Ok(Some(value.as_bytes()[0])),
+            Some(value) => Err(format!(
+                "Option '{}' expects a single character, 'tab' or '\\t', but got '{}'",
+                name,
+                value,
+            )),
+        }
+    };
+
+    let column = |name: &str| -> Result<Option<Column>, String> {
+        match matches.opt_str(name) {
+            None => Ok(None),
+            Some(value) => match value.parse::<usize>() {
+                Ok(0) => Err(format!("Option '{}' numbers columns from 1", name)),
+                Ok(number) => Ok(Some(Column::Index(number - 1))),
+                Err(_) if matches.opt_present("headers") => Ok(Some(Column::Name(value))),
+                Err(_) => Err(format!("Option '{}' with a column name requires 'headers'", name)),
+            },
+        }
+    };
+
+    let mut format = InteractionReaderBuilder::new();
+    format
+        .has_headers(matches.opt_present("headers"))
+        .quoting(!matches.opt_present("no-quoting"))
+        .malformed_lines(malformed_lines);
+
+    if let Some(delimiter) = single_byte("delimiter")? {
+        format.delimiter(delimiter);
+    }
+    if let Some(quote) = single_byte("quote")? {
+        format.quote(quote);
+    }
+    if let Some(user_column) = column("user-column")? {
+        format.user_column(user_column);
+    }
+    if let Some(item_column) = column("item-column")? {
+        format.item_column(item_column);
+    }
+    if let Some(weight_column) = column("weight-column")? {
+        format.weight_column(weight_column);
+    }
+    if let Some(timestamp_column) = column("timestamp-column")? {
+        format.timestamp_column(timestamp_column);
+    }
+    if let Some(action_column) = column("action-column")? {
+        format.action_column(action_column);
+    }
+
+    Ok(format)
+}
+
+/// Prints the reason of a failure and exits with an exit code which tells the kind of failure
+fn exit_on_failure(result: Result<(), Error>) {
+    if let Err(failure) = result {
+        eprintln!("\n{}\n", failure);
+
+        let exit_code = match failure {
+            Error::Config(_) => EXIT_USAGE,
+            Error::Io(_) => EXIT_IO,
+            Error::Parse(_) => EXIT_PARSE,
+            Error::UnknownUser(_) | Error::UnknownItem(_) => EXIT_UNKNOWN_ID,
+            Error::Overflow(_) => EXIT_OVERFLOW,
+        };
+        process::exit(exit_code);
+    }
 }
 
+/// Prints the usage, and exits with `EXIT_USAGE` if there is a `hint` about an invalid option
 fn print_usage_and_exit(
     program: &str,
     opts: &Options,
@@ -86,51 +695,746 @@ fn print_usage_and_exit(
         eprintln!("\n{}\n", hint);
     }
 
-    let brief = format!("Usage: {} [options]", program);
+    let brief = format!("Usage: {} [{}|{}|{}] [options]", program, RECOMMEND, SERVE, EVALUATE);
     eprint!("{}", opts.usage(&brief));
+
+    process::exit(if hint.is_some() { EXIT_USAGE } else { 0 });
+}
+
+/// Exit code for invalid options and parameters
+const EXIT_USAGE: i32 = 2;
+
+/// Exit code for failures to read or write files
+const EXIT_IO: i32 = 3;
+
+/// Exit code for malformed input, e.g., in strict mode or with too many malformed lines
+const EXIT_PARSE: i32 = 4;
+
+/// Exit code for users or items which are unknown to the data dictionary
+const EXIT_UNKNOWN_ID: i32 = 5;
+
+/// Exit code for numbers which exceed the range of their type, e.g., too many items
+const EXIT_OVERFLOW: i32 = 6;
+
+/// Input path which denotes that we read from stdin
+const STDIN: &str = "-";
+
+/// Mode which writes recommendations for the users instead of the indicators
+const RECOMMEND: &str = "recommend";
+
+/// Mode which serves the indicators from the outputfile over HTTP
+const SERVE: &str = "serve";
+
+/// Mode which reports the quality of the recommendations on held out interactions
+const EVALUATE: &str = "evaluate";
+
+/// Address at which we answer HTTP requests in the serve mode, unless requested otherwise
+const DEFAULT_ADDRESS: &str = "127.0.0.1:8080";
+
+/// How to read the inputfiles, and how many of their lines may be malformed
+struct Input {
+    format: InteractionReaderBuilder,
+    rejects_path: Option<String>,
+    max_rejected_fraction: f64,
+}
+
+impl Input {
+
+    /// Opens the inputfile at `path` or stdin, the first pass over an inputfile appends its
+    /// malformed lines to the rejects file
+    fn open(
+        &self,
+        path: &str,
+        first_pass: bool,
+    ) -> Result<InteractionReader<Box<dyn Read>>, Error> {
+
+        let mut reader = if path == STDIN {
+            self.format.from_reader(decompressed(io::stdin())?)?
+        } else {
+            self.format.from_path(path).map_err(|failure| failed_to_read(path, failure))?
+        };
+
+        if let (true, Some(rejects_path)) = (first_pass, &self.rejects_path) {
+            reader.write_rejects_to(OpenOptions::new().append(true).open(rejects_path)?);
+        }
+
+        Ok(reader)
+    }
+
+    /// Fails if reading stopped at a malformed line in strict mode, or if too many lines were
+    /// malformed. Reports the malformed lines after the first pass over an inputfile.
+    fn check<R: Read>(
+        &self,
+        reader: &mut InteractionReader<R>,
+        path: &str,
+        first_pass: bool,
+    ) -> Result<(), Error> {
+
+        let report = reader.finish().map_err(|failure| failed_to_read(path, failure))?;
+
+        if !first_pass || report.num_rejected == 0 {
+            return Ok(());
+        }
+
+        eprintln!(
+            "Skipped {} of {} lines of {} as malformed, e.g.,",
+            report.num_rejected,
+            report.num_lines,
+            path,
+        );
+        for sample in &report.samples {
+            eprintln!("\t{}", sample);
+        }
+
+        if report.rejected_fraction() > self.max_rejected_fraction {
+            let failure = format!(
+                "Aborting, {:.1}% of the lines of {} are malformed, at most {:.1}% are allowed",
+                100.0 * report.rejected_fraction(),
+                path,
+                100.0 * self.max_rejected_fraction,
+            );
+            return Err(Error::Parse(failure));
+        }
+
+        Ok(())
+    }
+}
+
+/// Names the inputfile at `path` in the message of a `failure` to read it, keeps its kind
+fn failed_to_read(path: &str, failure: Error) -> Error {
+    match failure {
+        Error::Io(error) => {
+            let message = format!("Failed to read {}: {}", path, error);
+            Error::Io(io::Error::new(error.kind(), message))
+        },
+        Error::Parse(message) => Error::Parse(format!("Failed to read {}: {}", path, message)),
+        failure => failure,
+    }
+}
+
+/// How to recommend items to the users in the recommend mode
+struct Recommending {
+    num_recommendations: usize,
+    exclude_seen: bool,
+}
+
+/// How to count the cooccurrences: partitioned across threads, within a memory budget, or one
+/// range of items at a time
+struct Counting {
+    num_partitions: usize,
+    memory_budget: Option<usize>,
+    item_partitions: Option<usize>,
 }
 
+#[allow(clippy::too_many_arguments)]
 fn compute_indicators(
     interactions_path: &str,
-    n: usize,
+    input: &Input,
+    single_pass: bool,
+    config: &IndicatorConfig,
+    counting: Counting,
+    weighting: Weighting,
+    deduplication: Deduplication,
+    recommending: Option<Recommending>,
     indicators_path: Option<String>,
-) -> Result<(), Box<Error>> {
+    negative_indicators_path: Option<String>,
+    checkpoint_path: Option<String>,
+    resume_path: Option<String>,
+) -> Result<(), Error> {
+
+    let (restored_model, mut data_dict) = match resume_path {
+        Some(path) => {
+            eprintln!("Restoring training state from snapshot {}", path);
+            let (model, data_dict) = snapshot::read_snapshot(&path)?;
+            (Some(model), data_dict)
+        },
+        None => (None, DataDictionary::default()),
+    };
+
+    // A restored model keeps the weighting it was trained with
+    let weighting = restored_model.as_ref().map_or(weighting, |model| model.weighting());
 
-    // We use constants here for the moment, these should result in a good runtime/quality ratio.
-    const F_MAX: u32 = 500;
-    const K_MAX: u32 = 500;
+    // Repeated interactions are only combined within the input of this run. The downsampler skips
+    // repetitions of items in the histories of the users, so we only keep track of all user-item
+    // pairs if their weights need to be combined beforehand.
+    let mut deduplicator = if deduplication.requires_two_passes() {
+        Some(Deduplicator::new(deduplication))
+    } else {
+        None
+    };
 
-    println!("Reading {} to compute data statistics (pass 1/2)", interactions_path);
+    if !single_pass {
+        eprintln!("Reading {} to compute data statistics (pass 1/2)", interactions_path);
+
+        let mut reader_pass_one = input.open(interactions_path, true)?;
+        if weighting == Weighting::Unweighted {
+            let interactions = reader_pass_one.interactions()
+                .map(|(user, item)| (user, item, 1.0));
+            register_all(&mut data_dict, interactions, weighting, &mut deduplicator)?;
+        } else {
+            let interactions = reader_pass_one.weighted_interactions();
+            register_all(&mut data_dict, interactions, weighting, &mut deduplicator)?;
+        }
+        input.check(&mut reader_pass_one, interactions_path, true)?;
+
+        print_statistics(&data_dict, weighting, deduplication);
+
+        eprintln!(
+            "Reading {} to compute {} item indicators per item (pass 2/2)",
+            interactions_path,
+            config.num_indicators_per_item(),
+        );
+    } else {
+        eprintln!(
+            "Reading {} to compute {} item indicators per item (single pass)",
+            interactions_path,
+            config.num_indicators_per_item(),
+        );
+    }
+
+    let mut model = match restored_model {
+        Some(model) => {
+            eprintln!("Continuing training from the restored snapshot (seed {})", model.seed());
+            model
+        },
+        None => {
+            eprintln!("Downsampling interactions with seed {}", config.seed());
+            let mut model =
+                IndicatorModel::with_config(data_dict.num_users(), data_dict.num_items(), config);
+            model.set_weighting(weighting)?;
+            model
+        },
+    };
+
+    if weighting != Weighting::Unweighted {
+        eprintln!("Weighting interactions by {}", weighting);
+    }
+
+    if config.measure() != Measure::LogLikelihoodRatio {
+        eprintln!("Ranking indicators by {}", config.measure());
+    }
+
+    // The measure and the thresholds are not part of a snapshot
+    model.configure(config);
+    model.set_negative_indicators(negative_indicators_path.is_some());
+
+    if counting.num_partitions > 1 {
+        eprintln!("Counting cooccurrences with {} threads", counting.num_partitions);
+    }
+
+    let start = Instant::now();
+
+    let mut reader = input.open(interactions_path, single_pass)?;
+    update_model(
+        &mut model,
+        &mut reader,
+        &mut data_dict,
+        &mut deduplicator,
+        single_pass,
+        &counting,
+    )?;
+    input.check(&mut reader, interactions_path, single_pass)?;
+
+    let num_items_rescored = model.refresh();
+
+    if single_pass {
+        print_statistics(&data_dict, weighting, deduplication);
+    }
+    print_repetitions(&model);
 
-    let mut reader_pass_one = io::csv_reader(&interactions_path)?;
-    let interactions = io::interactions_from_csv(&mut reader_pass_one);
-    let data_dict = DataDictionary::from_owned(interactions);
+    eprintln!(
+        "{} cooccurrences observed, {}ms training time, {} items rescored",
+        model.num_cooccurrences_observed(),
+        start.elapsed().as_millis(),
+        num_items_rescored,
+    );
+
+    if let Some(path) = checkpoint_path {
+        eprintln!("Writing training state to snapshot {}", path);
+        snapshot::write_snapshot(&path, &model, &data_dict)?;
+    }
+
+    let histories = match recommending {
+        Some(_) => {
+            eprintln!("Reading {} to collect the histories of the users", interactions_path);
+            Some(read_histories(interactions_path, input, &data_dict, weighting)?)
+        },
+        None => None,
+    };
+
+    // Build reverse index, make sure we consume the data dictionary
+    let renaming: Renaming = data_dict.into();
+
+    if let (Some(negative_indicators), Some(path)) =
+        (model.negative_indicators(), negative_indicators_path) {
+        eprintln!("Writing negative indicators...");
+        recoreco::io::write_indicators(negative_indicators, &renaming, Some(path))?;
+    }
+
+    let seed = model.seed();
+    let indicators = model.into_indicators();
+
+    if let (Some(recommending), Some(histories)) = (recommending, histories) {
+        let mut recommender = Recommender::new(&indicators);
+        recommender.set_exclude_seen(recommending.exclude_seen);
+
+        let num_users = histories.iter().filter(|history| !history.is_empty()).count();
+        let recommendations = histories
+            .iter()
+            .enumerate()
+            .filter(|(_, history)| !history.is_empty())
+            .map(|(user, history)| {
+                (user as u32, recommender.recommend(history, recommending.num_recommendations))
+            });
+
+        eprintln!("Writing recommendations...");
+        recoreco::io::write_recommendations(recommendations, &renaming, indicators_path)?;
+        eprintln!("Wrote recommendations for {} users, downsampled with seed {}", num_users, seed);
+
+        return Ok(());
+    }
+
+    eprintln!("Writing indicators...");
+    recoreco::io::write_indicators(&indicators, &renaming, indicators_path)?;
+    eprintln!(
+        "Wrote the indicators of {} items, downsampled with seed {}",
+        indicators.num_items(),
+        seed,
+    );
+
+    Ok(())
+}
+
+/// Collects the items of all interactions from the inputfile per user, including the interactions
+/// which were not sampled
+fn read_histories(
+    interactions_path: &str,
+    input: &Input,
+    data_dict: &DataDictionary,
+    weighting: Weighting,
+) -> Result<Vec<Vec<u32>>, Error> {
+
+    let mut histories = vec![Vec::new(); data_dict.num_users()];
+
+    let mut add_to_history = |user: String, item: String| -> Result<(), Error> {
+        let (user, item) = (data_dict.user_index(&user)?, data_dict.item_index(&item)?);
+        histories[user as usize].push(item);
+        Ok(())
+    };
+
+    let mut reader = input.open(interactions_path, false)?;
+    if weighting == Weighting::Unweighted {
+        for (user, item) in reader.interactions() {
+            add_to_history(user, item)?;
+        }
+    } else {
+        for (user, item, weight) in reader.weighted_interactions() {
+            if weighting.admits(weight) {
+                add_to_history(user, item)?;
+            }
+        }
+    }
+    input.check(&mut reader, interactions_path, false)?;
+
+    Ok(histories)
+}
+
+/// Holds out interactions of every user according to the `split`, computes indicators from the
+/// remaining interactions and reports how well the top-`k` recommendations from these
+/// indicators and from the most popular items predict the held out interactions
+fn evaluate_indicators(
+    interactions_path: &str,
+    input: &Input,
+    config: &IndicatorConfig,
+    counting: Counting,
+    split: Split,
+    k: usize,
+) -> Result<(), Error> {
+
+    eprintln!("Reading {} to split the interactions of every user by {}", interactions_path, split);
+
+    // We need all interactions in memory to split them per user
+    let mut reader = input.open(interactions_path, true)?;
+    let mut timestamped_interactions: Vec<(String, String, f64)> =
+        reader.timestamped_interactions().collect();
+    input.check(&mut reader, interactions_path, true)?;
+
+    // Interactions without a timestamp column are already ordered by their position
+    timestamped_interactions.sort_by(|(_, _, first), (_, _, second)| {
+        first.partial_cmp(second).unwrap_or(Ordering::Equal)
+    });
+
+    let mut data_dict = DataDictionary::default();
+
+    let mut interactions: Vec<(u32, u32)> = Vec::new();
+    for (user, item, _) in timestamped_interactions {
+        interactions.push(data_dict.register(user, item)?);
+    }
+
+    print_statistics(&data_dict, Weighting::Unweighted, Deduplication::KeepFirst);
+
+    // Held out repetitions of training interactions would never be recommended
+    let num_repetitions = dedup::keep_first(&mut interactions);
+    if num_repetitions > 0 {
+        eprintln!(
+            "Removed {} repeated interactions of users with the same item, keeping the first \
+            of them.",
+            num_repetitions,
+        );
+    }
+
+    let holdout = evaluation::split_per_user(&interactions, split, config.seed());
+
+    eprintln!(
+        "Computing {} item indicators per item from {} training interactions, holding out {} \
+        test interactions",
+        config.num_indicators_per_item(),
+        holdout.train.len(),
+        holdout.test.len(),
+    );
+    eprintln!("Downsampling interactions with seed {}", config.seed());
+
+    if config.measure() != Measure::LogLikelihoodRatio {
+        eprintln!("Ranking indicators by {}", config.measure());
+    }
+
+    let mut model =
+        IndicatorModel::with_config(data_dict.num_users(), data_dict.num_items(), config);
+
+    count_cooccurrences(&mut model, holdout.train.iter().cloned(), &counting)?;
+    model.refresh();
+
+    let indicators = model.into_indicators();
+
+    eprintln!("Evaluating the top-{} recommendations...", k);
+    let report = evaluation::evaluate(&holdout, &indicators, k);
 
     println!(
+        "{:<12} {:>8} {:>10} {:>10} {:>10} {:>10} {:>10}",
+        "recommender",
+        "users",
+        format!("hit@{}", k),
+        format!("prec@{}", k),
+        format!("recall@{}", k),
+        format!("map@{}", k),
+        format!("ndcg@{}", k),
+    );
+    print_metrics("indicators", &report.indicators);
+    print_metrics("popularity", &report.popularity);
+
+    Ok(())
+}
+
+fn print_metrics(recommender: &str, metrics: &Metrics) {
+    println!(
+        "{:<12} {:>8} {:>10.4} {:>10.4} {:>10.4} {:>10.4} {:>10.4}",
+        recommender,
+        metrics.num_users,
+        metrics.hit_rate,
+        metrics.precision,
+        metrics.recall,
+        metrics.map,
+        metrics.ndcg,
+    );
+}
+
+/// Loads the indicators from the file at `indicators_path` and answers HTTP requests for them at
+/// `address`, the indicators are reloaded whenever the file is replaced
+fn serve_indicators(address: &str, indicators_path: &str) -> Result<(), Error> {
+
+    eprintln!("Loading indicators from {}", indicators_path);
+    let index = recoreco::io::read_indicators(indicators_path)?;
+
+    eprintln!("Serving indicators for {} items at http://{}", index.num_items(), address);
+    server::serve(address, index, Some(indicators_path))?;
+
+    Ok(())
+}
+
+fn print_statistics(
+    data_dict: &DataDictionary,
+    weighting: Weighting,
+    deduplication: Deduplication,
+) {
+    eprintln!(
         "Found {} interactions between {} users and {} items.",
         data_dict.num_interactions(),
         data_dict.num_users(),
         data_dict.num_items(),
     );
 
-    println!("Reading {} to compute {} item indicators per item (pass 2/2)", interactions_path, n);
+    if data_dict.num_duplicates() > 0 {
+        eprintln!(
+            "Found {} repeated interactions of users with the same item, deduplicated by {}.",
+            data_dict.num_duplicates(),
+            deduplication,
+        );
+    }
+
+    if weighting != Weighting::Unweighted {
+        eprintln!("The interactions have an overall weight of {}.", data_dict.total_weight());
+    }
+}
+
+/// Registers the interactions of the first pass which are admitted by the `weighting`, and hands
+/// them to the `deduplicator` if their weights need to be combined
+fn register_all<T>(
+    data_dict: &mut DataDictionary,
+    interactions: T,
+    weighting: Weighting,
+    deduplicator: &mut Option<Deduplicator>,
+) -> Result<(), Error>
+where
+    T: Iterator<Item = (String, String, f64)>
+{
+    match *deduplicator {
+        Some(ref mut deduplicator) => {
+            data_dict.extend_deduplicated(interactions, &weighting, deduplicator)
+        },
+        None => data_dict.extend_weighted(interactions, &weighting),
+    }
+}
+
+fn print_repetitions(model: &IndicatorModel) {
+    if model.num_repeated_interactions() > 0 {
+        eprintln!(
+            "Skipped {} repeated interactions of users with items in their downsampled histories.",
+            model.num_repeated_interactions(),
+        );
+    }
+}
+
+/// Feeds the interactions to the model. In a single pass, we register previously unseen users and
+/// items in the data dictionary on first sight. Otherwise, a deduplicator provides the combined
+/// weights of the repeated interactions from the first pass, and only the first interaction of
+/// every user-item pair is fed to the model.
+fn update_model<R: std::io::Read>(
+    model: &mut IndicatorModel,
+    reader: &mut InteractionReader<R>,
+    data_dict: &mut DataDictionary,
+    deduplicator: &mut Option<Deduplicator>,
+    single_pass: bool,
+    counting: &Counting,
+) -> Result<(), Error> {
+
+    let weighting = model.weighting();
+
+    if weighting != Weighting::Unweighted {
+        let interactions = reader.weighted_interactions()
+            .filter(|(_, _, weight)| weighting.admits(*weight));
+
+        for (user, item, weight) in interactions {
+            if single_pass {
+                let (user, item) = data_dict.register_weighted(user, item, weight)?;
+                model.update_weighted(user, item, weight);
+            } else {
+                let (user, item) = (data_dict.user_index(&user)?, data_dict.item_index(&item)?);
+                let weight = match *deduplicator {
+                    Some(ref mut deduplicator) => deduplicator.take(user, item),
+                    None => Some(weight),
+                };
+                if let Some(weight) = weight {
+                    model.update_weighted(user, item, weight);
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    let interactions = reader.interactions();
+    let mut failure = None;
+
+    if single_pass {
+        let indexed_interactions = interactions.map(|(user, item)| {
+            data_dict.register(user, item).map(Some)
+        });
+        count_cooccurrences(model, until_failure(indexed_interactions, &mut failure), counting)?;
+    } else {
+        let indexed_interactions = interactions.map(|(user, item)| {
+            let (user, item) = (data_dict.user_index(&user)?, data_dict.item_index(&item)?);
+            let is_first = match *deduplicator {
+                Some(ref mut deduplicator) => deduplicator.take(user, item).is_some(),
+                None => true,
+            };
+            Ok(if is_first { Some((user, item)) } else { None })
+        });
+        count_cooccurrences(model, until_failure(indexed_interactions, &mut failure), counting)?;
+    }
+
+    failure.map_or(Ok(()), Err)
+}
+
+/// Passes on the interactions which are kept, and stops at the first failure, which is stored in
+/// `failure`
+fn until_failure<'a, T>(
+    results: T,
+    failure: &'a mut Option<Error>,
+) -> impl Iterator<Item = (u32, u32)> + 'a
+where
+    T: Iterator<Item = Result<Option<(u32, u32)>, Error>> + 'a
+{
+    results
+        .map_while(move |result| result.map_err(|error| *failure = Some(error)).ok())
+        .flatten()
+}
+
+fn count_cooccurrences<T>(
+    model: &mut IndicatorModel,
+    interactions: T,
+    counting: &Counting,
+) -> Result<(), Error>
+where
+    T: Iterator<Item = (u32, u32)>
+{
+    match (counting.memory_budget, counting.item_partitions) {
+        (Some(memory_budget), _) => {
+            let num_spills = model.update_all_with_memory_budget(interactions, memory_budget)?;
+            if num_spills > 0 {
+                eprintln!("Spilled cooccurrence counts to disk {} times", num_spills);
+            }
+        },
+        (None, Some(item_partitions)) => {
+            eprintln!("Counting cooccurrences in {} item partitions", item_partitions);
+            model.update_all_out_of_core(interactions, item_partitions)?;
+        },
+        (None, None) => model.update_all_partitioned(interactions, counting.num_partitions)?,
+    }
+
+    Ok(())
+}
+
+/// Where the two types of interactions for cross-indicators come from
+enum CrossInput {
+    /// The primary and the secondary interactions are in separate inputfiles
+    Files { primary_path: String, secondary_path: String },
+    /// The action column of the inputfile tells the type of each interaction
+    Actions { path: String, primary_action: String, secondary_action: String },
+}
+
+/// The type of an interaction for cross-indicators
+#[derive(Clone, Copy)]
+enum InteractionType {
+    Primary,
+    Secondary,
+}
+
+impl CrossInput {
+
+    /// The inputfiles, together with the type of their interactions if they hold only one type
+    fn files(&self) -> Vec<(&str, Option<InteractionType>)> {
+        match *self {
+            CrossInput::Files { ref primary_path, ref secondary_path } => vec![
+                (primary_path, Some(InteractionType::Primary)),
+                (secondary_path, Some(InteractionType::Secondary)),
+            ],
+            CrossInput::Actions { ref path, .. } => vec![(path, None)],
+        }
+    }
+
+    /// Reads the interactions of an inputfile together with their types, and skips interactions
+    /// of other types
+    fn interactions<'a, R: Read>(
+        &'a self,
+        reader: &'a mut InteractionReader<R>,
+        interaction_type: Option<InteractionType>,
+    ) -> Box<dyn Iterator<Item = (String, String, InteractionType)> + 'a> {
+
+        match (interaction_type, self) {
+            (Some(interaction_type), _) => Box::new(reader
+                .interactions()
+                .map(move |(user, item)| (user, item, interaction_type))),
+            (None, CrossInput::Actions { primary_action, secondary_action, .. }) => Box::new(reader
+                .typed_interactions()
+                .filter_map(move |(user, item, action)| {
+                    if action == *primary_action {
+                        Some((user, item, InteractionType::Primary))
+                    } else if action == *secondary_action {
+                        Some((user, item, InteractionType::Secondary))
+                    } else {
+                        None
+                    }
+                })),
+            (None, CrossInput::Files { .. }) => {
+                unreachable!("Inputfiles without an action column hold one type of interactions")
+            },
+        }
+    }
+}
+
+fn compute_cross_indicators(
+    cross_input: &CrossInput,
+    input: &Input,
+    config: &IndicatorConfig,
+    indicators_path: Option<String>,
+    negative_indicators_path: Option<String>,
+) -> Result<(), Error> {
+
+    let files = cross_input.files();
+    let paths: Vec<&str> = files.iter().map(|(path, _)| *path).collect();
+
+    eprintln!("Reading {} to compute data statistics (pass 1/2)", paths.join(" and "));
+
+    let mut data_dict = DataDictionary::default();
+    for (path, interaction_type) in files.iter() {
+        let mut reader_pass_one = input.open(path, true)?;
+        for (user, item, _) in cross_input.interactions(&mut reader_pass_one, *interaction_type) {
+            data_dict.register(user, item)?;
+        }
+        input.check(&mut reader_pass_one, path, true)?;
+    }
+
+    print_statistics(&data_dict, Weighting::Unweighted, Deduplication::KeepFirst);
+
+    eprintln!(
+        "Reading {} to compute {} cross-indicators per item (pass 2/2)",
+        paths.join(" and "),
+        config.num_indicators_per_item(),
+    );
+    eprintln!("Downsampling interactions with seed {}", config.seed());
+
+    if config.measure() != Measure::LogLikelihoodRatio {
+        eprintln!("Ranking cross-indicators by {}", config.measure());
+    }
+
+    let mut model =
+        IndicatorModel::cross_with_config(data_dict.num_users(), data_dict.num_items(), config);
+    model.set_negative_indicators(negative_indicators_path.is_some());
 
-    let mut reader_pass_two = io::csv_reader(&interactions_path)?;
-    let interactions = io::interactions_from_csv(&mut reader_pass_two);
+    let start = Instant::now();
+
+    for (path, interaction_type) in files.iter() {
+        let mut reader = input.open(path, false)?;
+        for (user, item, interaction_type) in
+            cross_input.interactions(&mut reader, *interaction_type) {
+            let (user, item) = (data_dict.user_index(&user)?, data_dict.item_index(&item)?);
+            match interaction_type {
+                InteractionType::Primary => model.update(user, item),
+                InteractionType::Secondary => model.update_secondary(user, item)?,
+            }
+        }
+        input.check(&mut reader, path, false)?;
+    }
 
-    let indicators = recoreco::indicators(
-        interactions,
-        &data_dict,
-        n,
-        F_MAX,
-        K_MAX
+    let num_items_rescored = model.refresh();
+
+    print_repetitions(&model);
+    eprintln!(
+        "{} cross-occurrences observed, {}ms training time, {} items rescored",
+        model.num_cooccurrences_observed(),
+        start.elapsed().as_millis(),
+        num_items_rescored,
     );
 
-    // Build reverse index, make sure we consume the data dictionary
     let renaming: Renaming = data_dict.into();
 
-    println!("Writing indicators...");
-    recoreco::io::write_indicators(&indicators, &renaming, indicators_path)?;
+    if let (Some(negative_indicators), Some(path)) =
+        (model.negative_indicators(), negative_indicators_path) {
+        eprintln!("Writing negative cross-indicators...");
+        recoreco::io::write_indicators(negative_indicators, &renaming, Some(path))?;
+    }
+
+    eprintln!("Writing cross-indicators...");
+    recoreco::io::write_indicators(model.indicators(), &renaming, indicators_path)?;
 
     Ok(())
 }
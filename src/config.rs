@@ -0,0 +1,316 @@
+//! ## Configuration
+//!
+//! An `IndicatorConfig` bundles the tunables for the computation of the indicators: the number of
+//! indicators per item, how many interactions to account for per user and per item, the seed for
+//! the downsampling, the association measure and its thresholds, and the number of threads. It is
+//! built via an `IndicatorConfigBuilder`, which starts from the defaults and validates the
+//! tunables, so that an invalid configuration is reported before any interactions are read.
+//!
+/*
+ * RecoReco
+ * Copyright (C) 2018 Sebastian Schelter
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use error::Error;
+use measures::{Measure, Polarity};
+
+/// Number of indicators per item, unless configured otherwise
+pub const DEFAULT_NUM_INDICATORS_PER_ITEM: usize = 10;
+
+/// Maximum number of interactions per user (`k_max`) to account for, unless configured otherwise
+pub const DEFAULT_MAX_INTERACTIONS_PER_USER: u32 = 500;
+
+/// Maximum number of interactions per item (`f_max`) to account for, unless configured otherwise
+pub const DEFAULT_MAX_INTERACTIONS_PER_ITEM: u32 = 500;
+
+/// Validated tunables for the computation of the indicators, see `IndicatorConfigBuilder`.
+///
+/// # Examples
+///
+/// ```
+/// extern crate recoreco;
+/// use recoreco::config::IndicatorConfig;
+/// use recoreco::measures::Polarity;
+///
+/// let config = IndicatorConfig::builder()
+///     .num_indicators_per_item(20)
+///     .max_interactions_per_user(100)
+///     .polarity(Polarity::Positive)
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(config.num_indicators_per_item(), 20);
+/// assert_eq!(config.max_interactions_per_user(), 100);
+/// assert_eq!(config.max_interactions_per_item(), 500);
+///
+/// assert!(IndicatorConfig::builder().max_interactions_per_item(0).build().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndicatorConfig {
+    num_indicators_per_item: usize,
+    max_interactions_per_user: u32,
+    max_interactions_per_item: u32,
+    seed: u64,
+    measure: Measure,
+    polarity: Polarity,
+    min_score: f64,
+    min_cooccurrences: u32,
+    num_threads: Option<usize>,
+}
+
+impl Default for IndicatorConfig {
+    fn default() -> Self {
+        IndicatorConfig {
+            num_indicators_per_item: DEFAULT_NUM_INDICATORS_PER_ITEM,
+            max_interactions_per_user: DEFAULT_MAX_INTERACTIONS_PER_USER,
+            max_interactions_per_item: DEFAULT_MAX_INTERACTIONS_PER_ITEM,
+            seed: ::DEFAULT_SEED,
+            measure: Measure::LogLikelihoodRatio,
+            polarity: Polarity::Any,
+            min_score: f64::NEG_INFINITY,
+            min_cooccurrences: 1,
+            num_threads: None,
+        }
+    }
+}
+
+impl IndicatorConfig {
+
+    /// Creates a builder which starts from the default configuration
+    pub fn builder() -> IndicatorConfigBuilder {
+        IndicatorConfigBuilder::default()
+    }
+
+    /// The number of highly associated items to compute per item
+    pub fn num_indicators_per_item(&self) -> usize {
+        self.num_indicators_per_item
+    }
+
+    /// The maximum number of interactions to account for per user (`k_max`), we keep a uniform
+    /// sample of the interactions of users with more interactions
+    pub fn max_interactions_per_user(&self) -> u32 {
+        self.max_interactions_per_user
+    }
+
+    /// The maximum number of interactions to account for per item (`f_max`), we ignore further
+    /// interactions with an item once it reached this number
+    pub fn max_interactions_per_item(&self) -> u32 {
+        self.max_interactions_per_item
+    }
+
+    /// The seed for the random downsampling of the interactions
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The association measure to rank the indicators by
+    pub fn measure(&self) -> Measure {
+        self.measure
+    }
+
+    /// The direction of the associations to select as indicators
+    pub fn polarity(&self) -> Polarity {
+        self.polarity
+    }
+
+    /// The minimum association score of an indicator
+    pub fn min_score(&self) -> f64 {
+        self.min_score
+    }
+
+    /// The minimum number of cooccurrences of an indicator with an item
+    pub fn min_cooccurrences(&self) -> u32 {
+        self.min_cooccurrences
+    }
+
+    /// The number of ranges of items whose cooccurrences are counted in parallel on the rayon
+    /// thread pool, or `None` to count them sequentially
+    pub fn num_threads(&self) -> Option<usize> {
+        self.num_threads
+    }
+}
+
+/// Configures the computation of the indicators, see `IndicatorConfig`. All tunables start from
+/// their defaults, and `build` checks them.
+#[derive(Debug, Clone, Default)]
+pub struct IndicatorConfigBuilder {
+    config: IndicatorConfig,
+}
+
+impl IndicatorConfigBuilder {
+
+    /// Creates a builder which starts from the default configuration
+    pub fn new() -> Self {
+        IndicatorConfigBuilder::default()
+    }
+
+    /// The number of highly associated items to compute per item, 10 by default
+    pub fn num_indicators_per_item(&mut self, num_indicators_per_item: usize) -> &mut Self {
+        self.config.num_indicators_per_item = num_indicators_per_item;
+        self
+    }
+
+    /// The maximum number of interactions to account for per user (`k_max`), 500 by default
+    pub fn max_interactions_per_user(&mut self, max_interactions_per_user: u32) -> &mut Self {
+        self.config.max_interactions_per_user = max_interactions_per_user;
+        self
+    }
+
+    /// The maximum number of interactions to account for per item (`f_max`), 500 by default
+    pub fn max_interactions_per_item(&mut self, max_interactions_per_item: u32) -> &mut Self {
+        self.config.max_interactions_per_item = max_interactions_per_item;
+        self
+    }
+
+    /// The seed for the random downsampling of the interactions, `recoreco::DEFAULT_SEED` by
+    /// default. Runs with the same seed and the same input produce the same indicators.
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.config.seed = seed;
+        self
+    }
+
+    /// The association measure to rank the indicators by, the loglikelihood ratio by default
+    pub fn measure(&mut self, measure: Measure) -> &mut Self {
+        self.config.measure = measure;
+        self
+    }
+
+    /// The direction of the associations to select as indicators, `Polarity::Any` by default
+    pub fn polarity(&mut self, polarity: Polarity) -> &mut Self {
+        self.config.polarity = polarity;
+        self
+    }
+
+    /// The minimum association score of an indicator, there is no minimum by default
+    pub fn min_score(&mut self, min_score: f64) -> &mut Self {
+        self.config.min_score = min_score;
+        self
+    }
+
+    /// The minimum number of cooccurrences of an indicator with an item, 1 by default
+    pub fn min_cooccurrences(&mut self, min_cooccurrences: u32) -> &mut Self {
+        self.config.min_cooccurrences = min_cooccurrences;
+        self
+    }
+
+    /// The number of ranges of items whose cooccurrences are counted in parallel on the rayon
+    /// thread pool, which gives the same results as counting them sequentially, the default
+    pub fn num_threads(&mut self, num_threads: usize) -> &mut Self {
+        self.config.num_threads = Some(num_threads);
+        self
+    }
+
+    /// Returns the configuration, or fails with `Error::Config` if a tunable is out of range, and
+    /// with `Error::Overflow` if the maximum numbers of interactions per user and item are too
+    /// large to count their cooccurrences in `u32`. Large products are fine otherwise, as the
+    /// loglikelihood ratio only precomputes the logarithms of the first 2^20 sums.
+    pub fn build(&self) -> Result<IndicatorConfig, Error> {
+
+        let config = &self.config;
+
+        let problem = if config.num_indicators_per_item == 0 {
+            String::from("The number of indicators per item must be positive")
+        } else if config.max_interactions_per_user == 0 || config.max_interactions_per_item == 0 {
+            String::from("The maximum numbers of interactions per user and item must be positive")
+        } else if config
+            .max_interactions_per_user
+            .checked_mul(config.max_interactions_per_item)
+            .is_none() {
+            // A row or column sum of the cooccurrence matrix can reach this product
+            return Err(Error::Overflow(format!(
+                "The product of the maximum numbers of interactions per user and item must not \
+                exceed {}",
+                u32::MAX,
+            )));
+        } else if config.min_score.is_nan() {
+            String::from("The minimum score must be a number")
+        } else if config.num_threads == Some(0) {
+            String::from("The number of threads must be positive")
+        } else {
+            return Ok(config.clone());
+        };
+
+        Err(Error::Config(problem))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use config::{IndicatorConfig, IndicatorConfigBuilder};
+    use error::Error;
+    use measures::Measure;
+
+    #[test]
+    fn validate_tunables() {
+
+        let config = IndicatorConfigBuilder::new()
+            .seed(42)
+            .measure(Measure::Jaccard)
+            .min_cooccurrences(3)
+            .num_threads(4)
+            .build()
+            .unwrap();
+
+        assert_eq!(config.seed(), 42);
+        assert_eq!(config.measure(), Measure::Jaccard);
+        assert_eq!(config.min_cooccurrences(), 3);
+        assert_eq!(config.num_threads(), Some(4));
+        assert_eq!(IndicatorConfig::builder().build().unwrap(), IndicatorConfig::default());
+
+        let invalid_configs = vec![
+            IndicatorConfig::builder().num_indicators_per_item(0).build(),
+            IndicatorConfig::builder().max_interactions_per_user(0).build(),
+            IndicatorConfig::builder().min_score(f64::NAN).build(),
+            IndicatorConfig::builder().num_threads(0).build(),
+        ];
+
+        for invalid_config in invalid_configs {
+            match invalid_config {
+                Err(Error::Config(_)) => {},
+                other => panic!("Expected a configuration error, got {:?}", other),
+            }
+        }
+
+        let too_large_caps = IndicatorConfig::builder()
+            .max_interactions_per_user(100_000)
+            .max_interactions_per_item(100_000)
+            .build();
+
+        match too_large_caps {
+            Err(Error::Overflow(_)) => {},
+            other => panic!("Expected an overflow, got {:?}", other),
+        }
+
+        // The product of the largest caps is exactly u32::MAX
+        let largest_caps = IndicatorConfig::builder()
+            .max_interactions_per_user(65_535)
+            .max_interactions_per_item(65_537)
+            .build();
+        assert!(largest_caps.is_ok());
+
+        let caps_past_the_limit = IndicatorConfig::builder()
+            .max_interactions_per_user(65_536)
+            .max_interactions_per_item(65_536)
+            .build();
+
+        match caps_past_the_limit {
+            Err(Error::Overflow(_)) => {},
+            other => panic!("Expected an overflow, got {:?}", other),
+        }
+    }
+}
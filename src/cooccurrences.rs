@@ -0,0 +1,316 @@
+//! ## Counting cooccurrences within a memory budget
+//!
+//! Hash-based rows of counts support incremental updates, but carry a large overhead per entry.
+//! For large catalogs of items, we count the cooccurrences of a batch of interactions with a
+//! `CooccurrenceCounter` instead, which spills its partial counts to sorted run files on disk
+//! whenever they exceed a memory budget. The runs are merged afterwards into a compact
+//! `recoreco::types::CsrMatrix` for scoring.
+//!
+/*
+ * RecoReco
+ * Copyright (C) 2018 Sebastian Schelter
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use fnv::FnvHashMap;
+
+use snapshot;
+use types::CsrMatrix;
+
+/// Estimated number of bytes of memory per buffered count, including the overhead of the hash map
+pub const BYTES_PER_COUNT: usize = 24;
+
+/// Distinguishes the run files of counters within the same process
+static NEXT_COUNTER_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts cooccurrences of pairs of items in a hash map, and spills the counts to a sorted run
+/// file in the temporary directory as soon as they exceed the memory budget.
+///
+/// # Examples
+///
+/// ```
+/// extern crate recoreco;
+/// use recoreco::cooccurrences::CooccurrenceCounter;
+///
+/// /* A tiny budget forces the counter to spill after every other count */
+/// let mut counter = CooccurrenceCounter::new(48);
+///
+/// counter.increment(0, 1);
+/// counter.increment(1, 0);
+/// counter.increment(0, 1);
+///
+/// let c = counter.into_matrix(2).unwrap();
+///
+/// assert_eq!(c.get(0, 1), 2);
+/// assert_eq!(c.get(1, 0), 1);
+/// ```
+pub struct CooccurrenceCounter {
+    memory_budget: usize,
+    counts: FnvHashMap<(u32, u32), u32>,
+    spill_directory: PathBuf,
+    id: usize,
+    runs: Vec<PathBuf>,
+}
+
+impl CooccurrenceCounter {
+
+    /// Creates a counter which holds at most `memory_budget` bytes worth of counts in memory.
+    pub fn new(memory_budget: usize) -> Self {
+        CooccurrenceCounter {
+            memory_budget,
+            counts: FnvHashMap::default(),
+            spill_directory: env::temp_dir(),
+            id: NEXT_COUNTER_ID.fetch_add(1, Ordering::SeqCst),
+            runs: Vec::new(),
+        }
+    }
+
+    /// Returns the number of run files which have been spilled to disk so far.
+    pub fn num_spills(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Increments the count of the pair of `row` and `column` by one.
+    pub fn increment(&mut self, row: u32, column: u32) -> io::Result<()> {
+        self.add(row, column, 1)
+    }
+
+    /// Adds `count` to the count of the pair of `row` and `column`, and spills the counts to disk
+    /// if they exceed the memory budget.
+    pub fn add(&mut self, row: u32, column: u32, count: u32) -> io::Result<()> {
+
+        let current = self.counts.entry((row, column)).or_insert(0);
+        *current = current.checked_add(count).expect("Count exceeds u32::MAX");
+
+        if self.counts.len() * BYTES_PER_COUNT > self.memory_budget {
+            self.spill()?;
+        }
+
+        Ok(())
+    }
+
+    /// Merges the spilled runs and the buffered counts into a compact matrix with `num_rows` rows.
+    /// The run files are removed afterwards.
+    pub fn into_matrix(mut self, num_rows: usize) -> io::Result<CsrMatrix> {
+
+        if self.runs.is_empty() {
+            let mut entries: Vec<(u32, u32, u32)> = self.counts
+                .drain()
+                .map(|((row, column), count)| (row, column, count))
+                .collect();
+            entries.sort_unstable();
+
+            return Ok(CsrMatrix::from_sorted_entries(num_rows, entries.into_iter()));
+        }
+
+        self.spill()?;
+
+        let mut runs = Vec::with_capacity(self.runs.len());
+        for path in &self.runs {
+            runs.push(Run::open(path)?);
+        }
+
+        let mut merged_runs = MergedRuns::new(runs)?;
+        let matrix = CsrMatrix::from_sorted_entries(num_rows, merged_runs.by_ref());
+
+        match merged_runs.error {
+            Some(error) => Err(error),
+            None => Ok(matrix),
+        }
+    }
+
+    /// Writes the buffered counts, sorted by row and column, to a new run file
+    fn spill(&mut self) -> io::Result<()> {
+
+        let mut entries: Vec<(u32, u32, u32)> = self.counts
+            .iter()
+            .map(|(&(row, column), &count)| (row, column, count))
+            .collect();
+        entries.sort_unstable();
+
+        // Keep the capacity of the hash map, it fills up to the same size again until the next
+        // spill, so releasing its memory would only make us reallocate and rehash it every time
+        self.counts.clear();
+
+        let path = self.spill_directory.join(format!(
+            "recoreco-{}-{}-{}.run",
+            process::id(),
+            self.id,
+            self.runs.len(),
+        ));
+
+        let mut out = BufWriter::new(File::create(&path)?);
+        self.runs.push(path);
+
+        snapshot::write_u64(&mut out, entries.len() as u64)?;
+        for (row, column, count) in entries {
+            snapshot::write_u32(&mut out, row)?;
+            snapshot::write_u32(&mut out, column)?;
+            snapshot::write_u32(&mut out, count)?;
+        }
+
+        out.flush()
+    }
+}
+
+impl Drop for CooccurrenceCounter {
+    fn drop(&mut self) {
+        for path in &self.runs {
+            // The run files are temporary, there is nothing left to do if their removal fails
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Sequential reader for the entries of a spilled run file
+struct Run {
+    input: BufReader<File>,
+    num_remaining_entries: u64,
+}
+
+impl Run {
+
+    fn open(path: &PathBuf) -> io::Result<Self> {
+        let mut input = BufReader::new(File::open(path)?);
+        let num_remaining_entries = snapshot::read_u64(&mut input)?;
+        Ok(Run { input, num_remaining_entries })
+    }
+
+    fn next_entry(&mut self) -> io::Result<Option<(u32, u32, u32)>> {
+        if self.num_remaining_entries == 0 {
+            return Ok(None);
+        }
+        self.num_remaining_entries -= 1;
+
+        let row = snapshot::read_u32(&mut self.input)?;
+        let column = snapshot::read_u32(&mut self.input)?;
+        let count = snapshot::read_u32(&mut self.input)?;
+
+        Ok(Some((row, column, count)))
+    }
+}
+
+/// K-way merge of sorted runs, which sums up the counts of equal pairs. Iteration stops at the
+/// first failed read, and the error is kept for the caller.
+struct MergedRuns {
+    runs: Vec<Run>,
+    heap: BinaryHeap<Reverse<(u32, u32, usize, u32)>>,
+    error: Option<io::Error>,
+}
+
+impl MergedRuns {
+
+    fn new(mut runs: Vec<Run>) -> io::Result<Self> {
+        let mut heap = BinaryHeap::with_capacity(runs.len());
+        for (run_index, run) in runs.iter_mut().enumerate() {
+            if let Some((row, column, count)) = run.next_entry()? {
+                heap.push(Reverse((row, column, run_index, count)));
+            }
+        }
+        Ok(MergedRuns { runs, heap, error: None })
+    }
+
+    /// Pops the smallest entry and refills the heap from the run it came from
+    fn pop(&mut self) -> io::Result<Option<(u32, u32, u32)>> {
+        match self.heap.pop() {
+            Some(Reverse((row, column, run_index, count))) => {
+                if let Some((next_row, next_column, next_count)) =
+                    self.runs[run_index].next_entry()? {
+                    self.heap.push(Reverse((next_row, next_column, run_index, next_count)));
+                }
+                Ok(Some((row, column, count)))
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+impl Iterator for MergedRuns {
+    type Item = (u32, u32, u32);
+
+    fn next(&mut self) -> Option<Self::Item> {
+
+        let (row, column, mut count) = match self.pop() {
+            Ok(entry) => entry?,
+            Err(error) => {
+                self.error = Some(error);
+                return None;
+            }
+        };
+
+        // Sum up the counts of the same pair from the other runs
+        while let Some(Reverse((next_row, next_column, _, _))) = self.heap.peek() {
+            if (*next_row, *next_column) != (row, column) {
+                break;
+            }
+            match self.pop() {
+                Ok(Some((_, _, next_count))) => {
+                    count = count.checked_add(next_count).expect("Count exceeds u32::MAX");
+                },
+                Ok(None) => break,
+                Err(error) => {
+                    self.error = Some(error);
+                    return None;
+                }
+            }
+        }
+
+        Some((row, column, count))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use cooccurrences::{CooccurrenceCounter, BYTES_PER_COUNT};
+
+    #[test]
+    fn spilled_counts_are_merged() {
+
+        let pairs: Vec<(u32, u32)> = (0..5000u32).map(|n| (n % 13, (n * 7) % 17)).collect();
+
+        let mut in_memory = CooccurrenceCounter::new(usize::MAX);
+        let mut spilling = CooccurrenceCounter::new(20 * BYTES_PER_COUNT);
+
+        for (row, column) in pairs {
+            in_memory.increment(row, column).unwrap();
+            spilling.increment(row, column).unwrap();
+        }
+
+        assert_eq!(in_memory.num_spills(), 0);
+        assert!(spilling.num_spills() > 1);
+
+        let runs = spilling.runs.clone();
+        let expected = in_memory.into_matrix(13).unwrap();
+        let merged = spilling.into_matrix(13).unwrap();
+
+        assert_eq!(merged, expected);
+        assert_eq!(merged.num_entries(), 13 * 17);
+        assert!(runs.iter().all(|path| !path.exists()));
+    }
+}
@@ -0,0 +1,243 @@
+//! ## Deduplication of repeated interactions
+//!
+//! Users often interact with the same item several times, e.g., they listen to a song again or
+//! buy the same product twice. If we fed every repetition to the model, the item would appear
+//! several times in the history of the user, which inflates its cooccurrence counts, makes it
+//! cooccur with itself and uses up the budget of `k_max` interactions per user. The downsampler
+//! therefore skips repetitions of an item which is already in the history of a user, without
+//! keeping track of all user-item pairs. A `Deduplication` policy decides which weight the
+//! interaction of a pair carries, and a `Deduplicator` combines the weights of the repetitions
+//! in a first pass over the interactions for the policies which need to see all of them.
+//!
+/*
+ * RecoReco
+ * Copyright (C) 2018 Sebastian Schelter
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::collections::hash_map::Entry;
+use std::fmt;
+use std::str::FromStr;
+
+use fnv::FnvHashMap;
+
+/// Which weight the single interaction of a repeatedly interacting user-item pair carries. The
+/// combined weight changes the counts via a prioritised weighting, where it is the priority which
+/// decides whether the pair is kept in the downsampled history of the user, see
+/// `recoreco::weights::Weighting::is_prioritised`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Deduplication {
+    /// Only the first interaction counts, repetitions are ignored (the default)
+    #[default]
+    KeepFirst,
+    /// The pair counts once with the highest weight of its interactions, e.g., the best rating
+    CountOnce,
+    /// The pair counts once with the sum of the weights of its interactions, e.g., the overall
+    /// play count. For unweighted interactions, this is the number of repetitions.
+    SumWeights,
+}
+
+impl Deduplication {
+
+    /// Whether the weight of a pair is only known after all of its interactions have been seen
+    pub fn requires_two_passes(&self) -> bool {
+        *self != Deduplication::KeepFirst
+    }
+
+    fn combine(&self, weight: f64, repeated_weight: f64) -> f64 {
+        match *self {
+            Deduplication::KeepFirst => weight,
+            Deduplication::CountOnce => weight.max(repeated_weight),
+            Deduplication::SumWeights => weight + repeated_weight,
+        }
+    }
+}
+
+impl fmt::Display for Deduplication {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Deduplication::KeepFirst => write!(f, "first"),
+            Deduplication::CountOnce => write!(f, "once"),
+            Deduplication::SumWeights => write!(f, "weight"),
+        }
+    }
+}
+
+impl FromStr for Deduplication {
+    type Err = String;
+
+    /// Parses `first`, `once` or `weight`
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "first" => Ok(Deduplication::KeepFirst),
+            "once" => Ok(Deduplication::CountOnce),
+            "weight" => Ok(Deduplication::SumWeights),
+            _ => Err(format!(
+                "Unknown deduplication '{}', expected one of first, once or weight",
+                name,
+            )),
+        }
+    }
+}
+
+/// Keeps track of the user-item pairs seen so far and of their combined weights. We `observe` all
+/// interactions in a first pass to combine the weights of the repetitions, and `take` the combined
+/// weight of a pair at its first interaction in the second pass.
+///
+/// Note that a deduplicator holds every distinct user-item pair, so it is only worth its memory
+/// for policies which require two passes, see `Deduplication::requires_two_passes`.
+///
+/// # Examples
+///
+/// ```
+/// extern crate recoreco;
+/// use recoreco::dedup::{Deduplication, Deduplicator};
+///
+/// let mut deduplicator = Deduplicator::new(Deduplication::SumWeights);
+///
+/// /* First pass */
+/// deduplicator.observe(0, 1, 2.0);
+/// deduplicator.observe(0, 1, 3.0);
+///
+/// assert_eq!(deduplicator.num_duplicates(), 1);
+///
+/// /* Second pass */
+/// assert_eq!(deduplicator.take(0, 1), Some(5.0));
+/// assert_eq!(deduplicator.take(0, 1), None);
+/// ```
+pub struct Deduplicator {
+    policy: Deduplication,
+    weights: FnvHashMap<(u32, u32), f64>,
+    num_duplicates: u64,
+}
+
+impl Deduplicator {
+
+    /// Creates a deduplicator which combines the weights of repeated interactions via `policy`.
+    pub fn new(policy: Deduplication) -> Self {
+        Deduplicator { policy, weights: FnvHashMap::default(), num_duplicates: 0 }
+    }
+
+    /// Returns the deduplication policy
+    pub fn policy(&self) -> Deduplication {
+        self.policy
+    }
+
+    /// Returns the number of repeated interactions seen so far
+    pub fn num_duplicates(&self) -> u64 {
+        self.num_duplicates
+    }
+
+    /// Records an interaction between `user` and `item` with the given `weight` and combines its
+    /// weight with the weights of previous interactions of the pair. Returns whether this is the
+    /// first interaction of the pair.
+    pub fn observe(&mut self, user: u32, item: u32, weight: f64) -> bool {
+        match self.weights.entry((user, item)) {
+            Entry::Occupied(mut entry) => {
+                let combined_weight = self.policy.combine(*entry.get(), weight);
+                entry.insert(combined_weight);
+                self.num_duplicates += 1;
+                false
+            },
+            Entry::Vacant(entry) => {
+                entry.insert(weight);
+                true
+            },
+        }
+    }
+
+    /// Returns the combined weight of the pair of `user` and `item` for its first interaction, and
+    /// `None` for the repetitions of the pair or pairs which have not been observed.
+    pub fn take(&mut self, user: u32, item: u32) -> Option<f64> {
+        self.weights.remove(&(user, item))
+    }
+}
+
+/// Removes the repetitions of user-item pairs from a batch of `interactions` in memory, and keeps
+/// the first interaction of every pair in its position. Sorts the positions of the interactions
+/// instead of hashing the pairs. Returns the number of removed repetitions.
+pub fn keep_first(interactions: &mut Vec<(u32, u32)>) -> u64 {
+
+    let mut positions: Vec<usize> = (0..interactions.len()).collect();
+    positions.sort_unstable_by_key(|position| (interactions[*position], *position));
+
+    let mut is_repetition = vec![false; interactions.len()];
+    for pair in positions.windows(2) {
+        if interactions[pair[0]] == interactions[pair[1]] {
+            is_repetition[pair[1]] = true;
+        }
+    }
+
+    let num_interactions = interactions.len();
+    let mut position = 0;
+    interactions.retain(|_| {
+        position += 1;
+        !is_repetition[position - 1]
+    });
+
+    (num_interactions - interactions.len()) as u64
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use dedup::{keep_first, Deduplication, Deduplicator};
+
+    #[test]
+    fn combine_repeated_weights() {
+
+        let interactions = [(0, 0, 2.0), (0, 1, 1.0), (0, 0, 5.0), (1, 0, 1.0), (0, 0, 3.0)];
+
+        for &(policy, expected_weight) in &[
+            (Deduplication::KeepFirst, 2.0),
+            (Deduplication::CountOnce, 5.0),
+            (Deduplication::SumWeights, 10.0),
+        ] {
+            let mut deduplicator = Deduplicator::new(policy);
+            for &(user, item, weight) in &interactions {
+                deduplicator.observe(user, item, weight);
+            }
+
+            assert_eq!(deduplicator.num_duplicates(), 2);
+
+            assert_eq!(deduplicator.take(0, 0), Some(expected_weight));
+            assert_eq!(deduplicator.take(0, 1), Some(1.0));
+            assert_eq!(deduplicator.take(0, 0), None);
+            assert_eq!(deduplicator.take(1, 0), Some(1.0));
+        }
+    }
+
+    #[test]
+    fn keep_first_interactions() {
+
+        let mut interactions = vec![(0, 0), (0, 1), (1, 0), (0, 0), (1, 0), (0, 0), (2, 1)];
+
+        assert_eq!(keep_first(&mut interactions), 3);
+        assert_eq!(interactions, vec![(0, 0), (0, 1), (1, 0), (2, 1)]);
+    }
+
+    #[test]
+    fn parse_deduplications() {
+        for policy in &[
+            Deduplication::KeepFirst,
+            Deduplication::CountOnce,
+            Deduplication::SumWeights,
+        ] {
+            assert_eq!(policy.to_string().parse(), Ok(*policy));
+        }
+        assert!("last".parse::<Deduplication>().is_err());
+    }
+}
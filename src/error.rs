@@ -0,0 +1,105 @@
+//! ## Errors
+//!
+//! The fallible operations of **recoreco** report failures via a single `Error` type, which
+//! distinguishes failures to read or write files, malformed input, identifiers which are unknown
+//! to a data dictionary, numbers which exceed the range of their type, and invalid parameters.
+//! Functions which only read or write files, like the ones in `snapshot`, return an
+//! `io::Result`, whose errors convert into `Error`.
+//!
+/*
+ * RecoReco
+ * Copyright (C) 2018 Sebastian Schelter
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+extern crate csv;
+
+use std::error;
+use std::fmt;
+use std::io;
+
+/// The ways in which an operation of **recoreco** can fail
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a file failed
+    Io(io::Error),
+    /// The input does not have the expected format, e.g., a malformed line in strict mode
+    Parse(String),
+    /// A user which is unknown to the data dictionary
+    UnknownUser(String),
+    /// An item which is unknown to the data dictionary or to the indicators
+    UnknownItem(String),
+    /// A number exceeds the range of its type, e.g., there are too many items for `u32` indexes
+    Overflow(String),
+    /// An invalid parameter, e.g., a column name which does not occur in the header line
+    Config(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref error) => write!(f, "{}", error),
+            Error::Parse(ref message) => write!(f, "{}", message),
+            Error::UnknownUser(ref user) => write!(f, "Unknown user '{}'", user),
+            Error::UnknownItem(ref item) => write!(f, "Unknown item '{}'", item),
+            Error::Overflow(ref message) => write!(f, "{}", message),
+            Error::Config(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+/// Failures to read the underlying input are IO errors, everything else is malformed input
+impl From<csv::Error> for Error {
+    fn from(error: csv::Error) -> Self {
+        if let csv::ErrorKind::Io(_) = *error.kind() {
+            return Error::Io(error.into());
+        }
+        Error::Parse(error.to_string())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::io;
+
+    use error::Error;
+
+    #[test]
+    fn describe_errors() {
+
+        let error: Error = io::Error::new(io::ErrorKind::NotFound, "No such file").into();
+        assert_eq!(error.to_string(), "No such file");
+
+        let error = Error::UnknownItem(String::from("unicorn"));
+        assert_eq!(error.to_string(), "Unknown item 'unicorn'");
+    }
+}
@@ -0,0 +1,396 @@
+//! ## Offline evaluation of the recommendation quality
+//!
+//! In order to find out whether a change of the parameters, e.g., of the number of indicators per
+//! item, improves the quality of the recommendations, we hold out some interactions of every user
+//! as test set, compute indicators from the remaining training interactions, and check how well
+//! the recommendations for each user predict the held out items. We report the hit rate,
+//! precision, recall, mean average precision (MAP) and normalized discounted cumulative gain
+//! (NDCG) of the top-k recommendations, together with the same metrics for recommending the most
+//! popular items as a baseline.
+//!
+/*
+ * RecoReco
+ * Copyright (C) 2018 Sebastian Schelter
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::fmt;
+use std::str::FromStr;
+
+use fnv::FnvHashSet;
+use rand::Rng;
+
+use recommend::Recommender;
+use sampling::SamplingRng;
+use types::IndicatorMatrix;
+
+/// How to split the interactions of every user into a training and a test set. A user keeps at
+/// least one training interaction, users with a single interaction are only used for training.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Split {
+    /// Holds out a random fraction of the interactions of every user
+    RandomFraction(f64),
+    /// Holds out the last interactions of every user, e.g., in a chronologically sorted file
+    LastN(usize),
+}
+
+impl fmt::Display for Split {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Split::RandomFraction(fraction) => write!(f, "random:{}", fraction),
+            Split::LastN(n) => write!(f, "last:{}", n),
+        }
+    }
+}
+
+impl FromStr for Split {
+    type Err = String;
+
+    /// Parses `random:<fraction>` or `last:<number>`
+    fn from_str(description: &str) -> Result<Self, Self::Err> {
+
+        let invalid = || {
+            format!(
+                "Unknown split '{}', expected random:<fraction> with a fraction in (0, 1) or \
+                last:<number> with a positive number",
+                description,
+            )
+        };
+
+        let mut parts = description.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("random"), Some(fraction)) => match fraction.parse() {
+                Ok(fraction) if fraction > 0.0 && fraction < 1.0 => {
+                    Ok(Split::RandomFraction(fraction))
+                },
+                _ => Err(invalid()),
+            },
+            (Some("last"), Some(n)) => match n.parse() {
+                Ok(n) if n > 0 => Ok(Split::LastN(n)),
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Interactions between users and items, split into a training and a test set
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Holdout {
+    pub train: Vec<(u32, u32)>,
+    pub test: Vec<(u32, u32)>,
+}
+
+/// Splits the `interactions` between users and items into a training and a test set per user,
+/// both in the order of the input. The `seed` determines the held out interactions for a random
+/// split.
+pub fn split_per_user(interactions: &[(u32, u32)], split: Split, seed: u64) -> Holdout {
+
+    let num_users = interactions
+        .iter()
+        .map(|(user, _)| *user as usize + 1)
+        .max()
+        .unwrap_or(0);
+
+    let mut positions_per_user: Vec<Vec<usize>> = vec![Vec::new(); num_users];
+    for (position, (user, _)) in interactions.iter().enumerate() {
+        positions_per_user[*user as usize].push(position);
+    }
+
+    let mut rng = SamplingRng::from_seed(seed);
+    let mut held_out = vec![false; interactions.len()];
+
+    for positions in &mut positions_per_user {
+
+        let num_held_out = match split {
+            Split::RandomFraction(fraction) => {
+                rng.shuffle(positions);
+                (positions.len() as f64 * fraction).round() as usize
+            },
+            Split::LastN(n) => n,
+        };
+
+        let num_held_out = num_held_out.min(positions.len().saturating_sub(1));
+        for position in &positions[positions.len() - num_held_out..] {
+            held_out[*position] = true;
+        }
+    }
+
+    let (test, train): (Vec<_>, Vec<_>) = interactions
+        .iter()
+        .zip(held_out)
+        .partition(|(_, is_held_out)| *is_held_out);
+
+    let unzip = |interactions: Vec<(&(u32, u32), bool)>| -> Vec<(u32, u32)> {
+        interactions.into_iter().map(|(interaction, _)| *interaction).collect()
+    };
+
+    Holdout { train: unzip(train), test: unzip(test) }
+}
+
+/// Ranking metrics of the top-k recommendations, averaged over the evaluated users
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Metrics {
+    pub num_users: usize,
+    pub hit_rate: f64,
+    pub precision: f64,
+    pub recall: f64,
+    pub map: f64,
+    pub ndcg: f64,
+}
+
+/// Accumulates the ranking metrics of the top-`k` recommendations for many users.
+///
+/// # Examples
+///
+/// ```
+/// extern crate recoreco;
+/// use recoreco::evaluation::Evaluator;
+///
+/// let mut evaluator = Evaluator::new(2);
+///
+/// /* The held out item 7 is recommended at the second position */
+/// evaluator.add(&[3, 7, 9], &[7, 8]);
+///
+/// let metrics = evaluator.metrics();
+///
+/// assert_eq!(metrics.hit_rate, 1.0);
+/// assert_eq!(metrics.precision, 0.5);
+/// assert_eq!(metrics.recall, 0.5);
+/// assert_eq!(metrics.map, 0.25);
+/// ```
+pub struct Evaluator {
+    k: usize,
+    num_users: usize,
+    hits: f64,
+    precision: f64,
+    recall: f64,
+    average_precision: f64,
+    ndcg: f64,
+}
+
+impl Evaluator {
+
+    /// Creates an evaluator for the top-`k` recommendations
+    pub fn new(k: usize) -> Self {
+        Evaluator {
+            k,
+            num_users: 0,
+            hits: 0.0,
+            precision: 0.0,
+            recall: 0.0,
+            average_precision: 0.0,
+            ndcg: 0.0,
+        }
+    }
+
+    /// Adds the metrics for a user with the ranked `recommended` items and the held out `relevant`
+    /// items. Users without relevant items are ignored.
+    pub fn add(&mut self, recommended: &[u32], relevant: &[u32]) {
+
+        let relevant: FnvHashSet<u32> = relevant.iter().cloned().collect();
+        if relevant.is_empty() || self.k == 0 {
+            return;
+        }
+
+        let mut num_hits = 0;
+        let mut sum_of_precisions = 0.0;
+        let mut dcg = 0.0;
+
+        for (rank, item) in recommended.iter().take(self.k).enumerate() {
+            if relevant.contains(item) {
+                num_hits += 1;
+                sum_of_precisions += f64::from(num_hits) / (rank + 1) as f64;
+                dcg += 1.0 / (rank as f64 + 2.0).log2();
+            }
+        }
+
+        let num_ideal_hits = relevant.len().min(self.k);
+        let ideal_dcg: f64 = (0..num_ideal_hits)
+            .map(|rank| 1.0 / (rank as f64 + 2.0).log2())
+            .sum();
+
+        self.num_users += 1;
+        if num_hits > 0 {
+            self.hits += 1.0;
+        }
+        self.precision += f64::from(num_hits) / self.k as f64;
+        self.recall += f64::from(num_hits) / relevant.len() as f64;
+        self.average_precision += sum_of_precisions / num_ideal_hits as f64;
+        self.ndcg += dcg / ideal_dcg;
+    }
+
+    /// Returns the metrics averaged over all users added so far
+    pub fn metrics(&self) -> Metrics {
+
+        if self.num_users == 0 {
+            return Metrics::default();
+        }
+
+        let num_users = self.num_users as f64;
+
+        Metrics {
+            num_users: self.num_users,
+            hit_rate: self.hits / num_users,
+            precision: self.precision / num_users,
+            recall: self.recall / num_users,
+            map: self.average_precision / num_users,
+            ndcg: self.ndcg / num_users,
+        }
+    }
+}
+
+/// Metrics of the recommendations from the indicators and of the popularity baseline
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Report {
+    pub indicators: Metrics,
+    pub popularity: Metrics,
+}
+
+/// Evaluates the top-`k` recommendations from the `indicators`, which have been computed from the
+/// training interactions of the `holdout`, on its held out test interactions. The popularity
+/// baseline recommends the items with the most training interactions. In both cases, we do not
+/// recommend the items from the training interactions of a user.
+pub fn evaluate(holdout: &Holdout, indicators: &IndicatorMatrix, k: usize) -> Report {
+
+    let Holdout { ref train, ref test } = *holdout;
+
+    let num_users = train
+        .iter()
+        .chain(test.iter())
+        .map(|(user, _)| *user as usize + 1)
+        .max()
+        .unwrap_or(0);
+
+    let histories = group_by_user(train, num_users);
+    let held_out_items = group_by_user(test, num_users);
+
+    let mut popularity = vec![0usize; indicators.num_items()];
+    for (_, item) in train.iter() {
+        if *item as usize >= popularity.len() {
+            popularity.resize(*item as usize + 1, 0);
+        }
+        popularity[*item as usize] += 1;
+    }
+
+    let mut popular_items: Vec<u32> = (0..popularity.len() as u32).collect();
+    popular_items.sort_by_key(|item| (usize::MAX - popularity[*item as usize], *item));
+
+    let recommender = Recommender::new(indicators);
+    let mut indicator_evaluator = Evaluator::new(k);
+    let mut popularity_evaluator = Evaluator::new(k);
+
+    for (history, relevant) in histories.iter().zip(held_out_items.iter()) {
+
+        if relevant.is_empty() {
+            continue;
+        }
+
+        let recommended: Vec<u32> = recommender
+            .recommend(history, k)
+            .iter()
+            .map(|scored_item| scored_item.item)
+            .collect();
+        indicator_evaluator.add(&recommended, relevant);
+
+        let seen_items: FnvHashSet<u32> = history.iter().cloned().collect();
+        let most_popular: Vec<u32> = popular_items
+            .iter()
+            .filter(|item| !seen_items.contains(item))
+            .take(k)
+            .cloned()
+            .collect();
+        popularity_evaluator.add(&most_popular, relevant);
+    }
+
+    Report {
+        indicators: indicator_evaluator.metrics(),
+        popularity: popularity_evaluator.metrics(),
+    }
+}
+
+fn group_by_user(interactions: &[(u32, u32)], num_users: usize) -> Vec<Vec<u32>> {
+    let mut items_per_user = vec![Vec::new(); num_users];
+    for (user, item) in interactions {
+        items_per_user[*user as usize].push(*item);
+    }
+    items_per_user
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use evaluation::{evaluate, split_per_user, Evaluator, Holdout, Split};
+    use types::{IndicatorMatrix, ScoredItem};
+
+    #[test]
+    fn split_holds_out_interactions_per_user() {
+
+        let interactions: Vec<(u32, u32)> = vec![(0, 0), (0, 1), (1, 0), (0, 2), (2, 3), (0, 3)];
+
+        let holdout = split_per_user(&interactions, Split::LastN(2), 42);
+        assert_eq!(holdout.train, vec![(0, 0), (0, 1), (1, 0), (2, 3)]);
+        assert_eq!(holdout.test, vec![(0, 2), (0, 3)]);
+
+        let holdout = split_per_user(&interactions, Split::RandomFraction(0.5), 42);
+        assert_eq!(holdout.train.len(), 4);
+        assert_eq!(holdout.test.len(), 2);
+        assert!(holdout.test.iter().all(|(user, _)| *user == 0));
+
+        assert_eq!("random:0.2".parse(), Ok(Split::RandomFraction(0.2)));
+        assert_eq!("last:3".parse(), Ok(Split::LastN(3)));
+        assert!("random:1.5".parse::<Split>().is_err());
+        assert!("last:0".parse::<Split>().is_err());
+    }
+
+    #[test]
+    fn ranking_metrics() {
+
+        let mut evaluator = Evaluator::new(3);
+
+        evaluator.add(&[1, 2, 3], &[1, 3]);
+        evaluator.add(&[4, 5, 6], &[7]);
+        evaluator.add(&[1], &[]);
+
+        let metrics = evaluator.metrics();
+
+        let ndcg = (1.0 + 1.0 / 4f64.log2()) / (1.0 + 1.0 / 3f64.log2());
+
+        assert_eq!(metrics.num_users, 2);
+        assert!((metrics.hit_rate - 0.5).abs() < 1e-9);
+        assert!((metrics.precision - (2.0 / 3.0) / 2.0).abs() < 1e-9);
+        assert!((metrics.recall - 0.5).abs() < 1e-9);
+        assert!((metrics.map - (1.0 + 2.0 / 3.0) / 2.0 / 2.0).abs() < 1e-9);
+        assert!((metrics.ndcg - ndcg / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn compare_with_popularity_baseline() {
+
+        let mut indicators = IndicatorMatrix::new(4);
+        indicators.set_indicators_for(0, vec![ScoredItem { item: 3, score: 1.0 }]);
+
+        let holdout = Holdout {
+            train: vec![(0, 0), (1, 1), (2, 1), (2, 0)],
+            test: vec![(0, 3)],
+        };
+
+        let report = evaluate(&holdout, &indicators, 1);
+
+        assert_eq!(report.indicators.hit_rate, 1.0);
+        assert_eq!(report.popularity.hit_rate, 0.0);
+    }
+}
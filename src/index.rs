@@ -0,0 +1,281 @@
+//! ## Looking up indicators by item name
+//!
+//! An `IndicatorIndex` holds computed indicators together with the original string identifiers of
+//! the items, and answers which items are highly associated with a given item, as well as the
+//! reverse question of which items have a given item among their indicators. It can be built
+//! from freshly computed indicators, or be loaded from the output file of **recoreco** via
+//! `recoreco::io::read_indicators`, so that services can embed it without recomputing the
+//! indicators.
+//!
+/*
+ * RecoReco
+ * Copyright (C) 2018 Sebastian Schelter
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use fnv::FnvHashMap;
+
+use error::Error;
+use recommend::Recommender;
+use stats::Renaming;
+use types::{IndicatorMatrix, ScoredItem};
+
+/// Indicators which can be looked up by the string identifiers of the items.
+///
+/// # Examples
+///
+/// ```
+/// extern crate recoreco;
+/// use recoreco::index::IndicatorIndex;
+/// use recoreco::stats::{DataDictionary, Renaming};
+/// use recoreco::{scored_indicators, DEFAULT_SEED};
+///
+/// let interactions = vec![
+///     (String::from("alice"), String::from("apple")),
+///     (String::from("alice"), String::from("pony")),
+///     (String::from("bob"), String::from("apple")),
+///     (String::from("bob"), String::from("pony")),
+///     (String::from("charles"), String::from("bike")),
+/// ];
+///
+/// let data_dict = DataDictionary::try_from(interactions.iter()).unwrap();
+/// let indicators = scored_indicators(
+///     interactions.into_iter(),
+///     &data_dict,
+///     10,
+///     500,
+///     500,
+///     DEFAULT_SEED
+/// ).unwrap();
+///
+/// let renaming: Renaming = data_dict.into();
+/// let index = IndicatorIndex::new(&indicators, &renaming).unwrap();
+///
+/// let (item, _score) = index.indicators_for("apple").unwrap()[0];
+/// assert_eq!(item, "pony");
+///
+/// assert!(index.indicated_by("pony").unwrap().iter().any(|(item, _)| *item == "apple"));
+/// assert!(index.indicators_for("unicorn").is_none());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IndicatorIndex {
+    item_names: Vec<String>,
+    item_indexes: FnvHashMap<String, u32>,
+    indicators: IndicatorMatrix,
+    indicated_by: IndicatorMatrix,
+}
+
+impl IndicatorIndex {
+
+    /// Builds an index for the `indicators`, `renaming` maps their internal item indexes back to
+    /// the original string identifiers. Fails with `Error::UnknownItem` if `renaming` has no
+    /// identifier for one of the items.
+    pub fn new(indicators: &IndicatorMatrix, renaming: &Renaming) -> Result<Self, Error> {
+        let item_names = (0..indicators.num_items() as u32)
+            .map(|item| {
+                renaming
+                    .item_name(item)
+                    .map(str::to_owned)
+                    .ok_or_else(|| Error::UnknownItem(format!("#{}", item)))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(IndicatorIndex::from_parts(item_names, indicators.clone()))
+    }
+
+    /// Builds an index from the names of the items and their indicators, which refer to the items
+    /// by their position in `item_names`
+    pub(crate) fn from_parts(item_names: Vec<String>, indicators: IndicatorMatrix) -> Self {
+
+        let item_indexes = item_names
+            .iter()
+            .enumerate()
+            .map(|(item, name)| (name.clone(), item as u32))
+            .collect();
+
+        let mut reverse_rows: Vec<Vec<ScoredItem>> = vec![Vec::new(); item_names.len()];
+        for (item, indicators_for_item) in indicators.iter() {
+            for scored_item in indicators_for_item {
+                reverse_rows[scored_item.item as usize]
+                    .push(ScoredItem { item, score: scored_item.score });
+            }
+        }
+
+        let mut indicated_by = IndicatorMatrix::new(item_names.len());
+        for (item, mut row) in reverse_rows.into_iter().enumerate() {
+            // Due to the reversed ordering of ScoredItem, this gives us descending scores
+            row.sort();
+            indicated_by.set_indicators_for(item as u32, row);
+        }
+
+        IndicatorIndex { item_names, item_indexes, indicators, indicated_by }
+    }
+
+    /// Returns the number of items covered by the index
+    pub fn num_items(&self) -> usize {
+        self.item_names.len()
+    }
+
+    /// Returns the underlying indicators, indexed by the internal item index
+    pub fn indicators(&self) -> &IndicatorMatrix {
+        &self.indicators
+    }
+
+    /// Returns the internal index for the item with the string identifier `name`, if it is known
+    pub fn item_index(&self, name: &str) -> Option<u32> {
+        self.item_indexes.get(name).cloned()
+    }
+
+    /// Returns the string identifier of the item with the internal index `item`, or `None` if the
+    /// index is out of range
+    pub fn item_name(&self, item: u32) -> Option<&str> {
+        self.item_names.get(item as usize).map(String::as_str)
+    }
+
+    /// Returns the indicators of the item `name` together with their scores, ranked by descending
+    /// score, or `None` if the item is unknown.
+    pub fn indicators_for(&self, name: &str) -> Option<Vec<(&str, f64)>> {
+        self.item_index(name)
+            .map(|item| self.named(self.indicators.indicators_for(item)))
+    }
+
+    /// Returns the items which have the item `name` among their indicators, together with the
+    /// score of the item in their indicators, ranked by descending score. Returns `None` if the
+    /// item is unknown.
+    pub fn indicated_by(&self, name: &str) -> Option<Vec<(&str, f64)>> {
+        self.item_index(name)
+            .map(|item| self.named(self.indicated_by.indicators_for(item)))
+    }
+
+    /// Returns the top-`n` items for a user who interacted with the items in `history`, together
+    /// with their aggregated scores, see `recoreco::recommend::Recommender`. Unknown items in the
+    /// history are ignored.
+    pub fn recommend<'a, T>(&self, history: T, n: usize) -> Vec<(&str, f64)>
+    where
+        T: Iterator<Item = &'a str>
+    {
+        let history: Vec<u32> = history
+            .filter_map(|name| self.item_index(name))
+            .collect();
+
+        self.named(&Recommender::new(&self.indicators).recommend(&history, n))
+    }
+
+    fn named(&self, scored_items: &[ScoredItem]) -> Vec<(&str, f64)> {
+        scored_items
+            .iter()
+            .filter_map(|scored_item| {
+                self.item_name(scored_item.item).map(|name| (name, scored_item.score))
+            })
+            .collect()
+    }
+}
+
+/// Indicators for three artists, which are shared by the tests of the index and the server
+#[cfg(test)]
+pub(crate) fn example() -> (IndicatorMatrix, Renaming) {
+
+    use stats::DataDictionary;
+
+    let interactions = [
+        (String::from("user_a"), String::from("michael jackson")),
+        (String::from("user_a"), String::from("queen")),
+        (String::from("user_a"), String::from("justin timberlake")),
+    ];
+    let renaming: Renaming = DataDictionary::from(interactions.iter()).into();
+
+    let mut indicators = IndicatorMatrix::new(3);
+    indicators.set_indicators_for(0, vec![
+        ScoredItem { item: 2, score: 1523.4 },
+        ScoredItem { item: 1, score: 987.1 },
+    ]);
+    indicators.set_indicators_for(2, vec![ScoredItem { item: 1, score: 1200.0 }]);
+
+    (indicators, renaming)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use error::Error;
+    use index::{example, IndicatorIndex};
+    use io::{read_indicators, write_indicators};
+    use types::IndicatorMatrix;
+
+    #[test]
+    fn lookup_by_item_name() {
+
+        let (indicators, renaming) = example();
+        let index = IndicatorIndex::new(&indicators, &renaming).unwrap();
+
+        assert_eq!(
+            index.indicators_for("michael jackson"),
+            Some(vec![("justin timberlake", 1523.4), ("queen", 987.1)]),
+        );
+        assert_eq!(index.indicators_for("queen"), Some(vec![]));
+        assert_eq!(
+            index.indicated_by("queen"),
+            Some(vec![("justin timberlake", 1200.0), ("michael jackson", 987.1)]),
+        );
+        assert_eq!(index.indicated_by("abba"), None);
+
+        assert_eq!(index.item_name(1), Some("queen"));
+        assert_eq!(index.item_name(3), None);
+
+        assert_eq!(
+            index.recommend(vec!["michael jackson", "abba"].into_iter(), 10),
+            vec![("justin timberlake", 1523.4), ("queen", 987.1)],
+        );
+
+        let unnamed_items = IndicatorMatrix::new(4);
+        assert!(IndicatorIndex::new(&unnamed_items, &renaming).is_err());
+    }
+
+    #[test]
+    fn load_from_output_file() {
+
+        let path = env::temp_dir().join(format!("recoreco-index-test-{}.json", process::id()));
+        let path = path.to_str().unwrap();
+
+        let (indicators, renaming) = example();
+        write_indicators(&indicators, &renaming, Some(String::from(path))).unwrap();
+
+        let loaded = read_indicators(path).unwrap();
+        let index = IndicatorIndex::new(&indicators, &renaming).unwrap();
+
+        for name in &["michael jackson", "queen", "justin timberlake"] {
+            assert_eq!(loaded.indicators_for(name), index.indicators_for(name));
+            assert_eq!(loaded.indicated_by(name), index.indicated_by(name));
+        }
+
+        fs::write(path, "{\"for_item\":\"queen\"}\n").unwrap();
+        let error = read_indicators(path).err().unwrap();
+        assert!(error.to_string().contains("Line 1"));
+
+        let repeated = "{\"for_item\":\"queen\",\"indicated_items\":[],\"scores\":[]}\n";
+        fs::write(path, repeated.repeat(2)).unwrap();
+        match read_indicators(path) {
+            Err(Error::Parse(problem)) => assert!(problem.contains("Line 2"), "{}", problem),
+            other => panic!("Expected a parse error, got {:?}", other),
+        }
+
+        fs::remove_file(path).unwrap();
+    }
+}
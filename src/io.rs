@@ -2,9 +2,11 @@
 //!
 //! This module provides a few convenience functions to consume interaction data stored in CSV
 //! files, which by our experience is the most common data format for item interactions. Note that
-//! these implementations do not assume that they can hold the whole CSV in memory.
+//! these implementations do not assume that they can hold the whole CSV in memory. Input which is
+//! compressed with gzip or zstd is transparently decompressed while reading, if **recoreco** is
+//! built with the `compression` feature.
 //!
-/**
+/*
  * RecoReco
  * Copyright (C) 2018 Sebastian Schelter
  *
@@ -26,18 +28,31 @@ extern crate csv;
 extern crate serde;
 extern crate fnv;
 extern crate serde_json;
+#[cfg(feature = "compression")]
+extern crate flate2;
+#[cfg(feature = "compression")]
+extern crate zstd;
 
 use std;
+use std::cell::RefCell;
+use std::fmt;
 use std::io;
+use std::iter;
+use std::str;
+use std::str::FromStr;
 use std::io::prelude::*;
-use std::io::BufWriter;
+use std::io::{BufRead, BufReader, BufWriter};
 use std::io::stdout;
 use std::fs::File;
 use std::path::Path;
+use std::rc::Rc;
 
-use fnv::FnvHashSet;
+use fnv::{FnvHashMap, FnvHashSet};
 
+use error::Error;
+use index::IndicatorIndex;
 use stats::Renaming;
+use types::{IndicatorMatrix, ScoredItem};
 
 /// Reads a CSV input file. We expect **NO headers**, and a **user-item pair per line**
 /// with **tab separation**, which denotes an interaction between a user and this item, e.g.,
@@ -51,21 +66,666 @@ use stats::Renaming;
 /// charles&#9;pony
 /// charles&#9;bike
 /// </pre>
-pub fn csv_reader(file: &str) -> Result<csv::Reader<std::fs::File>, csv::Error> {
-    let reader = csv::ReaderBuilder::new()
-        .has_headers(false)
-        .delimiter(b'\t')
-        .from_path(file)?;
+///
+/// Files compressed with gzip or zstd are decompressed on the fly, see `open_input`.
+pub fn csv_reader(file: &str) -> Result<csv::Reader<Box<dyn Read>>, csv::Error> {
+    let reader = reader_builder().from_reader(open_input(file)?);
 
     Ok(reader)
 }
 
+/// Reads CSV input in the same format as `csv_reader` from stdin. Note that the input can only be
+/// consumed once, e.g., via `recoreco::single_pass_indicators`.
+pub fn stdin_csv_reader() -> csv::Reader<io::Stdin> {
+    reader_builder().from_reader(io::stdin())
+}
+
+/// Magic bytes at the start of gzip compressed data
+const GZIP_MAGIC_BYTES: [u8; 2] = [0x1f, 0x8b];
+
+/// Magic bytes at the start of a zstd frame
+const ZSTD_MAGIC_BYTES: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Formats of compressed input which we recognize
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Compression {
+    Gzip,
+    Zstd,
+}
+
+/// Opens the file at `path` for reading. Files with a `.gz` or `.zst` extension, or which start
+/// with the magic bytes of gzip or zstd, are decompressed on the fly, so that large interaction
+/// logs never need to be decompressed to disk. Compressed files result in an error if
+/// **recoreco** was built without the `compression` feature.
+pub fn open_input(path: &str) -> io::Result<Box<dyn Read>> {
+
+    let input = BufReader::new(File::open(Path::new(path))?);
+
+    if path.ends_with(".gz") {
+        decoder(input, Compression::Gzip)
+    } else if path.ends_with(".zst") {
+        decoder(input, Compression::Zstd)
+    } else {
+        decompressed(input)
+    }
+}
+
+/// Decompresses `input` on the fly if it starts with the magic bytes of gzip or zstd, e.g., for
+/// compressed data from stdin, and passes it through unchanged otherwise. Compressed input results
+/// in an error if **recoreco** was built without the `compression` feature.
+pub fn decompressed<R: Read + 'static>(input: R) -> io::Result<Box<dyn Read>> {
+
+    let mut input = BufReader::new(input);
+    let head = input.fill_buf()?;
+
+    if head.starts_with(&GZIP_MAGIC_BYTES) {
+        decoder(input, Compression::Gzip)
+    } else if head.starts_with(&ZSTD_MAGIC_BYTES) {
+        decoder(input, Compression::Zstd)
+    } else {
+        Ok(Box::new(input))
+    }
+}
+
+#[cfg(feature = "compression")]
+fn decoder<R: BufRead + 'static>(input: R, compression: Compression) -> io::Result<Box<dyn Read>> {
+    match compression {
+        Compression::Gzip => Ok(Box::new(flate2::bufread::MultiGzDecoder::new(input))),
+        Compression::Zstd => Ok(Box::new(zstd::Decoder::with_buffer(input)?)),
+    }
+}
+
+#[cfg(not(feature = "compression"))]
+fn decoder<R: BufRead + 'static>(_input: R, compression: Compression) -> io::Result<Box<dyn Read>> {
+
+    let format = match compression {
+        Compression::Gzip => "gzip",
+        Compression::Zstd => "zstd",
+    };
+
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "Input is compressed with {}, but recoreco was built without the 'compression' \
+            feature",
+            format,
+        ),
+    ))
+}
+
+fn reader_builder() -> csv::ReaderBuilder {
+    let mut builder = csv::ReaderBuilder::new();
+    builder
+        .has_headers(false)
+        .delimiter(b'\t');
+
+    builder
+}
+
+/// A column of the input, either identified by its zero-based position or by its name in the
+/// header line
+#[derive(Debug, Clone, PartialEq)]
+pub enum Column {
+    Index(usize),
+    Name(String),
+}
+
+/// Configures how to read interactions from CSV input which does not follow the default format of
+/// `csv_reader`, e.g., exports with a header line, another delimiter or additional columns.
+///
+/// # Examples
+///
+/// ```
+/// extern crate recoreco;
+/// use recoreco::io::{Column, InteractionReaderBuilder};
+///
+/// let export = "artist,plays,user\nqueen,12,alice\n\"ac,dc\",3,bob\n";
+///
+/// let mut reader = InteractionReaderBuilder::new()
+///     .delimiter(b',')
+///     .has_headers(true)
+///     .user_column(Column::Name(String::from("user")))
+///     .item_column(Column::Index(0))
+///     .weight_column(Column::Name(String::from("plays")))
+///     .from_reader(export.as_bytes())
+///     .unwrap();
+///
+/// let interactions: Vec<_> = reader.weighted_interactions().collect();
+///
+/// assert_eq!(interactions[0], (String::from("alice"), String::from("queen"), 12.0));
+/// assert_eq!(interactions[1], (String::from("bob"), String::from("ac,dc"), 3.0));
+/// ```
+#[derive(Debug, Clone)]
+pub struct InteractionReaderBuilder {
+    delimiter: u8,
+    has_headers: bool,
+    quoting: bool,
+    quote: u8,
+    user_column: Column,
+    item_column: Column,
+    weight_column: Column,
+    timestamp_column: Option<Column>,
+    action_column: Option<Column>,
+    malformed_lines: MalformedLines,
+}
+
+impl Default for InteractionReaderBuilder {
+    fn default() -> Self {
+        InteractionReaderBuilder {
+            delimiter: b'\t',
+            has_headers: false,
+            quoting: true,
+            quote: b'"',
+            user_column: Column::Index(0),
+            item_column: Column::Index(1),
+            weight_column: Column::Index(2),
+            timestamp_column: None,
+            action_column: None,
+            malformed_lines: MalformedLines::Lenient,
+        }
+    }
+}
+
+impl InteractionReaderBuilder {
+
+    /// Creates a builder for the default format of `csv_reader`: tab separated, without a header
+    /// line, with the user in the first, the item in the second and an optional weight in the
+    /// third column.
+    pub fn new() -> Self {
+        InteractionReaderBuilder::default()
+    }
+
+    /// The byte which separates the columns, a tab by default
+    pub fn delimiter(&mut self, delimiter: u8) -> &mut Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Whether the first line holds the names of the columns, which is not the case by default
+    pub fn has_headers(&mut self, has_headers: bool) -> &mut Self {
+        self.has_headers = has_headers;
+        self
+    }
+
+    /// Whether quotes mark fields which may contain the delimiter, enabled by default
+    pub fn quoting(&mut self, quoting: bool) -> &mut Self {
+        self.quoting = quoting;
+        self
+    }
+
+    /// The quote character, `"` by default
+    pub fn quote(&mut self, quote: u8) -> &mut Self {
+        self.quote = quote;
+        self
+    }
+
+    /// The column which holds the user, the first column by default
+    pub fn user_column(&mut self, column: Column) -> &mut Self {
+        self.user_column = column;
+        self
+    }
+
+    /// The column which holds the item, the second column by default
+    pub fn item_column(&mut self, column: Column) -> &mut Self {
+        self.item_column = column;
+        self
+    }
+
+    /// The column which holds the weight of an interaction, the third column by default.
+    /// Interactions without a value in this column have a weight of one.
+    pub fn weight_column(&mut self, column: Column) -> &mut Self {
+        self.weight_column = column;
+        self
+    }
+
+    /// The column which holds the numeric timestamp of an interaction, e.g., seconds since the
+    /// epoch. There is no timestamp column by default.
+    pub fn timestamp_column(&mut self, column: Column) -> &mut Self {
+        self.timestamp_column = Some(column);
+        self
+    }
+
+    /// The column which holds the type of an interaction, e.g., view or purchase, for reading two
+    /// types of interactions from the same input. There is no action column by default.
+    pub fn action_column(&mut self, column: Column) -> &mut Self {
+        self.action_column = Some(column);
+        self
+    }
+
+    /// How to handle lines which do not hold a valid interaction, lenient by default
+    pub fn malformed_lines(&mut self, malformed_lines: MalformedLines) -> &mut Self {
+        self.malformed_lines = malformed_lines;
+        self
+    }
+
+    /// Reads interactions from the file at `path`, which may be compressed, see `open_input`.
+    /// Fails with `Error::Config` if a column is referred to by a name which does not occur in the
+    /// header line.
+    pub fn from_path(&self, path: &str) -> Result<InteractionReader<Box<dyn Read>>, Error> {
+        self.build(open_input(path)?)
+    }
+
+    /// Reads interactions from `input`, e.g., stdin. Fails with `Error::Config` if a column is
+    /// referred to by a name which does not occur in the header line.
+    pub fn from_reader<R: Read>(&self, input: R) -> Result<InteractionReader<R>, Error> {
+        self.build(input)
+    }
+
+    fn csv_builder(&self) -> csv::ReaderBuilder {
+        let mut builder = csv::ReaderBuilder::new();
+        builder
+            .has_headers(self.has_headers)
+            .delimiter(self.delimiter)
+            .quoting(self.quoting)
+            .quote(self.quote)
+            .flexible(true);
+
+        builder
+    }
+
+    fn build<R: Read>(&self, input: R) -> Result<InteractionReader<R>, Error> {
+
+        let raw_input = Rc::new(RefCell::new(RawInput::default()));
+        let recorded_input = RecordedInput { input, raw_input: Rc::clone(&raw_input) };
+        let mut reader = self.csv_builder().from_reader(recorded_input);
+
+        let headers = if self.has_headers { Some(reader.headers()?.clone()) } else { None };
+
+        let position = |column: &Column| -> Result<usize, Error> {
+            let problem = match (column, &headers) {
+                (Column::Index(index), _) => return Ok(*index),
+                (Column::Name(name), Some(headers)) => {
+                    if let Some(index) = headers.iter().position(|header| header.trim() == name) {
+                        return Ok(index);
+                    }
+                    format!("No column named '{}' in the header line", name)
+                },
+                (Column::Name(name), None) => format!("Column '{}' requires a header line", name),
+            };
+            Err(Error::Config(problem))
+        };
+
+        Ok(InteractionReader {
+            user_column: position(&self.user_column)?,
+            item_column: position(&self.item_column)?,
+            weight_column: position(&self.weight_column)?,
+            timestamp_column: match self.timestamp_column {
+                Some(ref column) => Some(position(column)?),
+                None => None,
+            },
+            action_column: match self.action_column {
+                Some(ref column) => Some(position(column)?),
+                None => None,
+            },
+            malformed_lines: self.malformed_lines,
+            report: InputReport::default(),
+            failure: None,
+            rejects: None,
+            reader,
+            raw_input,
+        })
+    }
+}
+
+/// Reads interactions from CSV input in the format configured via `InteractionReaderBuilder`.
+/// Lines which lack the user or item column, or hold an invalid weight or timestamp, are malformed
+/// and handled according to `MalformedLines`.
+pub struct InteractionReader<R> {
+    reader: csv::Reader<RecordedInput<R>>,
+    raw_input: Rc<RefCell<RawInput>>,
+    user_column: usize,
+    item_column: usize,
+    weight_column: usize,
+    timestamp_column: Option<usize>,
+    action_column: Option<usize>,
+    malformed_lines: MalformedLines,
+    report: InputReport,
+    failure: Option<Error>,
+    rejects: Option<Box<dyn Write>>,
+}
+
+impl<R: Read> InteractionReader<R> {
+
+    /// Writes every skipped malformed line to `rejects` exactly as it occurs in the input, e.g., to
+    /// a file which can be inspected and fixed later
+    pub fn write_rejects_to<W: Write + 'static>(&mut self, rejects: W) {
+        self.rejects = Some(Box::new(BufWriter::new(rejects)));
+    }
+
+    /// Returns how many lines were read and rejected so far
+    pub fn report(&self) -> &InputReport {
+        &self.report
+    }
+
+    /// Checks whether reading stopped early, returns the number of read and rejected lines
+    /// otherwise. Reading stops at the first malformed line in strict mode, or when the input or
+    /// the rejects cannot be read or written. Fails with `Error::Parse` for a malformed line,
+    /// which names its line number and content.
+    pub fn finish(&mut self) -> Result<&InputReport, Error> {
+        if let Some(failure) = self.failure.take() {
+            return Err(failure);
+        }
+        if let Some(ref mut rejects) = self.rejects {
+            rejects.flush()?;
+        }
+        Ok(&self.report)
+    }
+
+    /// Returns an iterator over the user-item pairs of the interactions, which can be used to
+    /// construct a `recoreco::stats::DataDictionary` or to compute indicators via
+    /// `recoreco::indicators`
+    pub fn interactions<'a>(&'a mut self) -> impl Iterator<Item=(String, String)> + 'a {
+        let (user_column, item_column) = (self.user_column, self.item_column);
+
+        self.parsed(move |record, _| {
+            Ok((column(record, user_column)?, column(record, item_column)?))
+        })
+    }
+
+    /// Returns an iterator over the interactions together with their weights. Interactions
+    /// without a value in the weight column have a weight of one.
+    pub fn weighted_interactions<'a>(
+        &'a mut self
+    ) -> impl Iterator<Item=(String, String, f64)> + 'a {
+        let (user_column, item_column) = (self.user_column, self.item_column);
+        let weight_column = self.weight_column;
+
+        self.parsed(move |record, _| {
+            let weight = match record.get(weight_column) {
+                Some(_) => number(text(record, weight_column)?, "weight")?,
+                None => 1.0,
+            };
+            Ok((column(record, user_column)?, column(record, item_column)?, weight))
+        })
+    }
+
+    /// Returns an iterator over the interactions together with their timestamps. Without a
+    /// timestamp column, the position of an interaction in the input serves as its timestamp.
+    pub fn timestamped_interactions<'a>(
+        &'a mut self
+    ) -> impl Iterator<Item=(String, String, f64)> + 'a {
+        let (user_column, item_column) = (self.user_column, self.item_column);
+        let timestamp_column = self.timestamp_column;
+
+        self.parsed(move |record, position| {
+            let timestamp = match timestamp_column {
+                Some(timestamp_column) => {
+                    number(text(record, timestamp_column)?, "timestamp")?
+                },
+                None => position as f64,
+            };
+            Ok((column(record, user_column)?, column(record, item_column)?, timestamp))
+        })
+    }
+
+    /// Returns an iterator over the interactions together with their types from the action
+    /// column. Without an action column, every line is malformed.
+    pub fn typed_interactions<'a>(
+        &'a mut self
+    ) -> impl Iterator<Item=(String, String, String)> + 'a {
+        let (user_column, item_column) = (self.user_column, self.item_column);
+        let action_column = self.action_column;
+
+        self.parsed(move |record, _| {
+            let action = match action_column {
+                Some(action_column) => column(record, action_column)?,
+                None => return Err(String::from("no action column configured")),
+            };
+            Ok((column(record, user_column)?, column(record, item_column)?, action))
+        })
+    }
+
+    /// Applies `parse` to every record, and handles the records which cannot be parsed according
+    /// to the policy for malformed lines
+    fn parsed<'a, T, F>(&'a mut self, parse: F) -> impl Iterator<Item=T> + 'a
+    where
+        T: 'a,
+        F: Fn(&csv::ByteRecord, u64) -> Result<T, String> + 'a
+    {
+        let malformed_lines = self.malformed_lines;
+
+        let InteractionReader {
+            ref mut reader,
+            ref raw_input,
+            ref mut report,
+            ref mut failure,
+            ref mut rejects,
+            ..
+        } = *self;
+
+        let mut byte_record = csv::ByteRecord::new();
+
+        iter::from_fn(move || {
+            while failure.is_none() {
+
+                match reader.read_byte_record(&mut byte_record) {
+                    Ok(true) => {},
+                    Ok(false) => return None,
+                    Err(error) => {
+                        *failure = Some(error.into());
+                        return None;
+                    },
+                }
+
+                report.num_lines += 1;
+
+                // The record spans the input from its own position to the position of the next one
+                let end = reader.position().byte();
+                let start = byte_record.position().map_or(end, |position| position.byte());
+
+                let problem = match parse(&byte_record, report.num_lines) {
+                    Ok(parsed) => {
+                        raw_input.borrow_mut().consumed = end;
+                        return Some(parsed);
+                    },
+                    Err(problem) => problem,
+                };
+
+                let (line, raw_line) = raw_input.borrow_mut().take_line(start, end);
+
+                let content = String::from_utf8_lossy(&raw_line).into_owned();
+                let malformed_line = MalformedLine { line, content, problem };
+                report.num_rejected += 1;
+
+                if malformed_lines == MalformedLines::Strict {
+                    let message = format!("Malformed {}", malformed_line);
+                    *failure = Some(Error::Parse(message));
+                    return None;
+                }
+
+                if let Some(ref mut rejects) = *rejects {
+                    let written = rejects
+                        .write_all(&raw_line)
+                        .and_then(|_| rejects.write_all(b"\n"));
+                    if let Err(error) = written {
+                        *failure = Some(error.into());
+                        return None;
+                    }
+                }
+
+                if report.samples.len() < NUM_MALFORMED_SAMPLES {
+                    report.samples.push(malformed_line);
+                }
+            }
+
+            None
+        })
+    }
+}
+
+/// Passes the input through to the CSV reader, and records the chunks which the reader reads, so
+/// that we can recover the raw content of a malformed line
+struct RecordedInput<R> {
+    input: R,
+    raw_input: Rc<RefCell<RawInput>>,
+}
+
+impl<R: Read> Read for RecordedInput<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let num_bytes = self.input.read(buf)?;
+        self.raw_input.borrow_mut().record(&buf[..num_bytes]);
+        Ok(num_bytes)
+    }
+}
+
+/// The bytes of the input from `offset` on, which the CSV reader has read, in a buffer which is
+/// reused for every chunk. The records before `consumed` are parsed, and their bytes are only
+/// forgotten when the next chunk is read, so that parsing a valid record costs no copies. This
+/// holds at most a chunk and the record which is currently parsed. We count the line breaks of the
+/// forgotten bytes ourselves, as the CSV reader miscounts lines which end with `\r\n`.
+#[derive(Default)]
+struct RawInput {
+    bytes: Vec<u8>,
+    offset: u64,
+    consumed: u64,
+    num_line_breaks: u64,
+}
+
+impl RawInput {
+
+    /// Forgets the bytes of the parsed records, and appends the next `chunk` of the input
+    fn record(&mut self, chunk: &[u8]) {
+        let num_consumed = self.index(self.consumed);
+        if num_consumed > 0 {
+            self.num_line_breaks += line_breaks(&self.bytes[..num_consumed]);
+            self.bytes.drain(..num_consumed);
+            self.offset += num_consumed as u64;
+        }
+        self.bytes.extend_from_slice(chunk);
+    }
+
+    /// Returns the number of the line which starts at `start`, and the bytes of the input between
+    /// `start` and `end` without line terminators. The CSV reader ends a record at the `\r` of a
+    /// `\r\n`, so that the next record starts at the `\n`. Marks the input before `end` as
+    /// parsed.
+    fn take_line(&mut self, start: u64, end: u64) -> (u64, Vec<u8>) {
+        let mut from = self.index(start);
+        let to = self.index(end).max(from);
+
+        while from < to && (self.bytes[from] == b'\n' || self.bytes[from] == b'\r') {
+            from += 1;
+        }
+
+        let line = self.num_line_breaks + line_breaks(&self.bytes[..from]) + 1;
+        let mut content = self.bytes[from..to].to_vec();
+        while content.last().is_some_and(|byte| *byte == b'\n' || *byte == b'\r') {
+            content.pop();
+        }
+
+        self.consumed = end;
+        (line, content)
+    }
+
+    /// Index of the byte at `position` of the input in `bytes`
+    fn index(&self, position: u64) -> usize {
+        (position.saturating_sub(self.offset) as usize).min(self.bytes.len())
+    }
+}
+
+fn line_breaks(bytes: &[u8]) -> u64 {
+    bytes.iter().filter(|byte| **byte == b'\n').count() as u64
+}
+
+/// Number of malformed lines which we keep as samples in lenient mode
+const NUM_MALFORMED_SAMPLES: usize = 5;
+
+fn column(record: &csv::ByteRecord, index: usize) -> Result<String, String> {
+    text(record, index).map(str::to_owned)
+}
+
+/// The text in the column at `index`, we only check the columns which we read for valid UTF-8
+fn text(record: &csv::ByteRecord, index: usize) -> Result<&str, String> {
+    let bytes = record.get(index).ok_or_else(|| format!("no column {}", index + 1))?;
+    str::from_utf8(bytes).map_err(|_| format!("invalid UTF-8 in column {}", index + 1))
+}
+
+fn number(value: &str, name: &str) -> Result<f64, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("{} '{}' is not a number", name, value))
+}
+
+/// How to handle lines of the input which do not hold a valid interaction, e.g., because a column
+/// is missing or a weight is not a number
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MalformedLines {
+    /// Stop reading at the first malformed line, see `InteractionReader::finish`
+    Strict,
+    /// Skip malformed lines, but count them and keep a few of them as samples
+    #[default]
+    Lenient,
+}
+
+impl fmt::Display for MalformedLines {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MalformedLines::Strict => write!(f, "strict"),
+            MalformedLines::Lenient => write!(f, "lenient"),
+        }
+    }
+}
+
+impl FromStr for MalformedLines {
+    type Err = String;
+
+    /// Parses `strict` or `lenient`
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "strict" => Ok(MalformedLines::Strict),
+            "lenient" => Ok(MalformedLines::Lenient),
+            _ => Err(format!("Unknown policy '{}', expected strict or lenient", name)),
+        }
+    }
+}
+
+/// A line of the input which does not hold a valid interaction
+#[derive(Debug, Clone, PartialEq)]
+pub struct MalformedLine {
+    /// The number of the line in the input, starting at 1
+    pub line: u64,
+    /// The content of the line as it occurs in the input, without the line terminator. Invalid
+    /// UTF-8 is replaced by the replacement character.
+    pub content: String,
+    /// Why the line does not hold a valid interaction
+    pub problem: String,
+}
+
+impl fmt::Display for MalformedLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {} ({}): '{}'", self.line, self.problem, self.content)
+    }
+}
+
+/// How many lines of the input were read and rejected as malformed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InputReport {
+    pub num_lines: u64,
+    pub num_rejected: u64,
+    /// The first few rejected lines
+    pub samples: Vec<MalformedLine>,
+}
+
+impl InputReport {
+
+    /// Returns the fraction of the lines which were rejected, zero for empty input
+    pub fn rejected_fraction(&self) -> f64 {
+        if self.num_lines == 0 {
+            0.0
+        } else {
+            self.num_rejected as f64 / self.num_lines as f64
+        }
+    }
+}
+
 /// Converts a `csv::Reader` for an interaction file into an `Iterator<Item=(String, String)>` over
 /// the contained interactions.
 ///
 /// This iterator can be used to construct a `recoreco::stats::DataDictionary` via
-/// `recoreco::stats::DataDictionary::from` or to compute  highly associated paris of items
-/// via `recoreco::indicators`.
+/// `recoreco::stats::DataDictionary::try_from_owned` or to compute highly associated pairs of
+/// items via `recoreco::indicators`. Malformed lines are skipped silently, use
+/// `InteractionReader::interactions` to count them or to reject the input instead.
+#[deprecated(note = "Skips malformed lines silently, use InteractionReader::interactions")]
 pub fn interactions_from_csv<'a, R>(
     reader: &'a mut csv::Reader<R>
 ) -> impl Iterator<Item=(String, String)> + 'a
@@ -73,9 +733,7 @@ pub fn interactions_from_csv<'a, R>(
 
     reader.deserialize()
         .filter_map(|result| {
-            if result.is_ok() {
-                // TODO handle potential errors here?
-                let (user, item): (String, String) = result.unwrap();
+            if let Ok((user, item)) = result {
                 Some((user, item))
             } else {
                 None
@@ -83,56 +741,505 @@ pub fn interactions_from_csv<'a, R>(
         })
 }
 
+/// Converts a `csv::Reader` for an interaction file with weights into an
+/// `Iterator<Item=(String, String, f64)>` over the contained interactions. We expect the weight of
+/// an interaction, e.g., a rating or a play count, in the third column, and assign a weight of one
+/// to interactions without a weight.
+///
+/// This iterator can be used to construct a `recoreco::stats::DataDictionary` via
+/// `recoreco::stats::DataDictionary::extend_weighted` or to compute highly associated pairs of
+/// items via `recoreco::weighted_indicators`. Malformed lines are skipped silently, use
+/// `InteractionReader::weighted_interactions` to count them or to reject the input instead.
+#[deprecated(note = "Skips malformed lines silently, use InteractionReader::weighted_interactions")]
+pub fn weighted_interactions_from_csv<'a, R>(
+    reader: &'a mut csv::Reader<R>
+) -> impl Iterator<Item=(String, String, f64)> + 'a
+    where R: std::io::Read {
+
+    reader.records()
+        .filter_map(|result| {
+            let record = result.ok()?;
+            let weight = match record.get(2) {
+                Some(weight) => weight.trim().parse().ok()?,
+                None => 1.0,
+            };
+            Some((record.get(0)?.to_owned(), record.get(1)?.to_owned(), weight))
+        })
+}
+
 /// Struct used for JSON serialization of computed indicators. Field names will be used in JSON.
 #[derive(Serialize)]
 struct Indicators<'a> {
     for_item: &'a str,
-    indicated_items: FnvHashSet<&'a str>,
+    indicated_items: Vec<&'a str>,
+    scores: Vec<f64>,
 }
 
 /// Output the computed indicators in JSON format, using the original identifiers from the
 /// inputfile. If an `indicators_path` is supplied, we write to a file at the specified path,
-/// otherwise, we output to stdout. Each line holds a JSON representation 
+/// otherwise, we output to stdout. We write a line for every item, which holds a JSON representation
+/// of its indicators. The indicated items are ranked by their association with the item, and
+/// `scores` holds the corresponding LLR scores
 ///
-/// `{ "for_item": "michael jackson", "indicated_items": ["justin timberlake", "queen"] }`
+/// `{ "for_item": "michael jackson", "indicated_items": ["justin timberlake", "queen"], "scores": [1523.4, 987.1] }`
 ///
+/// Fails with `Error::UnknownItem` if `renaming` has no identifier for an item.
 pub fn write_indicators(
-    indicators: &[FnvHashSet<u32>],
+    indicators: &IndicatorMatrix,
     renaming: &Renaming,
     indicators_path: Option<String>,
-) -> io::Result<()> {
+) -> Result<(), Error> {
 
     let mut out = match indicators_path {
-        Some(path) => boxed_writer(File::create(&Path::new(&path))?),
+        Some(path) => boxed_writer(File::create(Path::new(&path))?),
         _ => boxed_writer(stdout()),
     };
 
-    for (item_index, indicated_item_indices) in indicators.into_iter().enumerate() {
+    for (item_index, scored_items) in indicators.iter() {
 
-        let for_item = renaming.item_name(item_index as u32);
+        let for_item = item_name(renaming, item_index)?;
 
-        let indicated_items: FnvHashSet<&str> = indicated_item_indices
-            .into_iter()
-            .map(|item_index| renaming.item_name(*item_index as u32))
+        let indicated_items = scored_items
+            .iter()
+            .map(|scored_item| item_name(renaming, scored_item.item))
+            .collect::<Result<Vec<&str>, Error>>()?;
+
+        let scores: Vec<f64> = scored_items
+            .iter()
+            .map(|scored_item| scored_item.score)
             .collect();
 
         let indicators_as_json = json!(
             Indicators {
                 for_item,
-                indicated_items
+                indicated_items,
+                scores,
             });
 
-        writeln!(out, "{}", indicators_as_json.to_string())?;
+        writeln!(out, "{}", indicators_as_json)?;
     }
 
     Ok(())
 }
 
+/// Struct used for JSON deserialization of previously written indicators.
+#[derive(Deserialize)]
+struct OwnedIndicators {
+    for_item: String,
+    indicated_items: Vec<String>,
+    scores: Vec<f64>,
+}
+
+/// Reads indicators in the JSON format written by `write_indicators` from the file at
+/// `indicators_path`, and returns an index to look them up by item name. Fails with
+/// `Error::Parse` if a line does not hold indicators, or if the indicators of an item occur more
+/// than once.
+pub fn read_indicators(indicators_path: &str) -> Result<IndicatorIndex, Error> {
+
+    let input = BufReader::new(File::open(Path::new(indicators_path))?);
+
+    let mut item_names: Vec<String> = Vec::new();
+    let mut item_indexes: FnvHashMap<String, u32> = FnvHashMap::default();
+    let mut rows: Vec<(u32, Vec<ScoredItem>)> = Vec::new();
+    let mut items_with_indicators: FnvHashSet<u32> = FnvHashSet::default();
+
+    let mut index_of = |name: String| -> u32 {
+        let next_item_index = item_names.len() as u32;
+        *item_indexes.entry(name).or_insert_with_key(|name| {
+            item_names.push(name.clone());
+            next_item_index
+        })
+    };
+
+    for (line_index, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let invalid_line = |problem: String| {
+            Error::Parse(format!(
+                "Line {} of {} holds no indicators: {}",
+                line_index + 1,
+                indicators_path,
+                problem,
+            ))
+        };
+
+        let parsed: OwnedIndicators = serde_json::from_str(&line)
+            .map_err(|error| invalid_line(error.to_string()))?;
+
+        if parsed.indicated_items.len() != parsed.scores.len() {
+            return Err(invalid_line(String::from("different numbers of items and scores")));
+        }
+
+        let problem = format!("repeated indicators for item '{}'", parsed.for_item);
+        let item = index_of(parsed.for_item);
+        if !items_with_indicators.insert(item) {
+            return Err(invalid_line(problem));
+        }
+        let scored_items = parsed.indicated_items
+            .into_iter()
+            .zip(parsed.scores)
+            .map(|(name, score)| ScoredItem { item: index_of(name), score })
+            .collect();
+
+        rows.push((item, scored_items));
+    }
+
+    let mut indicators = IndicatorMatrix::new(item_names.len());
+    for (item, scored_items) in rows {
+        indicators.set_indicators_for(item, scored_items);
+    }
+
+    Ok(IndicatorIndex::from_parts(item_names, indicators))
+}
+
+/// Struct used for JSON serialization of recommendations. Field names will be used in JSON.
+#[derive(Serialize)]
+struct Recommendations<'a> {
+    for_user: &'a str,
+    recommended_items: Vec<&'a str>,
+    scores: Vec<f64>,
+}
+
+/// Output recommendations for users in JSON format, using the original identifiers from the
+/// inputfile. If a `recommendations_path` is supplied, we write to a file at the specified path,
+/// otherwise, we output to stdout. We write a line for every user, which holds the recommended
+/// items ranked by their aggregated scores, see `recoreco::recommend::Recommender`.
+///
+/// `{ "for_user": "alice", "recommended_items": ["queen", "justin timberlake"], "scores": [2510.5, 987.1] }`
+///
+/// Fails with `Error::UnknownUser` or `Error::UnknownItem` if `renaming` has no identifier for a
+/// user or an item.
+pub fn write_recommendations<T>(
+    recommendations: T,
+    renaming: &Renaming,
+    recommendations_path: Option<String>,
+) -> Result<(), Error>
+where
+    T: Iterator<Item = (u32, Vec<ScoredItem>)>
+{
+
+    let mut out = match recommendations_path {
+        Some(path) => boxed_writer(File::create(Path::new(&path))?),
+        _ => boxed_writer(stdout()),
+    };
+
+    for (user_index, scored_items) in recommendations {
+
+        let for_user = renaming
+            .user_name(user_index)
+            .ok_or_else(|| Error::UnknownUser(format!("#{}", user_index)))?;
+
+        let recommended_items = scored_items
+            .iter()
+            .map(|scored_item| item_name(renaming, scored_item.item))
+            .collect::<Result<Vec<&str>, Error>>()?;
+
+        let scores: Vec<f64> = scored_items
+            .iter()
+            .map(|scored_item| scored_item.score)
+            .collect();
+
+        let recommendations_as_json = json!(
+            Recommendations {
+                for_user,
+                recommended_items,
+                scores,
+            });
+
+        writeln!(out, "{}", recommendations_as_json)?;
+    }
+
+    Ok(())
+}
+
+/// Unknown internal indexes are reported as `#index`, there is no string identifier to name them
+fn item_name(renaming: &Renaming, item_index: u32) -> Result<&str, Error> {
+    renaming
+        .item_name(item_index)
+        .ok_or_else(|| Error::UnknownItem(format!("#{}", item_index)))
+}
+
 fn boxed_writer<T>(
     destination: T
-) -> Box<Write>
+) -> Box<dyn Write>
 where
     T: Write + 'static
 {
     Box::new(BufWriter::new(destination))
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::env;
+    use std::fs;
+    use std::io::Cursor;
+    use std::process;
+
+    #[cfg(feature = "compression")]
+    use super::flate2;
+    #[cfg(feature = "compression")]
+    use super::zstd;
+
+    use io::{decompressed, Column, InteractionReaderBuilder};
+    use io::{MalformedLine, MalformedLines};
+
+    #[test]
+    fn read_configured_columns() {
+
+        let export = "1700000300;bob;'ac;dc'\n1700000100;alice;queen\nbroken\n\
+            1700000200;alice;'the ''who'''\n";
+
+        let mut builder = InteractionReaderBuilder::new();
+        builder
+            .delimiter(b';')
+            .quote(b'\'')
+            .user_column(Column::Index(1))
+            .item_column(Column::Index(2))
+            .timestamp_column(Column::Index(0));
+
+        let interactions: Vec<_> = builder
+            .from_reader(export.as_bytes())
+            .unwrap()
+            .timestamped_interactions()
+            .collect();
+
+        assert_eq!(interactions, vec![
+            (String::from("bob"), String::from("ac;dc"), 1_700_000_300.0),
+            (String::from("alice"), String::from("queen"), 1_700_000_100.0),
+            (String::from("alice"), String::from("the 'who'"), 1_700_000_200.0),
+        ]);
+
+        builder.quoting(false);
+        let interactions: Vec<_> = builder
+            .from_reader(export.as_bytes())
+            .unwrap()
+            .interactions()
+            .collect();
+
+        assert_eq!(interactions[0], (String::from("bob"), String::from("'ac")));
+    }
+
+    #[test]
+    fn read_action_column() {
+
+        let log = "user,action,item\nalice,view,apple\nalice,purchase,pony\nbob,apple\n";
+
+        let mut builder = InteractionReaderBuilder::new();
+        builder
+            .delimiter(b',')
+            .has_headers(true)
+            .item_column(Column::Name(String::from("item")))
+            .action_column(Column::Name(String::from("action")));
+
+        let mut reader = builder.from_reader(log.as_bytes()).unwrap();
+        let interactions: Vec<_> = reader.typed_interactions().collect();
+
+        assert_eq!(interactions, vec![
+            (String::from("alice"), String::from("apple"), String::from("view")),
+            (String::from("alice"), String::from("pony"), String::from("purchase")),
+        ]);
+        assert_eq!(reader.finish().unwrap().num_rejected, 1);
+    }
+
+    #[test]
+    fn resolve_columns_by_header_name() {
+
+        let export = "user,item\nalice,queen\n";
+
+        let interactions: Vec<_> = InteractionReaderBuilder::new()
+            .delimiter(b',')
+            .has_headers(true)
+            .user_column(Column::Name(String::from("user")))
+            .item_column(Column::Name(String::from("item")))
+            .from_reader(export.as_bytes())
+            .unwrap()
+            .weighted_interactions()
+            .collect();
+
+        assert_eq!(interactions, vec![(String::from("alice"), String::from("queen"), 1.0)]);
+
+        let unknown_column = InteractionReaderBuilder::new()
+            .has_headers(true)
+            .item_column(Column::Name(String::from("artist")))
+            .from_reader(export.as_bytes());
+        assert!(unknown_column.is_err());
+
+        let without_headers = InteractionReaderBuilder::new()
+            .item_column(Column::Name(String::from("item")))
+            .from_reader(export.as_bytes());
+        assert!(without_headers.is_err());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn decompress_gzip_and_zstd() {
+
+        use std::io::Write;
+
+        let interactions = "alice\tapple\nalice\tdog\nbob\tapple\n";
+
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(interactions.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+
+        let zstd = zstd::encode_all(interactions.as_bytes(), 0).unwrap();
+
+        let directory = env::temp_dir().join(format!("recoreco-io-test-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+
+        // Compression is detected by the extension or by the magic bytes at the start of the file
+        for (name, content) in [
+            ("recoreco-io-test.tsv", interactions.as_bytes()),
+            ("recoreco-io-test.tsv.gz", &gzip[..]),
+            ("recoreco-io-test.tsv.zst", &zstd[..]),
+            ("recoreco-io-test-gzip.tsv", &gzip[..]),
+            ("recoreco-io-test-zstd.tsv", &zstd[..]),
+        ] {
+            let path = directory.join(name);
+            let path = path.to_str().unwrap();
+            fs::write(path, content).unwrap();
+
+            let mut reader = InteractionReaderBuilder::new().from_path(path).unwrap();
+            let read: Vec<_> = reader.interactions().collect();
+
+            assert_eq!(read.len(), 3, "{}", name);
+            assert_eq!(read[2], (String::from("bob"), String::from("apple")));
+
+            fs::remove_file(path).unwrap();
+        }
+        fs::remove_dir(&directory).unwrap();
+
+        let from_stdin: Vec<_> = InteractionReaderBuilder::new()
+            .from_reader(decompressed(Cursor::new(gzip)).unwrap())
+            .unwrap()
+            .interactions()
+            .collect();
+
+        assert_eq!(from_stdin.len(), 3);
+    }
+
+    #[cfg(not(feature = "compression"))]
+    #[test]
+    fn reject_compressed_input_without_compression() {
+
+        use std::io::ErrorKind;
+
+        let gzip = Cursor::new(vec![0x1f, 0x8b, 0x08, 0x00]);
+        let zstd = Cursor::new(vec![0x28, 0xb5, 0x2f, 0xfd, 0x00]);
+
+        for input in [gzip, zstd] {
+            let failure = decompressed(input).err().unwrap();
+            assert_eq!(failure.kind(), ErrorKind::Unsupported);
+            assert!(failure.to_string().contains("'compression' feature"));
+        }
+
+        let plain: Vec<_> = InteractionReaderBuilder::new()
+            .from_reader(decompressed(Cursor::new("alice\tapple\n")).unwrap())
+            .unwrap()
+            .interactions()
+            .collect();
+
+        assert_eq!(plain.len(), 1);
+    }
+
+    #[test]
+    fn handle_malformed_lines() {
+
+        let input = "alice\tapple\t2\nalice,dog\nbob\tpony\tmany\nbob\tapple\n";
+
+        let mut lenient = InteractionReaderBuilder::new()
+            .from_reader(input.as_bytes())
+            .unwrap();
+
+        let path = env::temp_dir().join(format!("recoreco-io-test-rejects-{}.tsv", process::id()));
+        lenient.write_rejects_to(fs::File::create(&path).unwrap());
+
+        let interactions: Vec<_> = lenient.weighted_interactions().collect();
+        assert_eq!(interactions.len(), 2);
+
+        let report = lenient.finish().unwrap().clone();
+        assert_eq!(report.num_lines, 4);
+        assert_eq!(report.num_rejected, 2);
+        assert_eq!(report.rejected_fraction(), 0.5);
+        assert_eq!(report.samples[0], MalformedLine {
+            line: 2,
+            content: String::from("alice,dog"),
+            problem: String::from("no column 2"),
+        });
+        assert_eq!(report.samples[1].problem, "weight 'many' is not a number");
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "alice,dog\nbob\tpony\tmany\n");
+        fs::remove_file(&path).unwrap();
+
+        let mut strict = InteractionReaderBuilder::new()
+            .malformed_lines(MalformedLines::Strict)
+            .from_reader(input.as_bytes())
+            .unwrap();
+
+        assert_eq!(strict.interactions().count(), 1);
+
+        let error = strict.finish().err().unwrap();
+        assert_eq!(error.to_string(), "Malformed line 2 (no column 2): 'alice,dog'");
+    }
+
+    #[test]
+    fn reject_raw_lines() {
+
+        let input = "user,item,plays\nalice,\"ac,dc\",2\r\n\"bob\",\"queen\",\"many\"\r\n\
+            charles,queen\n";
+
+        let mut reader = InteractionReaderBuilder::new()
+            .delimiter(b',')
+            .has_headers(true)
+            .from_reader(input.as_bytes())
+            .unwrap();
+
+        let path = env::temp_dir().join(format!("recoreco-io-test-raw-{}.csv", process::id()));
+        reader.write_rejects_to(fs::File::create(&path).unwrap());
+
+        let interactions: Vec<_> = reader.weighted_interactions().collect();
+        assert_eq!(interactions, vec![
+            (String::from("alice"), String::from("ac,dc"), 2.0),
+            (String::from("charles"), String::from("queen"), 1.0),
+        ]);
+
+        let report = reader.finish().unwrap().clone();
+        assert_eq!(report.num_rejected, 1);
+        assert_eq!(report.samples[0].line, 3);
+        assert_eq!(report.samples[0].content, "\"bob\",\"queen\",\"many\"");
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "\"bob\",\"queen\",\"many\"\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reject_raw_lines_across_chunks() {
+
+        // Many more lines than fit into the buffer of the reader, every 1000th one is malformed
+        let mut input = String::new();
+        for line in 1..=20_000 {
+            if line % 1000 == 0 {
+                input.push_str(&format!("user_{}\tno_weight_{}\tx\r\n", line, line));
+            } else {
+                input.push_str(&format!("user_{}\titem_{}\t{}\r\n", line, line % 7, line));
+            }
+        }
+
+        let mut reader = InteractionReaderBuilder::new().from_reader(input.as_bytes()).unwrap();
+        assert_eq!(reader.weighted_interactions().count(), 19_980);
+
+        let report = reader.finish().unwrap();
+        assert_eq!(report.num_lines, 20_000);
+        assert_eq!(report.num_rejected, 20);
+        for (index, sample) in report.samples.iter().enumerate() {
+            let line = (index as u64 + 1) * 1000;
+            assert_eq!(sample.line, line);
+            assert_eq!(sample.content, format!("user_{}\tno_weight_{}\tx", line, line));
+        }
+    }
+}
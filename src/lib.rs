@@ -4,15 +4,15 @@
 //! interested in X are also interested in Y') from interactions between users and items. It is a
 //! command line tool that expects a CSV file as input, where each line denotes an interaction
 //! between a user and an item and consists of a user identifier and an item identifier separated
-//! by a tab character. Recoreco by default outputs 10 associated items per item (with no particular
-//! ranking) in JSON format.
+//! by a tab character. Recoreco by default outputs 10 associated items per item (ranked by the
+//! strength of their association) in JSON format.
 //!
 //! If you would like to learn more about the math behind the approach that **recoreco** is built
 //! on, checkout the book on [practical machine learning: innovations in recommendation](https://mapr.com/practical-machine-learning/)
 //! and the talk on [real-time puppies and ponies](https://www.slideshare.net/tdunning/realtime-puppies-and-ponies-evolving-indicator-recommendations-in-realtime)
 //! from my friend [Ted Dunning](https://twitter.com/ted_dunning).
 
-/**
+/*
  * RecoReco
  * Copyright (C) 2018 Sebastian Schelter
  *
@@ -33,37 +33,63 @@
 extern crate rand;
 extern crate fnv;
 extern crate rayon;
+#[cfg(feature = "server")]
+extern crate tiny_http;
 
 #[macro_use]
 extern crate serde_derive;
 #[macro_use]
 extern crate serde_json;
 
-use std::collections::BinaryHeap;
 use std::time::{Duration, Instant};
 
-use rand::Rng;
-use fnv::FnvHashSet;
-use rayon::prelude::*;
-
 mod llr;
 pub mod io;
 pub mod types;
 pub mod stats;
+pub mod model;
+pub mod snapshot;
+pub mod measures;
+pub mod cooccurrences;
+pub mod weights;
+pub mod dedup;
+pub mod recommend;
+pub mod index;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod evaluation;
+pub mod error;
+pub mod config;
+
+mod sampling;
 
 mod usage_tests;
 
-use llr::ScoredItem;
-use types::{SparseVector, SparseMatrix, SparseBinaryMatrix};
+use types::{SparseBinaryMatrix, IndicatorMatrix, ScoredItem};
 use stats::DataDictionary;
+use model::IndicatorModel;
+use weights::Weighting;
+use recommend::Recommender;
+use error::Error;
+use config::IndicatorConfig;
+
+/// Default seed for the random number generator used to downsample the interactions
+pub const DEFAULT_SEED: u64 = 0x5EED_2EC0_2EC0_5EED;
 
 /// Compute item indicators from a stream of interactions.
 ///
 /// * `interactions` - the observed interactions
 /// * `data_dict` - a data dictionary which maps string to integer identifiers
 /// * `num_indicators_per_item` - the number of highly associated items to compute per item (use 10 as default)
-/// * `f_max` - the maximum number of interactions to account for per user (use 500 as default)
-/// * `k_max` - The maximum number of interactions to account for per item (use 500 as default)
+/// * `f_max` - the maximum number of interactions to account for per item (use 500 as default)
+/// * `k_max` - the maximum number of interactions to account for per user (use 500 as default)
+/// * `seed` - the seed for the random downsampling of the interactions, runs with the same seed
+///   and the same input produce the same indicators (use `recoreco::DEFAULT_SEED` as default)
+///
+/// Fails with `Error::Config` if a tunable is out of range, see
+/// `recoreco::config::IndicatorConfigBuilder::build`, and with `Error::UnknownUser` or
+/// `Error::UnknownItem` if an interaction refers to a user or item which is unknown to
+/// `data_dict`. `recoreco::indicators_with_config` takes the tunables as named fields instead.
 ///
 /// # Examples
 ///
@@ -72,7 +98,7 @@ use stats::DataDictionary;
 /// ```
 /// extern crate recoreco;
 /// use recoreco::stats::{DataDictionary, Renaming};
-/// use recoreco::indicators;
+/// use recoreco::{indicators, DEFAULT_SEED};
 ///
 /// /* Our input data comprises of observed interactions between users and items.
 ///    The identifiers used can be strings of arbitrary length and structure. */
@@ -92,7 +118,7 @@ use stats::DataDictionary;
 ///    interaction data once to compute a data dictionary that helps us map from string to
 ///    integer identifiers and has basic statistics of the data */
 ///
-/// let data_dict = DataDictionary::from(interactions.iter());
+/// let data_dict = DataDictionary::try_from(interactions.iter()).unwrap();
 ///
 /// println!(
 ///     "Found {} interactions between {} users and {} items.",
@@ -110,8 +136,9 @@ use stats::DataDictionary;
 ///     &data_dict,
 ///     10,
 ///     500,
-///     500
-/// );
+///     500,
+///     DEFAULT_SEED
+/// ).unwrap();
 ///
 /// /* The renaming data structure helps us map the integer ids back to the original
 ///    string ids. */
@@ -120,11 +147,11 @@ use stats::DataDictionary;
 ///
 /// /* We print the resulting highly associated pairs of items. */
 /// for (item_index, indicated_items_for_item) in indicated_items.iter().enumerate() {
-///     let item_name = renaming.item_name(item_index as u32);
+///     let item_name = renaming.item_name(item_index as u32).unwrap();
 ///     println!("Items highly associated with {}:", item_name);
 ///
 ///     for indicated_item_index in indicated_items_for_item.iter() {
-///         let indicated_item_name = renaming.item_name(*indicated_item_index as u32);
+///         let indicated_item_name = renaming.item_name(*indicated_item_index).unwrap();
 ///         println!("\t{}", indicated_item_name);
 ///     }
 /// }
@@ -134,199 +161,533 @@ pub fn indicators<T>(
     data_dict: &DataDictionary,
     num_indicators_per_item: usize,
     f_max: u32,
-    k_max: u32
-) -> SparseBinaryMatrix
+    k_max: u32,
+    seed: u64,
+) -> Result<SparseBinaryMatrix, Error>
+where
+    T: Iterator<Item = (String, String)>
+{
+    scored_indicators(interactions, data_dict, num_indicators_per_item, f_max, k_max, seed)
+        .map(|indicators| indicators.to_binary())
+}
+
+/// Compute item indicators from a stream of interactions, and retain their association scores.
+///
+/// Takes the same arguments as `recoreco::indicators`, but returns the highly associated items of
+/// each item together with their LLR scores, sorted by descending score. The result is indexed by
+/// the internal item index from `data_dict` and has a (potentially empty) row for every item.
+///
+/// # Examples
+///
+/// ```
+/// extern crate recoreco;
+/// use recoreco::stats::DataDictionary;
+/// use recoreco::{scored_indicators, DEFAULT_SEED};
+///
+/// let interactions = vec![
+///     (String::from("alice"), String::from("apple")),
+///     (String::from("alice"), String::from("pony")),
+///     (String::from("bob"), String::from("apple")),
+///     (String::from("bob"), String::from("pony")),
+///     (String::from("charles"), String::from("bike")),
+/// ];
+///
+/// let data_dict = DataDictionary::try_from(interactions.iter()).unwrap();
+/// let scored_items = scored_indicators(
+///     interactions.into_iter(),
+///     &data_dict,
+///     10,
+///     500,
+///     500,
+///     DEFAULT_SEED
+/// ).unwrap();
+///
+/// for (item_index, indicators_for_item) in scored_items.iter() {
+///     println!("Items highly associated with item {}:", item_index);
+///     /* The strongest association comes first */
+///     for scored_item in indicators_for_item.iter() {
+///         println!("\t{} ({})", scored_item.item, scored_item.score);
+///     }
+/// }
+/// ```
+pub fn scored_indicators<T>(
+    interactions: T,
+    data_dict: &DataDictionary,
+    num_indicators_per_item: usize,
+    f_max: u32,
+    k_max: u32,
+    seed: u64,
+) -> Result<IndicatorMatrix, Error>
+where
+    T: Iterator<Item = (String, String)>
+{
+    let config = config(num_indicators_per_item, f_max, k_max, seed)?;
+    indicators_with_config(interactions, data_dict, &config)
+}
+
+/// Compute item indicators from a stream of interactions, with all tunables taken from a
+/// validated `recoreco::config::IndicatorConfig`.
+///
+/// Returns the same result as `recoreco::scored_indicators` for the same tunables, also if the
+/// `config` asks for several threads, which count the cooccurrences of ranges of items in
+/// parallel. Fails with `Error::UnknownUser` or `Error::UnknownItem` if an interaction refers to a
+/// user or item which is unknown to `data_dict`.
+///
+/// # Examples
+///
+/// ```
+/// extern crate recoreco;
+/// use recoreco::config::IndicatorConfig;
+/// use recoreco::indicators_with_config;
+/// use recoreco::stats::DataDictionary;
+///
+/// let interactions = vec![
+///     (String::from("alice"), String::from("apple")),
+///     (String::from("alice"), String::from("pony")),
+///     (String::from("bob"), String::from("apple")),
+///     (String::from("bob"), String::from("pony")),
+///     (String::from("charles"), String::from("bike")),
+/// ];
+///
+/// let config = IndicatorConfig::builder()
+///     .num_indicators_per_item(5)
+///     .max_interactions_per_user(100)
+///     .max_interactions_per_item(1000)
+///     .min_cooccurrences(2)
+///     .build()
+///     .unwrap();
+///
+/// let data_dict = DataDictionary::try_from(interactions.iter()).unwrap();
+/// let scored_items = indicators_with_config(interactions.into_iter(), &data_dict, &config)
+///     .unwrap();
+///
+/// let apple = data_dict.item_index("apple").unwrap();
+/// let pony = data_dict.item_index("pony").unwrap();
+///
+/// assert_eq!(scored_items.indicators_for(apple)[0].item, pony);
+/// ```
+pub fn indicators_with_config<T>(
+    interactions: T,
+    data_dict: &DataDictionary,
+    config: &IndicatorConfig,
+) -> Result<IndicatorMatrix, Error>
 where
     T: Iterator<Item = (String, String)>
 {
 
-    let num_items = data_dict.num_items();
-    let num_users = data_dict.num_users();
+    let start = Instant::now();
 
-    let max_sum_of_cooccurrences_per_item = (f_max * k_max) as usize;
+    let mut model = IndicatorModel::with_config(
+        data_dict.num_users(),
+        data_dict.num_items(),
+        config,
+    );
 
-    // Precompute most logarithms
-    let precomputed_logarithms: Vec<f64> = llr::logarithms_table(max_sum_of_cooccurrences_per_item);
+    let mut failure = None;
+    let indexed_interactions = interactions.map_while(|(user_str, item_str)| {
+        let indexes = data_dict
+            .item_index(&item_str)
+            .and_then(|item| data_dict.user_index(&user_str).map(|user| (user, item)));
+        indexes.map_err(|error| failure = Some(error)).ok()
+    });
 
-    // Downsampled history matrix A
-    let mut user_non_sampled_interaction_counts = types::new_dense_vector(num_users);
-    let mut user_interaction_counts = types::new_dense_vector(num_users);
-    let mut item_interaction_counts = types::new_dense_vector(num_items);
-    let mut samples_of_a: Vec<Vec<u32>> = vec![Vec::new(); num_users];
+    model.update_all_partitioned(indexed_interactions, config.num_threads().unwrap_or(1))?;
 
-    // Cooccurrence matrix C
-    let mut c: SparseMatrix = types::new_sparse_matrix(num_items);
-    let mut row_sums_of_c = types::new_dense_vector(num_items);
+    if let Some(failure) = failure {
+        return Err(failure);
+    }
 
-    let mut num_cooccurrences_observed: u64 = 0;
+    let num_items_rescored = model.refresh();
 
-    let mut rng = rand::XorShiftRng::new_unseeded();
+    let duration = to_millis(start.elapsed());
+    eprintln!(
+        "{} cooccurrences observed, {}ms training time, {} items rescored",
+        model.num_cooccurrences_observed(),
+        duration,
+        num_items_rescored,
+    );
+
+    Ok(model.into_indicators())
+}
 
-    let start = Instant::now();
 
-    let mut items_to_rescore = FnvHashSet::default();
+/// Compute item indicators from a stream of weighted interactions, e.g., ratings or play counts.
+///
+/// Takes the same arguments as `recoreco::scored_indicators`, but every interaction carries a
+/// weight, and the `weighting` decides how the weights influence the downsampling of the
+/// interactions, see `recoreco::weights::Weighting`. The data dictionary should be built with
+/// the same weighting via `recoreco::stats::DataDictionary::extend_weighted`.
+///
+/// # Examples
+///
+/// ```
+/// extern crate recoreco;
+/// use recoreco::stats::DataDictionary;
+/// use recoreco::weights::Weighting;
+/// use recoreco::{weighted_indicators, DEFAULT_SEED};
+///
+/// /* Ratings from one to five, we only account for ratings of four or more */
+/// let ratings = vec![
+///     (String::from("alice"), String::from("apple"), 5.0),
+///     (String::from("alice"), String::from("pony"), 4.0),
+///     (String::from("alice"), String::from("bike"), 1.0),
+///     (String::from("bob"), String::from("apple"), 4.0),
+///     (String::from("bob"), String::from("pony"), 5.0),
+/// ];
+///
+/// let weighting = Weighting::Threshold(4.0);
+///
+/// let mut data_dict = DataDictionary::default();
+/// data_dict.extend_weighted(ratings.clone().into_iter(), &weighting).unwrap();
+///
+/// let scored_items = weighted_indicators(
+///     ratings.into_iter(),
+///     &data_dict,
+///     10,
+///     500,
+///     500,
+///     DEFAULT_SEED,
+///     weighting,
+/// ).unwrap();
+///
+/// let apple = data_dict.item_index("apple").unwrap();
+/// let pony = data_dict.item_index("pony").unwrap();
+///
+/// assert_eq!(data_dict.num_items(), 2);
+/// assert_eq!(scored_items.indicators_for(apple)[0].item, pony);
+/// ```
+pub fn weighted_indicators<T>(
+    interactions: T,
+    data_dict: &DataDictionary,
+    num_indicators_per_item: usize,
+    f_max: u32,
+    k_max: u32,
+    seed: u64,
+    weighting: Weighting,
+) -> Result<IndicatorMatrix, Error>
+where
+    T: Iterator<Item = (String, String, f64)>
+{
 
-    for (user_str, item_str) in interactions {
+    let config = config(num_indicators_per_item, f_max, k_max, seed)?;
 
-        let item = *data_dict.item_index(&item_str);
-        let user = *data_dict.user_index(&user_str);
+    let start = Instant::now();
 
-        let item_idx = item as usize;
-        let user_idx = user as usize;
+    let mut model = IndicatorModel::with_config(
+        data_dict.num_users(),
+        data_dict.num_items(),
+        &config,
+    );
+    model.set_weighting(weighting)?;
 
-        // Update number of observed interactions for user
-        user_non_sampled_interaction_counts[user_idx] += 1;
+    model.update_all_weighted(interactions, data_dict)?;
 
-        // Check whether we have seen enough interactions for this item yet
-        if item_interaction_counts[item_idx] < f_max {
+    let num_items_rescored = model.refresh();
 
-            // Retrieve current history sample for interacting user
-            let mut user_history = &mut samples_of_a[user_idx];
-            let num_items_in_user_history = user_history.len();
+    let duration = to_millis(start.elapsed());
+    eprintln!(
+        "{} cooccurrences observed, {}ms training time, {} items rescored",
+        model.num_cooccurrences_observed(),
+        duration,
+        num_items_rescored,
+    );
 
-            // Check whether we have seen enough interactions for this user yet
-            if user_interaction_counts[user_idx] < k_max {
+    Ok(model.into_indicators())
+}
 
-                // Record coocurrences with all other items from user history
-                for other_item in user_history.iter() {
-                    *c[item_idx].entry(*other_item).or_insert(0) += 1;
-                    *c[*other_item as usize].entry(item).or_insert(0) += 1;
-                    row_sums_of_c[*other_item as usize] += 1;
-                }
+/// Compute item indicators from a stream of interactions in a single pass.
+///
+/// In contrast to `recoreco::scored_indicators`, this function does not require a data dictionary
+/// computed in an earlier pass over the interactions. Users and items are assigned internal
+/// indexes on first sight, and the internal data structures grow as needed. This allows us to
+/// consume interactions that can only be read once, e.g., from stdin. Returns the data dictionary
+/// built up during the pass together with the indicators. Fails with `Error::Config` if a
+/// tunable is out of range, and with `Error::Overflow` if there are too many users or items.
+///
+/// * `interactions` - the observed interactions
+/// * `num_indicators_per_item` - the number of highly associated items to compute per item (use 10 as default)
+/// * `f_max` - the maximum number of interactions to account for per item (use 500 as default)
+/// * `k_max` - the maximum number of interactions to account for per user (use 500 as default)
+/// * `seed` - the seed for the random downsampling of the interactions (use `recoreco::DEFAULT_SEED` as default)
+///
+/// # Examples
+///
+/// ```
+/// extern crate recoreco;
+/// use recoreco::stats::Renaming;
+/// use recoreco::{single_pass_indicators, DEFAULT_SEED};
+///
+/// let interactions = vec![
+///     (String::from("alice"), String::from("apple")),
+///     (String::from("alice"), String::from("pony")),
+///     (String::from("bob"), String::from("apple")),
+///     (String::from("bob"), String::from("pony")),
+/// ];
+///
+/// let (data_dict, scored_items) =
+///     single_pass_indicators(interactions.into_iter(), 10, 500, 500, DEFAULT_SEED).unwrap();
+///
+/// let renaming = Renaming::from(data_dict);
+///
+/// for (item_index, indicators_for_item) in scored_items.iter() {
+///     println!("Items highly associated with {}:", renaming.item_name(item_index).unwrap());
+///     for scored_item in indicators_for_item.iter() {
+///         println!("\t{}", renaming.item_name(scored_item.item).unwrap());
+///     }
+/// }
+/// ```
+pub fn single_pass_indicators<T>(
+    interactions: T,
+    num_indicators_per_item: usize,
+    f_max: u32,
+    k_max: u32,
+    seed: u64,
+) -> Result<(DataDictionary, IndicatorMatrix), Error>
+where
+    T: Iterator<Item = (String, String)>
+{
 
-                // Add item to user history
-                user_history.push(item);
-                // Register items for rescoring
-                items_to_rescore.extend(user_history.iter());
-                items_to_rescore.insert(item);
+    let config = config(num_indicators_per_item, f_max, k_max, seed)?;
 
-                // Update statistics for user and item interaction counts and
-                // cooccurrence matrix sums
-                user_interaction_counts[user_idx] += 1;
-                item_interaction_counts[item_idx] += 1;
-                row_sums_of_c[item_idx] += num_items_in_user_history as u32;
-                num_cooccurrences_observed += 2 * num_items_in_user_history as u64;
+    let start = Instant::now();
 
-            } else {
+    let mut data_dict = DataDictionary::default();
+    let mut model = IndicatorModel::with_config(0, 0, &config);
 
-                let num_interactions_seen_by_user =
-                    user_non_sampled_interaction_counts[user_idx];
+    model.register_and_update_all(interactions, &mut data_dict)?;
 
-                let k: usize = rng.gen_range(0, num_interactions_seen_by_user as usize);
+    let num_items_rescored = model.refresh();
 
-                if k < num_items_in_user_history {
-                    let previous_item = user_history[k];
+    let duration = to_millis(start.elapsed());
+    eprintln!(
+        "{} cooccurrences observed, {}ms training time, {} items rescored",
+        model.num_cooccurrences_observed(),
+        duration,
+        num_items_rescored,
+    );
 
-                    for (n, other_item) in user_history.iter().enumerate() {
+    Ok((data_dict, model.into_indicators()))
+}
 
-                        if n != k {
-                            // Adjust cooccurrence counts
-                            *c[item_idx].entry(*other_item).or_insert(0) += 1;
-                            *c[*other_item as usize].entry(item).or_insert(0) += 1;
-                            *c[previous_item as usize].entry(*other_item).or_insert(0) -= 1;
-                            *c[*other_item as usize].entry(previous_item).or_insert(0) -= 1;
-                        }
-                    }
+/// Compute cross-indicators between two types of interactions, e.g., views and purchases.
+///
+/// Both streams of interactions must share the same space of users and items, `data_dict` has to
+/// be built from both of them. We downsample both streams like in `recoreco::indicators`, and
+/// compute the cross-occurrence matrix between the secondary and the primary interactions. The
+/// result holds, for every item of the secondary interactions, the items of the primary
+/// interactions which are highly associated with it, in the sense of _'people who viewed X went on
+/// to buy Y'_ (with views as secondary and purchases as primary interactions). In contrast to
+/// `recoreco::indicators`, an item can be an indicator for itself.
+///
+/// Fails like `recoreco::indicators`.
+///
+/// * `primary_interactions` - the observed interactions of the type we want to recommend, e.g., purchases
+/// * `secondary_interactions` - the observed interactions of the type we use as evidence, e.g., views
+/// * `data_dict` - a data dictionary which maps string to integer identifiers for both streams
+/// * `num_indicators_per_item` - the number of highly associated items to compute per item (use 10 as default)
+/// * `f_max` - the maximum number of interactions to account for per item (use 500 as default)
+/// * `k_max` - the maximum number of interactions to account for per user (use 500 as default)
+/// * `seed` - the seed for the random downsampling of the interactions (use `recoreco::DEFAULT_SEED` as default)
+///
+/// # Examples
+///
+/// ```
+/// extern crate recoreco;
+/// use recoreco::stats::DataDictionary;
+/// use recoreco::{cross_indicators, DEFAULT_SEED};
+///
+/// let purchases = vec![
+///     (String::from("alice"), String::from("pony")),
+///     (String::from("bob"), String::from("pony")),
+/// ];
+///
+/// let views = vec![
+///     (String::from("alice"), String::from("apple")),
+///     (String::from("alice"), String::from("pony")),
+///     (String::from("bob"), String::from("apple")),
+///     (String::from("charles"), String::from("bike")),
+/// ];
+///
+/// let data_dict = DataDictionary::try_from(purchases.iter().chain(views.iter())).unwrap();
+///
+/// let scored_items = cross_indicators(
+///     purchases.into_iter(),
+///     views.into_iter(),
+///     &data_dict,
+///     10,
+///     500,
+///     500,
+///     DEFAULT_SEED
+/// ).unwrap();
+///
+/// let apple = data_dict.item_index("apple").unwrap();
+/// let pony = data_dict.item_index("pony").unwrap();
+///
+/// /* People who viewed an apple went on to buy a pony */
+/// assert_eq!(scored_items.indicators_for(apple)[0].item, pony);
+/// ```
+pub fn cross_indicators<P, S>(
+    primary_interactions: P,
+    secondary_interactions: S,
+    data_dict: &DataDictionary,
+    num_indicators_per_item: usize,
+    f_max: u32,
+    k_max: u32,
+    seed: u64,
+) -> Result<IndicatorMatrix, Error>
+where
+    P: Iterator<Item = (String, String)>,
+    S: Iterator<Item = (String, String)>,
+{
+    let config = config(num_indicators_per_item, f_max, k_max, seed)?;
 
-                    // Register items for rescoring
-                    items_to_rescore.extend(user_history.iter());
-                    items_to_rescore.insert(item);
+    cross_indicators_with_config(primary_interactions, secondary_interactions, data_dict, &config)
+}
 
-                    // update cooccurrence matrix sums
-                    row_sums_of_c[item_idx] += num_items_in_user_history as u32 - 1;
-                    row_sums_of_c[previous_item as usize] -=
-                        num_items_in_user_history as u32 - 1;
+/// Compute cross-indicators like `recoreco::cross_indicators`, and take the tunables, the measure
+/// and the thresholds from a validated `recoreco::config::IndicatorConfig`.
+///
+/// The cross-occurrences are always counted on the calling thread, see
+/// `recoreco::model::IndicatorModel::cross_with_config`. Fails with `Error::UnknownUser` or
+/// `Error::UnknownItem` if an interaction refers to a user or item which is unknown to
+/// `data_dict`.
+///
+/// # Examples
+///
+/// ```
+/// extern crate recoreco;
+/// use recoreco::config::IndicatorConfig;
+/// use recoreco::cross_indicators_with_config;
+/// use recoreco::measures::Measure;
+/// use recoreco::stats::DataDictionary;
+///
+/// let purchases = vec![
+///     (String::from("alice"), String::from("pony")),
+///     (String::from("bob"), String::from("pony")),
+/// ];
+///
+/// let views = vec![
+///     (String::from("alice"), String::from("apple")),
+///     (String::from("bob"), String::from("apple")),
+///     (String::from("charles"), String::from("bike")),
+/// ];
+///
+/// let config = IndicatorConfig::builder()
+///     .measure(Measure::Jaccard)
+///     .min_cooccurrences(2)
+///     .build()
+///     .unwrap();
+///
+/// let data_dict = DataDictionary::try_from(purchases.iter().chain(views.iter())).unwrap();
+///
+/// let scored_items = cross_indicators_with_config(
+///     purchases.into_iter(),
+///     views.into_iter(),
+///     &data_dict,
+///     &config,
+/// ).unwrap();
+///
+/// let apple = data_dict.item_index("apple").unwrap();
+/// let pony = data_dict.item_index("pony").unwrap();
+///
+/// /* Both people who viewed an apple went on to buy a pony */
+/// assert_eq!(scored_items.indicators_for(apple)[0].item, pony);
+/// assert_eq!(scored_items.indicators_for(apple)[0].score, 1.0);
+/// ```
+pub fn cross_indicators_with_config<P, S>(
+    primary_interactions: P,
+    secondary_interactions: S,
+    data_dict: &DataDictionary,
+    config: &IndicatorConfig,
+) -> Result<IndicatorMatrix, Error>
+where
+    P: Iterator<Item = (String, String)>,
+    S: Iterator<Item = (String, String)>,
+{
+    let mut model =
+        IndicatorModel::cross_with_config(data_dict.num_users(), data_dict.num_items(), config);
 
-                    // Replace previous item in user history
-                    user_history[k] = item;
+    model.update_all(primary_interactions, data_dict)?;
+    model.update_all_secondary(secondary_interactions, data_dict)?;
+    model.refresh();
 
-                    // Adjust item statistics
-                    item_interaction_counts[item_idx] += 1;
-                    item_interaction_counts[previous_item as usize] -= 1;
-                }
-            }
-        }
-    }
+    Ok(model.into_indicators())
+}
 
-    // Compute top-n indicators per item in parallel
-    let indicators = items_to_rescore
-        .par_iter()
-        .map(|item| {
-            rescore(
-                *item,
-                &c[*item as usize],
-                &row_sums_of_c,
-                num_cooccurrences_observed,
-                num_indicators_per_item,
-                &precomputed_logarithms,
-            )
-        })
+/// Compute recommendations for a user from the items in the history of the user and precomputed
+/// indicators.
+///
+/// The scores of the indicated items are summed up across the indicators of all items in the
+/// history, and items from the history are not recommended again. Items which are unknown to the
+/// `data_dict` are ignored. See `recoreco::recommend::Recommender` for more options.
+///
+/// * `history` - the string identifiers of the items the user interacted with
+/// * `indicators` - the indicators, e.g., computed via `recoreco::scored_indicators`
+/// * `data_dict` - the data dictionary which was used to compute the indicators
+/// * `num_recommendations` - the number of items to recommend (use 10 as default)
+///
+/// # Examples
+///
+/// ```
+/// extern crate recoreco;
+/// use recoreco::stats::DataDictionary;
+/// use recoreco::{recommendations, scored_indicators, DEFAULT_SEED};
+///
+/// let interactions = vec![
+///     (String::from("alice"), String::from("apple")),
+///     (String::from("alice"), String::from("pony")),
+///     (String::from("bob"), String::from("apple")),
+///     (String::from("bob"), String::from("pony")),
+///     (String::from("charles"), String::from("bike")),
+/// ];
+///
+/// let data_dict = DataDictionary::try_from(interactions.iter()).unwrap();
+/// let indicators = scored_indicators(
+///     interactions.into_iter(),
+///     &data_dict,
+///     10,
+///     500,
+///     500,
+///     DEFAULT_SEED
+/// ).unwrap();
+///
+/// /* Someone who likes apples might also like a pony */
+/// let history = vec!["apple"];
+/// let recommended_items = recommendations(history.into_iter(), &indicators, &data_dict, 10);
+///
+/// assert_eq!(recommended_items[0].item, data_dict.item_index("pony").unwrap());
+/// ```
+pub fn recommendations<'a, T>(
+    history: T,
+    indicators: &IndicatorMatrix,
+    data_dict: &DataDictionary,
+    num_recommendations: usize,
+) -> Vec<ScoredItem>
+where
+    T: Iterator<Item = &'a str>
+{
+    let history: Vec<u32> = history
+        .filter_map(|item| data_dict.find_item_index(item))
         .collect();
 
-    let duration = to_millis(start.elapsed());
-    println!(
-        "{} cooccurrences observed, {}ms training time, {} items rescored",
-        num_cooccurrences_observed,
-        duration,
-        items_to_rescore.len(),
-    );
+    Recommender::new(indicators).recommend(&history, num_recommendations)
+}
 
-    indicators
+/// Validates the positional tunables of the functions above
+fn config(
+    num_indicators_per_item: usize,
+    f_max: u32,
+    k_max: u32,
+    seed: u64,
+) -> Result<IndicatorConfig, Error> {
+    IndicatorConfig::builder()
+        .num_indicators_per_item(num_indicators_per_item)
+        .max_interactions_per_item(f_max)
+        .max_interactions_per_user(k_max)
+        .seed(seed)
+        .build()
 }
 
 fn to_millis(duration: Duration) -> u64 {
     (duration.as_secs() * 1_000) + u64::from(duration.subsec_millis())
 }
-
-fn rescore(
-    item: u32,
-    cooccurrence_counts: &SparseVector,
-    row_sums_of_c: &[u32],
-    num_cooccurrences_observed: u64,
-    n: usize,
-    logarithms_table: &[f64],
-) -> FnvHashSet<u32> {
-
-    // We can skip the scoring if we have seen less than n items
-    if cooccurrence_counts.len() <= n {
-        cooccurrence_counts
-            .keys()
-            .cloned()
-            .collect::<FnvHashSet<_>>()
-    } else {
-        // We'll use a heap to keep track of the current top-n scored items
-        let mut top_indicators: BinaryHeap<ScoredItem> = BinaryHeap::with_capacity(n);
-
-        for (other_item, num_cooccurrences) in cooccurrence_counts.iter() {
-            if *other_item != item {
-                // Compute counts of contingency table
-                let k11 = u64::from(*num_cooccurrences);
-                let k12 = u64::from(row_sums_of_c[item as usize]) - k11;
-                let k21 = u64::from(row_sums_of_c[*other_item as usize]) - k11;
-                let k22 = num_cooccurrences_observed + k11 - k12 - k21;
-
-                // Compute LLR score
-                let llr_score = llr::log_likelihood_ratio(k11, k12, k21, k22, logarithms_table);
-
-                // Update heap holding top-n scored items for this item
-                let scored_item = ScoredItem { item: *other_item, score: llr_score };
-
-                if top_indicators.len() < n {
-                    top_indicators.push(scored_item);
-                } else {
-                    let mut top = top_indicators.peek_mut().unwrap();
-                    if scored_item < *top {
-                        *top = scored_item;
-                    }
-                }
-            }
-        }
-
-        let indicators_for_item: FnvHashSet<u32> = top_indicators
-            .drain()
-            .map(|scored_item| scored_item.item)
-            .collect();
-
-        indicators_for_item
-    }
-}
\ No newline at end of file
@@ -1,4 +1,4 @@
-/**
+/*
  * RecoReco
  * Copyright (C) 2018 Sebastian Schelter
  *
@@ -18,21 +18,40 @@
 
 use std::cmp::Ordering;
 
-/// Result type used to find the top-k anomalous items per item via a binary heap
-#[derive(PartialEq, Debug)]
+/// An item together with its association score. Used to find the top-k anomalous items per item
+/// via a binary heap, and returned as part of the computed indicators.
+///
+/// Note that the ordering is reversed (a higher score compares as smaller), so that the top of a
+/// `BinaryHeap<ScoredItem>` always holds the lowest scored item, and `into_sorted_vec()` returns
+/// the items in order of descending score. Items with the same score are ordered by their index,
+/// and a score which is not a number ranks below all other scores.
+#[derive(Debug, Clone, Copy)]
 pub struct ScoredItem {
     pub item: u32,
     pub score: f64,
 }
 
 /// Ordering for our max-heap, not that we must use a special implementation here as there is no
-/// total order on floating point numbers.
+/// total order on floating point numbers. Ties are broken by the item index, so that the ranking
+/// does not depend on the order in which the items are scored.
 fn cmp_reverse(scored_item_a: &ScoredItem, scored_item_b: &ScoredItem) -> Ordering {
-    match scored_item_a.score.partial_cmp(&scored_item_b.score) {
-        Some(Ordering::Less) => Ordering::Greater,
-        Some(Ordering::Greater) => Ordering::Less,
-        Some(Ordering::Equal) => Ordering::Equal,
-        None => Ordering::Equal
+
+    let score_a = scored_item_a.score;
+    let score_b = scored_item_b.score;
+
+    let by_score = match (score_a.is_nan(), score_b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => score_b.partial_cmp(&score_a).unwrap_or(Ordering::Equal),
+    };
+
+    by_score.then(scored_item_a.item.cmp(&scored_item_b.item))
+}
+
+impl PartialEq for ScoredItem {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
     }
 }
 
@@ -46,10 +65,15 @@ impl Ord for ScoredItem {
 
 impl PartialOrd for ScoredItem {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(cmp_reverse(self, other))
+        Some(self.cmp(other))
     }
 }
 
+/// The largest table of logarithms that we precompute, 8 MB of `f64`s. The logarithms of larger
+/// arguments are computed on the fly, so that huge maximum numbers of interactions per user and
+/// item do not allocate a table of their product.
+pub const MAX_LOGARITHMS_TABLE_SIZE: usize = 1 << 20;
+
 /// Precompute a table of logarithms which will be used for lookups later
 pub fn logarithms_table(max_arg: usize) -> Vec<f64> {
 
@@ -60,7 +84,7 @@ pub fn logarithms_table(max_arg: usize) -> Vec<f64> {
 
 /// Highly optimized implementation of the loglikelihood-based G²-test. We enforce inlining of the
 /// logarithms computation, apply manual common subexpression elimination and leverage a precomputed
-/// logarithms table for a given range.
+/// logarithms table for a given range. Arguments beyond the table are computed on the fly.
 ///
 /// The following url gives some details on the original implementation:
 ///
@@ -74,11 +98,11 @@ pub fn log_likelihood_ratio(k11: u64, k12: u64, k21: u64, k22: u64, logarithms:
 
     let xlx_all = x_logx(k11 + k12 + k21 + k22);
 
-    let log_k11 = logarithms[k11 as usize];
-    let log_k12 = logarithms[k12 as usize];
-    let log_k21 = logarithms[k21 as usize];
-    let log_k11_12 = logarithms[(k11 + k12) as usize];
-    let log_k11_21 = logarithms[(k11 + k21) as usize];
+    let log_k11 = logarithm(k11, logarithms);
+    let log_k12 = logarithm(k12, logarithms);
+    let log_k21 = logarithm(k21, logarithms);
+    let log_k11_12 = logarithm(k11 + k12, logarithms);
+    let log_k11_21 = logarithm(k11 + k21, logarithms);
 
     let row_entropy = xlx_all - x_times_logx(k11 + k12, log_k11_12) - x_logx(k21 + k22);
     let column_entropy = xlx_all - x_times_logx(k11 + k21, log_k11_21) - x_logx(k12 + k22);
@@ -95,6 +119,34 @@ pub fn log_likelihood_ratio(k11: u64, k12: u64, k21: u64, k22: u64, logarithms:
     }
 }
 
+/// Signed square root of the loglikelihood ratio as in Mahout, which is positive if the items
+/// cooccur more often than expected and negative if they cooccur less often than expected. The
+/// magnitude is roughly comparable to a standard score.
+pub fn signed_root_log_likelihood_ratio(
+    k11: u64,
+    k12: u64,
+    k21: u64,
+    k22: u64,
+    logarithms: &[f64],
+) -> f64 {
+
+    let root_llr = log_likelihood_ratio(k11, k12, k21, k22, logarithms).sqrt();
+
+    if (k11 as f64 / (k11 + k12) as f64) < (k21 as f64 / (k21 + k22) as f64) {
+        -root_llr
+    } else {
+        root_llr
+    }
+}
+
+/// Looks up the logarithm of `x` in the table, we never call this with zero beyond the table
+#[inline(always)]
+fn logarithm(x: u64, logarithms: &[f64]) -> f64 {
+    match logarithms.get(x as usize) {
+        Some(log_x) => *log_x,
+        None => (x as f64).ln(),
+    }
+}
 
 #[inline(always)]
 pub fn x_logx(x: u64) -> f64 {
@@ -112,7 +164,6 @@ fn x_times_logx(x: u64, log_x: f64) -> f64 {
 mod tests {
 
     use std::collections::BinaryHeap;
-    use std::f64::EPSILON;
     use llr;
     use llr::ScoredItem;
 
@@ -127,6 +178,23 @@ mod tests {
         assert!(item_b < item_c);
     }
 
+    #[test]
+    fn scored_item_ties_broken_by_item() {
+        let item_a = ScoredItem { item: 1, score: 0.5 };
+        let item_b = ScoredItem { item: 2, score: 0.5 };
+        let not_a_number = ScoredItem { item: 0, score: f64::NAN };
+
+        assert!(item_a < item_b);
+        assert_ne!(item_a, item_b);
+        assert_eq!(item_a, ScoredItem { item: 1, score: 0.5 });
+        assert!(item_b < not_a_number);
+        assert_eq!(not_a_number, not_a_number);
+
+        let mut items = vec![item_b, not_a_number, item_a];
+        items.sort();
+        assert_eq!(items, vec![item_a, item_b, not_a_number]);
+    }
+
     #[test]
     fn llr() {
         // Some cases from http://citeseerx.ist.psu.edu/viewdoc/summary?doi=10.1.1.14.5962
@@ -137,12 +205,40 @@ mod tests {
         assert!(close_enough_to(llr::log_likelihood_ratio(9, 12, 429, 31327, &logs), 48.94));
     }
 
+    #[test]
+    fn llr_beyond_logarithms_table() {
+        let logs = llr::logarithms_table(500 * 500);
+
+        // The sum k11 + k12 is exactly the size of the small table
+        let small_logs = llr::logarithms_table(2552);
+        let expected = llr::log_likelihood_ratio(110, 2442, 111, 29114, &logs);
+        assert_eq!(llr::log_likelihood_ratio(110, 2442, 111, 29114, &small_logs), expected);
+        assert_eq!(llr::log_likelihood_ratio(110, 2442, 111, 29114, &[]), expected);
+    }
+
+    #[test]
+    fn signed_root_llr() {
+        let logs = llr::logarithms_table(500 * 500);
+
+        // Cooccurring more often than expected
+        let positive = llr::signed_root_log_likelihood_ratio(110, 2442, 111, 29114, &logs);
+        assert!(close_enough_to(positive, 270.72f64.sqrt()));
+
+        // Cooccurring less often than expected, e.g., 1 instead of 10 times
+        let negative = llr::signed_root_log_likelihood_ratio(1, 99, 99, 801, &logs);
+        assert!(negative < 0.0);
+        assert!(close_enough_to(
+            negative * negative,
+            llr::log_likelihood_ratio(1, 99, 99, 801, &logs)
+        ));
+    }
+
     fn close_enough_to(value: f64, expected: f64) -> bool {
         (value - expected).abs() < 0.01
     }
 
     fn within_epsilon(value: f64, expected: f64) -> bool {
-        (value - expected).abs() < EPSILON
+        (value - expected).abs() < f64::EPSILON
     }
 
     #[test]
@@ -184,4 +280,4 @@ mod tests {
         assert_eq!(top_k[2].item, 2);
         assert!(within_epsilon(top_k[2].score, 1.5));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,430 @@
+//! ## Association measures for pairs of items
+//!
+//! We decide which items are highly associated by scoring the contingency table of their
+//! cooccurrences. By default, we use the loglikelihood ratio test, but there are many other
+//! measures in use, which can be plugged in by implementing the `AssociationMeasure` trait.
+//!
+/*
+ * RecoReco
+ * Copyright (C) 2018 Sebastian Schelter
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::fmt;
+use std::str::FromStr;
+
+use llr;
+
+/// Contingency table of the cooccurrences of an item A (the item we compute indicators for) and
+/// another item B in the cooccurrence matrix.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ContingencyTable {
+    /// Number of cooccurrences of A and B
+    pub k11: u64,
+    /// Number of cooccurrences of A with items other than B
+    pub k12: u64,
+    /// Number of cooccurrences of B with items other than A
+    pub k21: u64,
+    /// Number of cooccurrences involving neither A nor B
+    pub k22: u64,
+}
+
+impl ContingencyTable {
+
+    /// Overall number of cooccurrences in the table
+    pub fn total(&self) -> u64 {
+        self.k11 + self.k12 + self.k21 + self.k22
+    }
+
+    /// Number of cooccurrences of A
+    pub fn row_sum(&self) -> u64 {
+        self.k11 + self.k12
+    }
+
+    /// Number of cooccurrences of B
+    pub fn column_sum(&self) -> u64 {
+        self.k11 + self.k21
+    }
+
+    /// Direction of the association: positive if A and B cooccur more often than expected under
+    /// independence, negative if they cooccur less often, and neither if they are independent.
+    pub fn polarity(&self) -> Polarity {
+        let cooccurrence_odds = self.k11 as f64 * self.k22 as f64;
+        let separate_odds = self.k12 as f64 * self.k21 as f64;
+
+        if cooccurrence_odds > separate_odds {
+            Polarity::Positive
+        } else if cooccurrence_odds < separate_odds {
+            Polarity::Negative
+        } else {
+            Polarity::Any
+        }
+    }
+}
+
+/// Direction of the associations to select as indicators. Measures such as the loglikelihood
+/// ratio are large for pairs of items which cooccur much less often than expected as well as for
+/// pairs which cooccur much more often than expected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Polarity {
+    /// Select all associations, regardless of their direction (the default)
+    Any,
+    /// Only select items which cooccur more often than expected
+    Positive,
+    /// Only select items which cooccur less often than expected, e.g., for 'do not recommend
+    /// together' rules. These are ranked by the absolute value of their score.
+    Negative,
+}
+
+impl Polarity {
+
+    /// Whether an association with the given contingency table has this polarity
+    pub fn admits(&self, table: &ContingencyTable) -> bool {
+        *self == Polarity::Any || *self == table.polarity()
+    }
+}
+
+/// A measure of the strength of the association between two items, computed from the contingency
+/// table of their cooccurrences. Higher scores denote stronger associations.
+pub trait AssociationMeasure: Send + Sync {
+    fn score(&self, table: &ContingencyTable) -> f64;
+}
+
+/// The loglikelihood ratio (G²) test, see `llr::log_likelihood_ratio`. This is the default
+/// measure and leverages a precomputed table of logarithms.
+pub struct LogLikelihoodRatio {
+    logarithms: Vec<f64>,
+}
+
+impl LogLikelihoodRatio {
+    /// Precomputes the logarithms for row and column sums up to and including `max_sum`, but at
+    /// most for the first 2^20 sums
+    pub fn new(max_sum: usize) -> Self {
+        let table_size = (max_sum + 1).min(llr::MAX_LOGARITHMS_TABLE_SIZE);
+        LogLikelihoodRatio { logarithms: llr::logarithms_table(table_size) }
+    }
+}
+
+impl AssociationMeasure for LogLikelihoodRatio {
+    fn score(&self, table: &ContingencyTable) -> f64 {
+        llr::log_likelihood_ratio(table.k11, table.k12, table.k21, table.k22, &self.logarithms)
+    }
+}
+
+/// Signed square root of the loglikelihood ratio, see `llr::signed_root_log_likelihood_ratio`.
+/// Positive for items which cooccur more often than expected, negative otherwise.
+pub struct SignedRootLogLikelihoodRatio {
+    logarithms: Vec<f64>,
+}
+
+impl SignedRootLogLikelihoodRatio {
+    /// Precomputes the logarithms for row and column sums up to and including `max_sum`, but at
+    /// most for the first 2^20 sums
+    pub fn new(max_sum: usize) -> Self {
+        let table_size = (max_sum + 1).min(llr::MAX_LOGARITHMS_TABLE_SIZE);
+        SignedRootLogLikelihoodRatio { logarithms: llr::logarithms_table(table_size) }
+    }
+}
+
+impl AssociationMeasure for SignedRootLogLikelihoodRatio {
+    fn score(&self, table: &ContingencyTable) -> f64 {
+        llr::signed_root_log_likelihood_ratio(
+            table.k11,
+            table.k12,
+            table.k21,
+            table.k22,
+            &self.logarithms,
+        )
+    }
+}
+
+/// Jaccard coefficient of the cooccurrences: the number of cooccurrences of both items, divided by
+/// the number of cooccurrences involving either item (`k11 / (k11 + k12 + k21)`).
+///
+/// Note that this is not the Jaccard coefficient of the sets of users who interacted with the
+/// items. The row sums count every user of an item once per other item in their history, so that
+/// users with long histories weigh more than in the textbook definition.
+pub struct Jaccard;
+
+impl AssociationMeasure for Jaccard {
+    fn score(&self, table: &ContingencyTable) -> f64 {
+        table.k11 as f64 / (table.k11 + table.k12 + table.k21) as f64
+    }
+}
+
+/// Cosine-style normalisation of the cooccurrences: the number of cooccurrences of both items,
+/// divided by the geometric mean of their row sums in the cooccurrence matrix
+/// (`k11 / sqrt((k11 + k12) * (k11 + k21))`).
+///
+/// Note that this is not the cosine similarity of the binary interaction vectors of the items,
+/// which would normalise by the numbers of users of the items instead of their row sums, see
+/// `Jaccard`.
+pub struct Cosine;
+
+impl AssociationMeasure for Cosine {
+    fn score(&self, table: &ContingencyTable) -> f64 {
+        table.k11 as f64 / ((table.row_sum() * table.column_sum()) as f64).sqrt()
+    }
+}
+
+/// Pointwise mutual information: the logarithm of the ratio between the observed and the expected
+/// number of cooccurrences.
+pub struct PointwiseMutualInformation;
+
+impl AssociationMeasure for PointwiseMutualInformation {
+    fn score(&self, table: &ContingencyTable) -> f64 {
+        Lift.score(table).ln()
+    }
+}
+
+/// Normalized pointwise mutual information, which is bounded by 1 for items which always cooccur.
+pub struct NormalizedPointwiseMutualInformation;
+
+impl AssociationMeasure for NormalizedPointwiseMutualInformation {
+    fn score(&self, table: &ContingencyTable) -> f64 {
+        let joint_probability = table.k11 as f64 / table.total() as f64;
+        if joint_probability >= 1.0 {
+            1.0
+        } else {
+            PointwiseMutualInformation.score(table) / -joint_probability.ln()
+        }
+    }
+}
+
+/// Lift: the ratio between the observed and the expected number of cooccurrences.
+pub struct Lift;
+
+impl AssociationMeasure for Lift {
+    fn score(&self, table: &ContingencyTable) -> f64 {
+        (table.k11 as f64 * table.total() as f64)
+            / (table.row_sum() as f64 * table.column_sum() as f64)
+    }
+}
+
+/// Conditional probability P(B|A) of observing the other item B, given the item A.
+pub struct ConditionalProbability;
+
+impl AssociationMeasure for ConditionalProbability {
+    fn score(&self, table: &ContingencyTable) -> f64 {
+        table.k11 as f64 / table.row_sum() as f64
+    }
+}
+
+/// p-value of a loglikelihood ratio, which asymptotically follows a chi-squared distribution with
+/// one degree of freedom if the items are independent.
+pub fn p_value_of_llr(llr: f64) -> f64 {
+    erfc((llr.max(0.0) / 2.0).sqrt())
+}
+
+/// Smallest loglikelihood ratio whose p-value does not exceed `max_p_value`, e.g., to use as a
+/// minimum score for the indicators. We find it via bisection, as the p-value is monotonically
+/// decreasing in the loglikelihood ratio.
+pub fn llr_for_p_value(max_p_value: f64) -> f64 {
+
+    let (mut low, mut high) = (0.0, 1.0);
+    while p_value_of_llr(high) > max_p_value && high < 1e6 {
+        high *= 2.0;
+    }
+
+    for _ in 0..100 {
+        let middle = (low + high) / 2.0;
+        if p_value_of_llr(middle) > max_p_value {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+
+    high
+}
+
+/// Complementary error function with a fractional error below 1.2e-7, based on a Chebyshev
+/// approximation from 'Numerical Recipes in C'.
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+
+    let polynomial = -z * z - 1.265_512_23 + t * (1.000_023_68 + t * (0.374_091_96
+        + t * (0.096_784_18 + t * (-0.186_288_06 + t * (0.278_868_07 + t * (-1.135_203_98
+        + t * (1.488_515_87 + t * (-0.822_152_23 + t * 0.170_872_77))))))));
+
+    let result = t * polynomial.exp();
+
+    if x >= 0.0 { result } else { 2.0 - result }
+}
+
+/// The association measures shipped with recoreco, e.g., for selection on the command line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Measure {
+    LogLikelihoodRatio,
+    SignedRootLogLikelihoodRatio,
+    Jaccard,
+    Cosine,
+    PointwiseMutualInformation,
+    NormalizedPointwiseMutualInformation,
+    Lift,
+    ConditionalProbability,
+}
+
+impl Measure {
+
+    /// All available measures
+    pub const ALL: [Measure; 8] = [
+        Measure::LogLikelihoodRatio,
+        Measure::SignedRootLogLikelihoodRatio,
+        Measure::Jaccard,
+        Measure::Cosine,
+        Measure::PointwiseMutualInformation,
+        Measure::NormalizedPointwiseMutualInformation,
+        Measure::Lift,
+        Measure::ConditionalProbability,
+    ];
+
+    /// Short name of the measure, as accepted by `Measure::from_str`
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Measure::LogLikelihoodRatio => "llr",
+            Measure::SignedRootLogLikelihoodRatio => "signed-llr",
+            Measure::Jaccard => "jaccard",
+            Measure::Cosine => "cosine",
+            Measure::PointwiseMutualInformation => "pmi",
+            Measure::NormalizedPointwiseMutualInformation => "npmi",
+            Measure::Lift => "lift",
+            Measure::ConditionalProbability => "conditional-probability",
+        }
+    }
+
+    /// Instantiates the measure. `max_sum` is an upper bound for the row and column sums in the
+    /// contingency tables to score, which allows the loglikelihood ratio to precompute logarithms.
+    pub fn instantiate(&self, max_sum: usize) -> Box<dyn AssociationMeasure> {
+        match *self {
+            Measure::LogLikelihoodRatio => Box::new(LogLikelihoodRatio::new(max_sum)),
+            Measure::SignedRootLogLikelihoodRatio => {
+                Box::new(SignedRootLogLikelihoodRatio::new(max_sum))
+            },
+            Measure::Jaccard => Box::new(Jaccard),
+            Measure::Cosine => Box::new(Cosine),
+            Measure::PointwiseMutualInformation => Box::new(PointwiseMutualInformation),
+            Measure::NormalizedPointwiseMutualInformation => {
+                Box::new(NormalizedPointwiseMutualInformation)
+            },
+            Measure::Lift => Box::new(Lift),
+            Measure::ConditionalProbability => Box::new(ConditionalProbability),
+        }
+    }
+}
+
+impl fmt::Display for Measure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Measure {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Measure::ALL
+            .iter()
+            .find(|measure| measure.name() == name)
+            .cloned()
+            .ok_or_else(|| {
+                let names: Vec<&str> = Measure::ALL.iter().map(|measure| measure.name()).collect();
+                format!("Unknown measure '{}', expected one of {}", name, names.join(", "))
+            })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use measures::*;
+
+    fn close_enough_to(value: f64, expected: f64) -> bool {
+        (value - expected).abs() < 0.0001
+    }
+
+    // A and B cooccur 10 times, A occurs 40 times, B occurs 20 times, 200 cooccurrences overall
+    const TABLE: ContingencyTable = ContingencyTable { k11: 10, k12: 30, k21: 10, k22: 150 };
+
+    #[test]
+    fn llr() {
+        let measure = Measure::LogLikelihoodRatio.instantiate(1000);
+        let expected = ::llr::log_likelihood_ratio(10, 30, 10, 150, &::llr::logarithms_table(1000));
+        assert!(close_enough_to(measure.score(&TABLE), expected));
+    }
+
+    #[test]
+    fn jaccard() {
+        assert!(close_enough_to(Jaccard.score(&TABLE), 10.0 / 50.0));
+    }
+
+    #[test]
+    fn cosine() {
+        assert!(close_enough_to(Cosine.score(&TABLE), 10.0 / (40.0f64 * 20.0).sqrt()));
+    }
+
+    #[test]
+    fn lift_and_pmi() {
+        // We observe 10 cooccurrences, but only expect 40 * 20 / 200 = 4
+        assert!(close_enough_to(Lift.score(&TABLE), 2.5));
+        assert!(close_enough_to(PointwiseMutualInformation.score(&TABLE), 2.5f64.ln()));
+        assert!(close_enough_to(
+            NormalizedPointwiseMutualInformation.score(&TABLE),
+            2.5f64.ln() / -(10.0f64 / 200.0).ln()
+        ));
+    }
+
+    #[test]
+    fn conditional_probability() {
+        assert!(close_enough_to(ConditionalProbability.score(&TABLE), 10.0 / 40.0));
+    }
+
+    #[test]
+    fn polarity() {
+        assert_eq!(TABLE.polarity(), Polarity::Positive);
+
+        // A and B cooccur once, but we expect 40 * 20 / 200 = 4 cooccurrences
+        let negative = ContingencyTable { k11: 1, k12: 39, k21: 19, k22: 141 };
+        assert_eq!(negative.polarity(), Polarity::Negative);
+        assert!(Polarity::Negative.admits(&negative));
+        assert!(!Polarity::Positive.admits(&negative));
+        assert!(Polarity::Any.admits(&negative));
+
+        let signed_llr = Measure::SignedRootLogLikelihoodRatio.instantiate(1000);
+        assert!(signed_llr.score(&TABLE) > 0.0);
+        assert!(signed_llr.score(&negative) < 0.0);
+    }
+
+    #[test]
+    fn p_values() {
+        // Critical values of the chi-squared distribution with one degree of freedom
+        assert!(close_enough_to(p_value_of_llr(3.841), 0.05));
+        assert!(close_enough_to(p_value_of_llr(10.828), 0.001));
+        assert!(close_enough_to(p_value_of_llr(0.0), 1.0));
+
+        assert!((llr_for_p_value(0.05) - 3.841).abs() < 0.001);
+        assert!((llr_for_p_value(0.001) - 10.828).abs() < 0.001);
+    }
+
+    #[test]
+    fn measures_from_names() {
+        for measure in Measure::ALL.iter() {
+            assert_eq!(measure.name().parse::<Measure>(), Ok(*measure));
+        }
+        assert!("unknown".parse::<Measure>().is_err());
+    }
+}
@@ -0,0 +1,1809 @@
+//! ## Stateful indicator computation for real-time updates
+//!
+//! The `IndicatorModel` owns the downsampled interaction history, the item cooccurrence matrix and
+//! its statistics. It accepts new interactions at any time, keeps track of the items whose
+//! cooccurrences changed, and re-scores only these 'dirty' items when asked to refresh its
+//! indicators. This allows us to keep the indicators up to date without replaying the whole history
+//! of interactions, as in the 'real-time puppies and ponies' architecture.
+//!
+/*
+ * RecoReco
+ * Copyright (C) 2018 Sebastian Schelter
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::io;
+use std::io::prelude::*;
+use std::collections::BinaryHeap;
+use std::ops::Range;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+
+use fnv::FnvHashSet;
+use rayon::prelude::*;
+
+use config::IndicatorConfig;
+use cooccurrences::CooccurrenceCounter;
+use error::Error;
+
+use llr::ScoredItem;
+use measures::{AssociationMeasure, ContingencyTable, Measure, Polarity};
+use sampling::{Downsampler, Sample, SamplingRng};
+use snapshot;
+use stats::DataDictionary;
+use types;
+use types::{CsrMatrix, CsrRowIter, DenseVector, SparseVector, SparseVectorIter, SparseMatrix,
+    IndicatorMatrix};
+use weights::Weighting;
+
+/// Incrementally updatable indicator model. Users and items are identified by their internal
+/// indexes (e.g., as assigned by a `recoreco::stats::DataDictionary`), the model grows on demand
+/// if it encounters a user or item index that it has not seen before.
+///
+/// Note that re-scoring only the dirty items is an approximation: the scores of the remaining
+/// items are not updated to reflect the growing overall number of observed cooccurrences.
+///
+/// # Examples
+///
+/// ```
+/// extern crate recoreco;
+/// use recoreco::model::IndicatorModel;
+/// use recoreco::DEFAULT_SEED;
+///
+/// let mut model = IndicatorModel::new(3, 4, 10, 500, 500, DEFAULT_SEED).unwrap();
+///
+/// model.update(0, 0);
+/// model.update(0, 1);
+/// model.update(1, 0);
+///
+/// /* Only the items which were touched by the new interactions are re-scored */
+/// model.refresh();
+///
+/// /* New interactions can arrive at any time, also for previously unseen users and items */
+/// model.update(3, 1);
+/// model.update(3, 4);
+/// model.refresh();
+///
+/// for (item_index, indicators_for_item) in model.indicators().iter() {
+///     println!("Item {} has {} indicators", item_index, indicators_for_item.len());
+/// }
+/// ```
+pub struct IndicatorModel {
+    // Criteria for selecting the indicators among the scored items
+    selection: Selection,
+
+    // Scores the contingency tables of pairs of items, `measure_kind` is `None` for custom measures
+    measure: Box<dyn AssociationMeasure>,
+    measure_kind: Option<Measure>,
+
+    // Downsampled history matrix A, and the seed which its sampling started from
+    samples_of_a: Downsampler,
+    seed: u64,
+
+    // Cooccurrence matrix C
+    c: Cooccurrences,
+    row_sums_of_c: DenseVector,
+    num_cooccurrences_observed: u64,
+
+    // Downsampled history matrix B of the secondary interactions, if C holds cross-occurrences
+    cross: Option<CrossOccurrences>,
+
+    items_to_rescore: FnvHashSet<u32>,
+    indicators: IndicatorMatrix,
+    negative_indicators: Option<IndicatorMatrix>,
+}
+
+impl IndicatorModel {
+
+    /// Creates an empty model, preallocated for `num_users` users and `num_items` items.
+    ///
+    /// * `num_indicators_per_item` - the number of highly associated items to compute per item (use 10 as default)
+    /// * `f_max` - the maximum number of interactions to account for per item (use 500 as default)
+    /// * `k_max` - the maximum number of interactions to account for per user (use 500 as default)
+    /// * `seed` - the seed for the random downsampling of the interactions (use `recoreco::DEFAULT_SEED` as default)
+    ///
+    /// Fails with `Error::Config` if one of the numbers is zero, and with `Error::Overflow` if
+    /// the product of `f_max` and `k_max` exceeds `u32::MAX`, see `with_config`.
+    pub fn new(
+        num_users: usize,
+        num_items: usize,
+        num_indicators_per_item: usize,
+        f_max: u32,
+        k_max: u32,
+        seed: u64,
+    ) -> Result<Self, Error> {
+
+        let config = IndicatorConfig::builder()
+            .num_indicators_per_item(num_indicators_per_item)
+            .max_interactions_per_item(f_max)
+            .max_interactions_per_user(k_max)
+            .seed(seed)
+            .build()?;
+
+        Ok(IndicatorModel::with_config(num_users, num_items, &config))
+    }
+
+    /// Creates an empty model like `new`, and takes the number of indicators, the maximum numbers
+    /// of interactions per user and item, the seed, the measure and the thresholds from `config`.
+    pub fn with_config(num_users: usize, num_items: usize, config: &IndicatorConfig) -> Self {
+
+        let f_max = config.max_interactions_per_item();
+        let k_max = config.max_interactions_per_user();
+
+        let mut model = IndicatorModel {
+            selection: Selection::top(config.num_indicators_per_item()),
+            measure: config.measure().instantiate(max_sum_of_cooccurrences(f_max, k_max)),
+            measure_kind: Some(config.measure()),
+            samples_of_a: Downsampler::new(num_users, num_items, f_max, k_max, config.seed()),
+            seed: config.seed(),
+            c: Cooccurrences::Incremental(types::new_sparse_matrix(num_items)),
+            row_sums_of_c: types::new_dense_vector(num_items),
+            num_cooccurrences_observed: 0,
+            cross: None,
+            items_to_rescore: FnvHashSet::default(),
+            indicators: IndicatorMatrix::new(num_items),
+            negative_indicators: None,
+        };
+
+        model.configure(config);
+        model
+    }
+
+    /// Creates an empty model like `with_config`, which computes cross-indicators between two types
+    /// of interactions, e.g., views and purchases. Primary interactions, e.g., purchases, are
+    /// registered via `update`, and secondary interactions, e.g., views, via `update_secondary`.
+    /// The model counts the cross-occurrences between the secondary and the primary items, so
+    /// that the indicators of an item from the secondary interactions are items from the primary
+    /// interactions, in the sense of _'people who viewed X went on to buy Y'_. In contrast to
+    /// regular indicators, an item can be an indicator for itself.
+    ///
+    /// Both types of interactions are downsampled separately with the same limits. Cross-indicator
+    /// models are always updated sequentially, and cannot be written to a snapshot.
+    pub fn cross_with_config(num_users: usize, num_items: usize, config: &IndicatorConfig) -> Self {
+
+        let samples_of_b = Downsampler::new(
+            num_users,
+            num_items,
+            config.max_interactions_per_item(),
+            config.max_interactions_per_user(),
+            config.seed().wrapping_add(1),
+        );
+
+        let mut model = IndicatorModel::with_config(num_users, num_items, config);
+        model.cross = Some(CrossOccurrences {
+            samples_of_b,
+            column_sums_of_c: types::new_dense_vector(num_items),
+        });
+        model
+    }
+
+    /// Takes the measure and the thresholds from `config`, which are not part of a snapshot, e.g.,
+    /// for a restored model. The number of indicators, the maximum numbers of interactions per
+    /// user and item and the seed are fixed when the model is created. Items are only re-scored
+    /// on the next refresh if their selection changes.
+    pub fn configure(&mut self, config: &IndicatorConfig) {
+        if self.measure_kind != Some(config.measure()) {
+            self.set_measure(config.measure());
+        }
+        self.set_polarity(config.polarity());
+        self.set_min_score(config.min_score());
+        self.set_min_cooccurrences(config.min_cooccurrences());
+    }
+
+    /// Scores item pairs with one of the built-in association measures. All items are re-scored
+    /// on the next refresh. Note that the measure is not part of a snapshot.
+    pub fn set_measure(&mut self, measure: Measure) {
+        let max_sum = max_sum_of_cooccurrences(self.samples_of_a.f_max, self.samples_of_a.k_max);
+        self.set_association_measure(measure.instantiate(max_sum));
+        self.measure_kind = Some(measure);
+    }
+
+    /// Scores item pairs with a custom association measure, see `set_measure`.
+    pub fn set_association_measure(&mut self, measure: Box<dyn AssociationMeasure>) {
+        self.measure = measure;
+        self.measure_kind = None;
+        self.mark_all_items_for_rescoring();
+    }
+
+    /// Only selects associations of the given polarity as indicators, e.g., `Polarity::Positive`
+    /// to ignore pairs of items which cooccur less often than expected. Defaults to
+    /// `Polarity::Any`. Note that the polarity is not part of a snapshot.
+    pub fn set_polarity(&mut self, polarity: Polarity) {
+        if polarity != self.selection.polarity {
+            self.selection.polarity = polarity;
+            self.mark_all_items_for_rescoring();
+        }
+    }
+
+    /// Only selects items with an association score of at least `min_score` as indicators, so
+    /// that items can have fewer indicators than requested if the evidence is weak. For the
+    /// loglikelihood ratio, `recoreco::measures::llr_for_p_value` gives the score corresponding
+    /// to a significance level. Note that the threshold is not part of a snapshot.
+    pub fn set_min_score(&mut self, min_score: f64) {
+        if min_score != self.selection.min_score {
+            self.selection.min_score = min_score;
+            self.mark_all_items_for_rescoring();
+        }
+    }
+
+    /// Only selects items which cooccur at least `min_cooccurrences` times with an item as its
+    /// indicators (defaults to 1). Note that the threshold is not part of a snapshot.
+    pub fn set_min_cooccurrences(&mut self, min_cooccurrences: u32) {
+        if min_cooccurrences != self.selection.min_cooccurrences {
+            self.selection.min_cooccurrences = min_cooccurrences;
+            self.mark_all_items_for_rescoring();
+        }
+    }
+
+    /// Additionally maintains the top negative associations per item (the items which cooccur
+    /// less often than expected), e.g., for 'do not recommend together' rules. These are ranked by
+    /// the magnitude of their score, and also hold this magnitude as score. Disabled by default.
+    pub fn set_negative_indicators(&mut self, enabled: bool) {
+        if enabled != self.negative_indicators.is_some() {
+            self.negative_indicators = if enabled {
+                Some(IndicatorMatrix::new(self.num_items()))
+            } else {
+                None
+            };
+            self.mark_all_items_for_rescoring();
+        }
+    }
+
+    /// Decides how the weights of the interactions influence the downsampling, see
+    /// `recoreco::weights::Weighting`. Defaults to `Weighting::Unweighted`. The weighting has to be
+    /// chosen before the model sees its first interaction, otherwise we fail with
+    /// `Error::Config`.
+    pub fn set_weighting(&mut self, weighting: Weighting) -> Result<(), Error> {
+        if self.has_seen_interactions() && weighting != self.samples_of_a.weighting {
+            return Err(Error::Config(String::from(
+                "The weighting cannot be changed after the first interaction",
+            )));
+        }
+        self.samples_of_a.weighting = weighting;
+        Ok(())
+    }
+
+    /// Returns the weighting of the interactions, see `set_weighting`.
+    pub fn weighting(&self) -> Weighting {
+        self.samples_of_a.weighting
+    }
+
+    /// Returns the seed which the downsampling started from. A model restored from a snapshot
+    /// keeps the seed of the model which wrote the snapshot, and continues with the state of its
+    /// random number generator.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Returns the number of users known to the model.
+    pub fn num_users(&self) -> usize {
+        self.samples_of_a.samples.len()
+    }
+
+    /// Returns the number of items known to the model.
+    pub fn num_items(&self) -> usize {
+        self.c.num_rows()
+    }
+
+    /// Returns the overall number of cooccurrences observed in the downsampled interactions.
+    pub fn num_cooccurrences_observed(&self) -> u64 {
+        self.num_cooccurrences_observed
+    }
+
+    /// Returns the number of repeated interactions of users with items which were already in their
+    /// downsampled histories, and were therefore skipped.
+    pub fn num_repeated_interactions(&self) -> u64 {
+        let num_repetitions_of_b = self.cross.as_ref()
+            .map_or(0, |cross| cross.samples_of_b.num_repetitions);
+        self.samples_of_a.num_repetitions + num_repetitions_of_b
+    }
+
+    /// Returns the number of items which need to be re-scored on the next refresh.
+    pub fn num_dirty_items(&self) -> usize {
+        self.items_to_rescore.len()
+    }
+
+    /// Returns the indicators as of the last refresh.
+    pub fn indicators(&self) -> &IndicatorMatrix {
+        &self.indicators
+    }
+
+    /// Returns the negative indicators as of the last refresh, if enabled via
+    /// `set_negative_indicators`.
+    pub fn negative_indicators(&self) -> Option<&IndicatorMatrix> {
+        self.negative_indicators.as_ref()
+    }
+
+    /// Consumes the model and returns its indicators as of the last refresh.
+    pub fn into_indicators(self) -> IndicatorMatrix {
+        self.indicators
+    }
+
+    /// Registers an interaction between the user with index `user` and the item with index `item`.
+    /// The interaction is incorporated into the downsampled history and the cooccurrence matrix,
+    /// the indicators are only recomputed on the next call to `refresh`. The interaction has a
+    /// weight of one, see `update_weighted`.
+    pub fn update(&mut self, user: u32, item: u32) {
+        self.update_weighted(user, item, 1.0);
+    }
+
+    /// Registers an interaction with the given `weight`, which decides whether the interaction is
+    /// kept in the downsampled history of the user according to the weighting of the model.
+    pub fn update_weighted(&mut self, user: u32, item: u32, weight: f64) {
+
+        self.ensure_item(item);
+
+        let sample = self.samples_of_a.sample_weighted(user, item, weight);
+
+        if self.cross.is_some() {
+            return self.update_cross_occurrences_of_primary(user, item, sample);
+        }
+
+        let item_idx = item as usize;
+
+        let c = self.c.incremental();
+        let row_sums_of_c = &mut self.row_sums_of_c;
+
+        // Retrieve current history sample for interacting user
+        let user_history = self.samples_of_a.history(user);
+
+        match sample {
+
+            Sample::Skipped => {},
+
+            Sample::Appended => {
+
+                // The item has been appended to the user history already
+                let num_items_in_user_history = user_history.len() - 1;
+
+                // Record coocurrences with all other items from user history
+                for other_item in user_history[..num_items_in_user_history].iter() {
+                    c[item_idx].increment(*other_item);
+                    c[*other_item as usize].increment(item);
+                    row_sums_of_c[*other_item as usize] += 1;
+                }
+
+                // Register items for rescoring
+                self.items_to_rescore.extend(user_history.iter());
+
+                // Update statistics for cooccurrence matrix sums
+                row_sums_of_c[item_idx] += num_items_in_user_history as u32;
+                self.num_cooccurrences_observed += 2 * num_items_in_user_history as u64;
+            },
+
+            Sample::Replaced { position, previous_item } => {
+
+                let num_items_in_user_history = user_history.len();
+
+                for (n, other_item) in user_history.iter().enumerate() {
+
+                    if n != position {
+                        // Adjust cooccurrence counts
+                        c[item_idx].increment(*other_item);
+                        c[*other_item as usize].increment(item);
+                        c[previous_item as usize].decrement(*other_item);
+                        c[*other_item as usize].decrement(previous_item);
+                    }
+                }
+
+                // Register items for rescoring
+                self.items_to_rescore.extend(user_history.iter());
+                self.items_to_rescore.insert(previous_item);
+
+                // update cooccurrence matrix sums
+                row_sums_of_c[item_idx] += num_items_in_user_history as u32 - 1;
+                row_sums_of_c[previous_item as usize] -= num_items_in_user_history as u32 - 1;
+            },
+        }
+    }
+
+    /// Registers a secondary interaction between the user with index `user` and the item with
+    /// index `item` for a model which computes cross-indicators, see `cross_with_config`. Secondary
+    /// interactions are unweighted. Fails with `Error::Config` for other models.
+    pub fn update_secondary(&mut self, user: u32, item: u32) -> Result<(), Error> {
+
+        if self.cross.is_none() {
+            return Err(Error::Config(String::from(
+                "Secondary interactions require a model for cross-indicators",
+            )));
+        }
+
+        self.ensure_item(item);
+
+        let cross = self.cross.as_mut().unwrap();
+        let sample = cross.samples_of_b.sample(user, item);
+
+        let c = self.c.incremental();
+        let row_sums_of_c = &mut self.row_sums_of_c;
+
+        // The secondary item cooccurs with every item from the primary history of the user
+        let history_of_a = history_of(&self.samples_of_a, user);
+        let num_items_in_history_of_a = history_of_a.len() as u32;
+
+        match sample {
+
+            Sample::Skipped => {},
+
+            Sample::Appended => {
+                for item_a in history_of_a {
+                    c[item as usize].increment(*item_a);
+                    cross.column_sums_of_c[*item_a as usize] += 1;
+                }
+
+                row_sums_of_c[item as usize] += num_items_in_history_of_a;
+                self.num_cooccurrences_observed += u64::from(num_items_in_history_of_a);
+                self.items_to_rescore.insert(item);
+            },
+
+            Sample::Replaced { previous_item, .. } => {
+                for item_a in history_of_a {
+                    c[item as usize].increment(*item_a);
+                    c[previous_item as usize].decrement(*item_a);
+                }
+
+                row_sums_of_c[item as usize] += num_items_in_history_of_a;
+                row_sums_of_c[previous_item as usize] -= num_items_in_history_of_a;
+                self.items_to_rescore.insert(item);
+                self.items_to_rescore.insert(previous_item);
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Adjusts the cross-occurrences of the secondary items of `user` after `item` was offered to
+    /// the primary history of the user
+    fn update_cross_occurrences_of_primary(&mut self, user: u32, item: u32, sample: Sample) {
+
+        let cross = self.cross.as_mut().unwrap();
+        let c = self.c.incremental();
+        let row_sums_of_c = &mut self.row_sums_of_c;
+
+        let history_of_b = history_of(&cross.samples_of_b, user);
+        let num_items_in_history_of_b = history_of_b.len() as u32;
+
+        match sample {
+
+            Sample::Skipped => {},
+
+            Sample::Appended => {
+                for item_b in history_of_b {
+                    c[*item_b as usize].increment(item);
+                    row_sums_of_c[*item_b as usize] += 1;
+                }
+
+                cross.column_sums_of_c[item as usize] += num_items_in_history_of_b;
+                self.num_cooccurrences_observed += u64::from(num_items_in_history_of_b);
+                self.items_to_rescore.extend(history_of_b.iter());
+            },
+
+            Sample::Replaced { previous_item, .. } => {
+                for item_b in history_of_b {
+                    c[*item_b as usize].increment(item);
+                    c[*item_b as usize].decrement(previous_item);
+                }
+
+                cross.column_sums_of_c[item as usize] += num_items_in_history_of_b;
+                cross.column_sums_of_c[previous_item as usize] -= num_items_in_history_of_b;
+                self.items_to_rescore.extend(history_of_b.iter());
+            },
+        }
+    }
+
+    /// Registers a stream of interactions with string identifiers, which are mapped to internal
+    /// indexes via `data_dict`. Fails if a user or item is unknown to `data_dict`.
+    pub fn update_all<T>(
+        &mut self,
+        interactions: T,
+        data_dict: &DataDictionary,
+    ) -> Result<(), Error>
+    where
+        T: Iterator<Item = (String, String)>
+    {
+        for (user_str, item_str) in interactions {
+
+            let item = data_dict.item_index(&item_str)?;
+            let user = data_dict.user_index(&user_str)?;
+
+            self.update(user, item);
+        }
+        Ok(())
+    }
+
+    /// Registers a stream of secondary interactions with string identifiers for a model which
+    /// computes cross-indicators, see `update_all` and `update_secondary`.
+    pub fn update_all_secondary<T>(
+        &mut self,
+        interactions: T,
+        data_dict: &DataDictionary,
+    ) -> Result<(), Error>
+    where
+        T: Iterator<Item = (String, String)>
+    {
+        for (user_str, item_str) in interactions {
+
+            let item = data_dict.item_index(&item_str)?;
+            let user = data_dict.user_index(&user_str)?;
+
+            self.update_secondary(user, item)?;
+        }
+        Ok(())
+    }
+
+
+    /// Registers a stream of weighted interactions with string identifiers, which are mapped to
+    /// internal indexes via `data_dict`. Interactions which are not admitted by the weighting of
+    /// the model are skipped, as they are not part of a dictionary built via
+    /// `DataDictionary::extend_weighted`.
+    pub fn update_all_weighted<T>(
+        &mut self,
+        interactions: T,
+        data_dict: &DataDictionary,
+    ) -> Result<(), Error>
+    where
+        T: Iterator<Item = (String, String, f64)>
+    {
+        for (user_str, item_str, weight) in interactions {
+            if self.samples_of_a.weighting.admits(weight) {
+
+                let item = data_dict.item_index(&item_str)?;
+                let user = data_dict.user_index(&user_str)?;
+
+                self.update_weighted(user, item, weight);
+            }
+        }
+        Ok(())
+    }
+
+    /// Registers a stream of interactions with string identifiers in a single pass. Previously
+    /// unseen users and items are added to `data_dict` on first sight, and the model grows
+    /// accordingly. Repetitions of an item in the downsampled history of a user are skipped, see
+    /// `num_repeated_interactions`.
+    pub fn register_and_update_all<T>(
+        &mut self,
+        interactions: T,
+        data_dict: &mut DataDictionary,
+    ) -> Result<(), Error>
+    where
+        T: Iterator<Item = (String, String)>
+    {
+        for (user_str, item_str) in interactions {
+            let (user, item) = data_dict.register(user_str, item_str)?;
+            self.update(user, item);
+        }
+        Ok(())
+    }
+
+    /// Registers a stream of interactions between users and items, identified by their internal
+    /// indexes, and counts the cooccurrences on `num_partitions` tasks of the rayon thread pool. We
+    /// first downsample all interactions on the calling thread, so that the limits of `f_max`
+    /// interactions per item and `k_max` interactions per user hold for the whole input, which
+    /// means that sampling is not parallelised. The items are then split into `num_partitions`
+    /// ranges with roughly the same number of cooccurrences, and each task counts the rows of its
+    /// range from the final histories of the users. The rows are disjoint, so that the tasks
+    /// together hold a single cooccurrence matrix, and we get the same counts as sequential
+    /// updates.
+    ///
+    /// Partitioning requires a fresh model, we fall back to sequential updates if the model has
+    /// already seen interactions or computes cross-indicators. Fails with `Error::Io` if a task
+    /// cannot count its range.
+    pub fn update_all_partitioned<T>(
+        &mut self,
+        interactions: T,
+        num_partitions: usize,
+    ) -> Result<(), Error>
+    where
+        T: Iterator<Item = (u32, u32)>
+    {
+        if num_partitions <= 1 || self.requires_sequential_updates() {
+            for (user, item) in interactions {
+                self.update(user, item);
+            }
+            return Ok(());
+        }
+
+        for (user, item) in interactions {
+            self.samples_of_a.sample(user, item);
+        }
+
+        self.count_sums_of_histories();
+        let histories = &self.samples_of_a.samples;
+        let ranges = partition_items(&self.row_sums_of_c, num_partitions);
+
+        let partial_rows = panic::catch_unwind(AssertUnwindSafe(|| {
+            ranges
+                .into_par_iter()
+                .map(|items| count_rows(histories, items))
+                .collect::<Vec<SparseMatrix>>()
+        }));
+
+        let partial_rows = partial_rows
+            .map_err(|_| io::Error::other("A range of items could not be counted"))?;
+
+        self.c = Cooccurrences::Incremental(partial_rows.into_iter().flatten().collect());
+        self.mark_all_items_for_rescoring();
+
+        Ok(())
+    }
+
+    /// Registers a batch of interactions between users and items, identified by their internal
+    /// indexes, and holds at most `memory_budget` bytes worth of cooccurrence counts in memory. We
+    /// first downsample all interactions, and then count the cooccurrences of the final histories
+    /// of the users, which gives the same counts as sequential updates. Partial counts which
+    /// exceed the budget are spilled to disk, and merged into a compact matrix afterwards, see
+    /// `recoreco::cooccurrences::CooccurrenceCounter`. Returns the number of spills.
+    ///
+    /// The budget only bounds the buffer of partial counts. The downsampled histories, the sums of
+    /// the cooccurrence matrix and the final compact matrix stay in memory regardless, and the
+    /// compact matrix is expanded back into hash-based rows on the next update. Counting within a
+    /// budget requires a fresh model, we fall back to sequential updates if the model has already
+    /// seen interactions or computes cross-indicators. Fails with `Error::Io` if the partial counts
+    /// cannot be spilled or merged.
+    pub fn update_all_with_memory_budget<T>(
+        &mut self,
+        interactions: T,
+        memory_budget: usize,
+    ) -> Result<usize, Error>
+    where
+        T: Iterator<Item = (u32, u32)>
+    {
+        if self.requires_sequential_updates() {
+            for (user, item) in interactions {
+                self.update(user, item);
+            }
+            return Ok(0);
+        }
+
+        for (user, item) in interactions {
+            self.samples_of_a.sample(user, item);
+        }
+
+        let num_items = self.count_sums_of_histories();
+
+        // Release the preallocated rows, the counts end up in a compact matrix
+        self.c = Cooccurrences::Incremental(SparseMatrix::new());
+
+        let mut counter = CooccurrenceCounter::new(memory_budget);
+
+        for history in self.samples_of_a.samples.iter() {
+            for (n, item) in history.iter().enumerate() {
+                for (m, other_item) in history.iter().enumerate() {
+                    if n != m {
+                        counter.increment(*item, *other_item)?;
+                    }
+                }
+            }
+        }
+
+        let num_spills = counter.num_spills();
+        self.c = Cooccurrences::Compact(counter.into_matrix(num_items)?);
+        self.mark_all_items_for_rescoring();
+
+        Ok(num_spills)
+    }
+
+    /// Registers a batch of interactions between users and items, identified by their internal
+    /// indexes, without ever materialising the whole cooccurrence matrix. We first downsample all
+    /// interactions, and split the items into `num_partitions` ranges with roughly the same number
+    /// of cooccurrences. Every refresh then counts and scores the rows of one range at a time from
+    /// the final histories of the users, which gives the same indicators as sequential updates.
+    /// Note that the downsampled histories and the sums of the cooccurrence matrix stay in memory.
+    ///
+    /// The whole matrix is materialised again on the next update. Partitioning requires a fresh
+    /// model, we fall back to sequential updates if the model has already seen interactions or
+    /// computes cross-indicators. Fails with `Error::Config` if there are no partitions.
+    pub fn update_all_out_of_core<T>(
+        &mut self,
+        interactions: T,
+        num_partitions: usize,
+    ) -> Result<(), Error>
+    where
+        T: Iterator<Item = (u32, u32)>
+    {
+        if num_partitions == 0 {
+            let problem = String::from("The number of item partitions must be positive");
+            return Err(Error::Config(problem));
+        }
+
+        if self.requires_sequential_updates() {
+            for (user, item) in interactions {
+                self.update(user, item);
+            }
+            return Ok(());
+        }
+
+        for (user, item) in interactions {
+            self.samples_of_a.sample(user, item);
+        }
+
+        let num_items = self.count_sums_of_histories();
+
+        self.c = Cooccurrences::Partitioned { num_items, num_partitions };
+        self.mark_all_items_for_rescoring();
+
+        Ok(())
+    }
+
+    /// Computes the row sums of C and the overall number of cooccurrences from the downsampled
+    /// histories of a fresh model, and returns the number of items
+    fn count_sums_of_histories(&mut self) -> usize {
+
+        let num_items = self.samples_of_a.item_interaction_counts.len().max(self.num_items());
+        self.row_sums_of_c.resize(num_items, 0);
+
+        for history in self.samples_of_a.samples.iter() {
+            let num_items_in_history = history.len() as u32;
+            for item in history {
+                self.row_sums_of_c[*item as usize] += num_items_in_history - 1;
+            }
+            self.num_cooccurrences_observed +=
+                u64::from(num_items_in_history) * u64::from(num_items_in_history.saturating_sub(1));
+        }
+
+        num_items
+    }
+
+    /// Re-scores all items whose cooccurrences changed since the last refresh, and returns the
+    /// number of re-scored items.
+    pub fn refresh(&mut self) -> usize {
+
+        let rescored_items = match self.c {
+            Cooccurrences::Partitioned { num_partitions, .. } => {
+                // Only materialise the rows of one range of items at a time
+                let mut rescored_items = Vec::with_capacity(self.items_to_rescore.len());
+                for items in partition_items(&self.row_sums_of_c, num_partitions) {
+                    let rows = count_rows(&self.samples_of_a.samples, items.clone());
+                    let items_to_rescore: Vec<u32> = self.items_to_rescore
+                        .iter()
+                        .filter(|item| items.contains(item))
+                        .cloned()
+                        .collect();
+                    rescored_items.extend(self.rescore_items(&items_to_rescore, |item| {
+                        rows[(item - items.start) as usize].iter()
+                    }));
+                }
+                rescored_items
+            },
+            _ => {
+                let items_to_rescore: Vec<u32> = self.items_to_rescore.iter().cloned().collect();
+                let c = &self.c;
+                self.rescore_items(&items_to_rescore, |item| c.row(item))
+            },
+        };
+
+        // Place the results at the row of their item, items without cooccurrences keep an empty row
+        let num_items = self.num_items();
+        self.indicators.resize(num_items);
+        if let Some(negative_indicators) = self.negative_indicators.as_mut() {
+            negative_indicators.resize(num_items);
+        }
+
+        for (item, indicators_for_item, negative_indicators_for_item) in rescored_items {
+            self.indicators.set_indicators_for(item, indicators_for_item);
+            if let (Some(negative_indicators), Some(negative_indicators_for_item)) =
+                (self.negative_indicators.as_mut(), negative_indicators_for_item) {
+                negative_indicators.set_indicators_for(item, negative_indicators_for_item);
+            }
+        }
+
+        let num_items_rescored = self.items_to_rescore.len();
+        self.items_to_rescore.clear();
+
+        num_items_rescored
+    }
+
+    /// Computes the top-n indicators (and negative indicators, if enabled) of `items` in parallel,
+    /// the function `row` gives the cooccurrence counts of an item
+    fn rescore_items<F, T>(
+        &self,
+        items: &[u32],
+        row: F,
+    ) -> Vec<(u32, Vec<ScoredItem>, Option<Vec<ScoredItem>>)>
+    where
+        F: Fn(u32) -> T + Sync,
+        T: Iterator<Item = (u32, u32)>,
+    {
+        let row_sums_of_c = &self.row_sums_of_c;
+        let num_cooccurrences_observed = self.num_cooccurrences_observed;
+        let measure = &*self.measure;
+
+        // Cross-occurrences are not symmetric, and an item can indicate itself
+        let (column_sums_of_c, excludes_item) = match self.cross {
+            Some(ref cross) => (&cross.column_sums_of_c, false),
+            None => (row_sums_of_c, true),
+        };
+
+        let selection = self.selection;
+        let negative_selection = if self.negative_indicators.is_some() {
+            Some(Selection { polarity: Polarity::Negative, ..selection })
+        } else {
+            None
+        };
+
+        let rescore_item = |item: u32, selection: &Selection| {
+            rescore(
+                row(item),
+                if excludes_item { Some(item) } else { None },
+                row_sums_of_c[item as usize],
+                column_sums_of_c,
+                num_cooccurrences_observed,
+                measure,
+                selection,
+            )
+        };
+
+        items
+            .par_iter()
+            .map(|item| {
+                let indicators_for_item = rescore_item(*item, &selection);
+                let negative_indicators_for_item = negative_selection
+                    .as_ref()
+                    .map(|negative_selection| rescore_item(*item, negative_selection));
+                (*item, indicators_for_item, negative_indicators_for_item)
+            })
+            .collect()
+    }
+
+    /// Writes the model state to a snapshot, see `recoreco::snapshot`
+    pub(crate) fn write_state<W: Write>(&self, out: &mut W) -> io::Result<()> {
+
+        if self.cross.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Models for cross-indicators cannot be written to a snapshot",
+            ));
+        }
+
+        let samples_of_a = &self.samples_of_a;
+
+        snapshot::write_u64(out, self.selection.n as u64)?;
+        snapshot::write_u32(out, samples_of_a.f_max)?;
+        snapshot::write_u32(out, samples_of_a.k_max)?;
+        snapshot::write_u64(out, self.seed)?;
+
+        for word in samples_of_a.rng.state().iter() {
+            snapshot::write_u32(out, *word)?;
+        }
+
+        snapshot::write_weighting(out, samples_of_a.weighting)?;
+
+        snapshot::write_u32(out, self.num_users() as u32)?;
+        for user_idx in 0..self.num_users() {
+            snapshot::write_u32(out, samples_of_a.user_non_sampled_interaction_counts[user_idx])?;
+            snapshot::write_u32(out, samples_of_a.user_interaction_counts[user_idx])?;
+            snapshot::write_items(out, &samples_of_a.samples[user_idx])?;
+            if samples_of_a.weighting.is_prioritised() {
+                for priority in &samples_of_a.priorities[user_idx] {
+                    snapshot::write_f64(out, *priority)?;
+                }
+            }
+        }
+
+        // Rows which are not materialised are counted one range of items at a time
+        let num_partitions = match self.c {
+            Cooccurrences::Partitioned { num_partitions, .. } => num_partitions,
+            _ => 1,
+        };
+
+        snapshot::write_u32(out, self.num_items() as u32)?;
+        for items in partition_items(&self.row_sums_of_c, num_partitions) {
+
+            let counted_rows = match self.c {
+                Cooccurrences::Partitioned { .. } => {
+                    Some(count_rows(&samples_of_a.samples, items.clone()))
+                },
+                _ => None,
+            };
+
+            for item in items.clone() {
+                let item_idx = item as usize;
+                snapshot::write_u32(out, samples_of_a.item_interaction_counts[item_idx])?;
+                snapshot::write_u32(out, self.row_sums_of_c[item_idx])?;
+
+                let (row_len, row) = match counted_rows {
+                    Some(ref rows) => {
+                        let row = &rows[(item - items.start) as usize];
+                        (row.len(), CooccurrencesRowIter::Incremental(row.iter()))
+                    },
+                    None => (self.c.row_len(item), self.c.row(item)),
+                };
+
+                snapshot::write_u32(out, row_len as u32)?;
+                for (other_item, count) in row {
+                    snapshot::write_u32(out, other_item)?;
+                    snapshot::write_u32(out, count)?;
+                }
+
+                let indicators_for_item = self.indicators.indicators_for(item);
+                snapshot::write_u32(out, indicators_for_item.len() as u32)?;
+                for scored_item in indicators_for_item {
+                    snapshot::write_u32(out, scored_item.item)?;
+                    snapshot::write_f64(out, scored_item.score)?;
+                }
+            }
+        }
+
+        snapshot::write_u64(out, self.num_cooccurrences_observed)?;
+
+        let items_to_rescore: Vec<u32> = self.items_to_rescore.iter().cloned().collect();
+        snapshot::write_items(out, &items_to_rescore)
+    }
+
+    /// Restores the model state from a snapshot, see `recoreco::snapshot`
+    pub(crate) fn read_state<R: Read>(input: &mut R) -> io::Result<Self> {
+
+        let num_indicators_per_item = snapshot::read_u64(input)? as usize;
+        let f_max = snapshot::read_u32(input)?;
+        let k_max = snapshot::read_u32(input)?;
+        let seed = snapshot::read_u64(input)?;
+
+        let mut rng_state = [0u32; 4];
+        for word in rng_state.iter_mut() {
+            *word = snapshot::read_u32(input)?;
+        }
+
+        let mut model = IndicatorModel::new(0, 0, num_indicators_per_item, f_max, k_max, seed)
+            .map_err(|failure| snapshot::invalid_data(failure.to_string()))?;
+        model.samples_of_a.rng = SamplingRng::from_state(rng_state);
+        model.samples_of_a.weighting = snapshot::read_weighting(input)?;
+
+        let num_users = snapshot::read_u32(input)?;
+        for _ in 0..num_users {
+            let samples_of_a = &mut model.samples_of_a;
+            samples_of_a.user_non_sampled_interaction_counts.push(snapshot::read_u32(input)?);
+            samples_of_a.user_interaction_counts.push(snapshot::read_u32(input)?);
+            let history = snapshot::read_items(input)?;
+
+            let mut priorities = Vec::new();
+            if samples_of_a.weighting.is_prioritised() {
+                for _ in 0..history.len() {
+                    priorities.push(snapshot::read_f64(input)?);
+                }
+            }
+
+            samples_of_a.samples.push(history);
+            samples_of_a.priorities.push(priorities);
+        }
+
+        let num_items = snapshot::read_u32(input)?;
+        for item in 0..num_items {
+            model.samples_of_a.item_interaction_counts.push(snapshot::read_u32(input)?);
+            model.row_sums_of_c.push(snapshot::read_u32(input)?);
+
+            let num_entries = snapshot::read_u32(input)? as usize;
+            let mut row = SparseVector::with_capacity(snapshot::capacity_for(num_entries));
+            for _ in 0..num_entries {
+                let other_item = snapshot::read_u32(input)?;
+                row.insert(other_item, snapshot::read_u32(input)?);
+            }
+            model.c.incremental().push(row);
+
+            let num_indicators = snapshot::read_u32(input)? as usize;
+            let mut indicators_for_item =
+                Vec::with_capacity(snapshot::capacity_for(num_indicators));
+            for _ in 0..num_indicators {
+                let other_item = snapshot::read_u32(input)?;
+                let score = snapshot::read_f64(input)?;
+                indicators_for_item.push(ScoredItem { item: other_item, score });
+            }
+            model.indicators.resize(item as usize + 1);
+            model.indicators.set_indicators_for(item, indicators_for_item);
+        }
+
+        model.num_cooccurrences_observed = snapshot::read_u64(input)?;
+        model.items_to_rescore = snapshot::read_items(input)?.into_iter().collect();
+
+        model.check_state().map_err(|problem| {
+            snapshot::invalid_data(format!("Inconsistent model state in snapshot: {}", problem))
+        })?;
+
+        Ok(model)
+    }
+
+    /// Checks the invariants of a restored state which later updates and refreshes rely on, so
+    /// that a corrupt snapshot is rejected instead of causing a panic later on
+    fn check_state(&mut self) -> Result<(), String> {
+
+        let num_items = self.num_items();
+        let samples_of_a = &self.samples_of_a;
+        let max_sum = max_sum_of_cooccurrences(samples_of_a.f_max, samples_of_a.k_max);
+
+        for history in &samples_of_a.samples {
+            if history.len() > samples_of_a.k_max as usize {
+                return Err(String::from("a history exceeds the maximum number of interactions"));
+            }
+            if history.iter().any(|item| *item as usize >= num_items) {
+                return Err(String::from("a history refers to an unknown item"));
+            }
+        }
+
+        let row_sums_of_c = &self.row_sums_of_c;
+        if row_sums_of_c.iter().any(|row_sum| *row_sum as usize > max_sum) {
+            return Err(String::from("a row sum exceeds the maximum number of cooccurrences"));
+        }
+
+        let total: u64 = row_sums_of_c.iter().map(|row_sum| u64::from(*row_sum)).sum();
+        if total != self.num_cooccurrences_observed {
+            return Err(String::from("the row sums do not match the observed cooccurrences"));
+        }
+
+        for (item, row) in self.c.incremental().iter().enumerate() {
+            for (other_item, count) in row.iter() {
+                let is_consistent = (other_item as usize) < num_items
+                    && count > 0
+                    && count <= row_sums_of_c[item]
+                    && count <= row_sums_of_c[other_item as usize];
+                if !is_consistent {
+                    return Err(String::from("a cooccurrence count exceeds its row sums"));
+                }
+            }
+        }
+
+        let refers_to_unknown_item = self.indicators
+            .iter()
+            .flat_map(|(_, indicators_for_item)| indicators_for_item.iter())
+            .map(|scored_item| scored_item.item)
+            .chain(self.items_to_rescore.iter().cloned())
+            .any(|item| item as usize >= num_items);
+
+        if refers_to_unknown_item {
+            return Err(String::from("an indicator or a dirty item refers to an unknown item"));
+        }
+
+        Ok(())
+    }
+
+    fn has_seen_interactions(&self) -> bool {
+        let has_seen = |samples: &Downsampler| {
+            samples.user_non_sampled_interaction_counts.iter().any(|count| *count > 0)
+        };
+        has_seen(&self.samples_of_a) || self.cross.as_ref().is_some_and(|cross| {
+            has_seen(&cross.samples_of_b)
+        })
+    }
+
+    /// Whether batches of interactions have to be registered one at a time, because the model
+    /// already holds counts or counts cross-occurrences
+    fn requires_sequential_updates(&self) -> bool {
+        self.has_seen_interactions() || self.cross.is_some()
+    }
+
+    fn mark_all_items_for_rescoring(&mut self) {
+        let row_sums_of_c = &self.row_sums_of_c;
+        let items_with_cooccurrences = (0..row_sums_of_c.len() as u32)
+            .filter(|item| row_sums_of_c[*item as usize] > 0);
+        self.items_to_rescore.extend(items_with_cooccurrences);
+    }
+
+    /// Counts the whole cooccurrence matrix from the downsampled histories, if its rows are only
+    /// counted on demand
+    fn materialise(&mut self) {
+        if let Cooccurrences::Partitioned { num_items, .. } = self.c {
+            let rows = count_rows(&self.samples_of_a.samples, 0..num_items as u32);
+            self.c = Cooccurrences::Incremental(rows);
+        }
+    }
+
+    fn ensure_item(&mut self, item: u32) {
+        self.materialise();
+        self.samples_of_a.ensure_item(item);
+        let num_items = item as usize + 1;
+        if num_items > self.num_items() {
+            self.c.incremental().resize(num_items, SparseVector::default());
+            self.row_sums_of_c.resize(num_items, 0);
+            if let Some(ref mut cross) = self.cross {
+                cross.column_sums_of_c.resize(num_items, 0);
+            }
+        }
+    }
+}
+
+/// The downsampled secondary interactions and the column sums of the cross-occurrence matrix of a
+/// model which computes cross-indicators, see `IndicatorModel::cross_with_config`
+struct CrossOccurrences {
+    samples_of_b: Downsampler,
+    column_sums_of_c: DenseVector,
+}
+
+/// Returns the downsampled history of `user`, which is empty for users without interactions
+fn history_of(samples: &Downsampler, user: u32) -> &[u32] {
+    samples.samples.get(user as usize).map_or(&[], Vec::as_slice)
+}
+
+/// Storage of the cooccurrence matrix C: hash-based rows, which support incremental updates, a
+/// compact matrix computed from a batch of interactions via `update_all_with_memory_budget`, or
+/// rows which are counted from the histories for a range of items at a time, see
+/// `update_all_out_of_core`
+enum Cooccurrences {
+    Incremental(SparseMatrix),
+    Compact(CsrMatrix),
+    Partitioned { num_items: usize, num_partitions: usize },
+}
+
+impl Cooccurrences {
+
+    fn num_rows(&self) -> usize {
+        match *self {
+            Cooccurrences::Incremental(ref c) => c.len(),
+            Cooccurrences::Compact(ref c) => c.num_rows(),
+            Cooccurrences::Partitioned { num_items, .. } => num_items,
+        }
+    }
+
+    fn row_len(&self, item: u32) -> usize {
+        match *self {
+            Cooccurrences::Incremental(ref c) => c[item as usize].len(),
+            Cooccurrences::Compact(ref c) => c.row_len(item),
+            Cooccurrences::Partitioned { .. } => panic!("Cooccurrences are not materialised"),
+        }
+    }
+
+    fn row<'a>(&'a self, item: u32) -> CooccurrencesRowIter<'a> {
+        match *self {
+            Cooccurrences::Incremental(ref c) => {
+                CooccurrencesRowIter::Incremental(c[item as usize].iter())
+            },
+            Cooccurrences::Compact(ref c) => CooccurrencesRowIter::Compact(c.row(item)),
+            Cooccurrences::Partitioned { .. } => panic!("Cooccurrences are not materialised"),
+        }
+    }
+
+    /// Returns the hash-based rows for updates, a compact matrix is expanded first. Rows which
+    /// are counted on demand have to be materialised by the model beforehand.
+    fn incremental(&mut self) -> &mut SparseMatrix {
+        if let Cooccurrences::Compact(ref c) = *self {
+            *self = Cooccurrences::Incremental(c.to_sparse_matrix());
+        }
+        match *self {
+            Cooccurrences::Incremental(ref mut c) => c,
+            _ => panic!("Cooccurrences are not materialised"),
+        }
+    }
+}
+
+enum CooccurrencesRowIter<'a> {
+    Incremental(SparseVectorIter<'a>),
+    Compact(CsrRowIter<'a>),
+}
+
+impl<'a> Iterator for CooccurrencesRowIter<'a> {
+    type Item = (u32, u32);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        match *self {
+            CooccurrencesRowIter::Incremental(ref mut entries) => entries.next(),
+            CooccurrencesRowIter::Compact(ref mut entries) => entries.next(),
+        }
+    }
+}
+
+/// Counts the rows of the cooccurrence matrix for a range of `items` from the downsampled
+/// `histories` of the users. Row `i` of the result holds the counts of item `items.start + i`.
+fn count_rows<'a, H>(histories: H, items: Range<u32>) -> SparseMatrix
+where
+    H: IntoIterator<Item = &'a Vec<u32>>
+{
+
+    let mut rows = types::new_sparse_matrix((items.end - items.start) as usize);
+
+    for history in histories {
+        for (n, item) in history.iter().enumerate() {
+            if items.contains(item) {
+                let row = &mut rows[(*item - items.start) as usize];
+                for (m, other_item) in history.iter().enumerate() {
+                    if n != m {
+                        row.increment(*other_item);
+                    }
+                }
+            }
+        }
+    }
+
+    rows
+}
+
+/// Splits the items into at most `num_partitions` consecutive ranges with roughly the same number
+/// of cooccurrences, which bounds the number of entries of the rows of a range
+fn partition_items(row_sums_of_c: &[u32], num_partitions: usize) -> Vec<Range<u32>> {
+
+    let num_cooccurrences: u64 = row_sums_of_c.iter().map(|sum| u64::from(*sum)).sum();
+    let num_cooccurrences_per_partition = num_cooccurrences / num_partitions as u64 + 1;
+
+    let mut ranges = Vec::with_capacity(num_partitions);
+    let mut start = 0;
+    let mut num_cooccurrences_in_range = 0;
+
+    for (item, row_sum) in row_sums_of_c.iter().enumerate() {
+        num_cooccurrences_in_range += u64::from(*row_sum);
+        if num_cooccurrences_in_range >= num_cooccurrences_per_partition {
+            ranges.push(start..item as u32 + 1);
+            start = item as u32 + 1;
+            num_cooccurrences_in_range = 0;
+        }
+    }
+
+    if (start as usize) < row_sums_of_c.len() || ranges.is_empty() {
+        ranges.push(start..row_sums_of_c.len() as u32);
+    }
+
+    ranges
+}
+
+/// Criteria for selecting the indicators of an item among its scored cooccurring items
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Selection {
+    /// Number of indicators to select
+    pub n: usize,
+    /// Polarity of the associations to select
+    pub polarity: Polarity,
+    /// Minimum score (or magnitude of the score for negative associations) of an indicator
+    pub min_score: f64,
+    /// Minimum number of cooccurrences of an indicator with the item
+    pub min_cooccurrences: u32,
+}
+
+impl Selection {
+
+    /// Selects the top-`n` items, regardless of their scores and number of cooccurrences
+    pub fn top(n: usize) -> Self {
+        Selection {
+            n,
+            polarity: Polarity::Any,
+            min_score: f64::NEG_INFINITY,
+            min_cooccurrences: 1,
+        }
+    }
+}
+
+/// The largest possible row sum of the cooccurrence matrix, which bounds the arguments of the
+/// association measures. We widen before multiplying, so that this never overflows.
+fn max_sum_of_cooccurrences(f_max: u32, k_max: u32) -> usize {
+    f_max as usize * k_max as usize
+}
+
+/// Computes the top indicators for a row of the cooccurrence matrix with the given row sum,
+/// according to the `selection`. We skip the `excluded_item`, e.g., the item to which the row
+/// belongs.
+pub(crate) fn rescore<T>(
+    cooccurrence_counts: T,
+    excluded_item: Option<u32>,
+    row_sum: u32,
+    column_sums: &[u32],
+    num_cooccurrences_observed: u64,
+    measure: &dyn AssociationMeasure,
+    selection: &Selection,
+) -> Vec<ScoredItem>
+where
+    T: Iterator<Item = (u32, u32)>
+{
+
+    let n = selection.n;
+
+    // We'll use a heap to keep track of the current top-n scored items
+    let mut top_indicators: BinaryHeap<ScoredItem> = BinaryHeap::with_capacity(n);
+
+    for (other_item, num_cooccurrences) in cooccurrence_counts {
+        if Some(other_item) != excluded_item && num_cooccurrences >= selection.min_cooccurrences {
+            // Compute counts of contingency table
+            let k11 = u64::from(num_cooccurrences);
+            let k12 = u64::from(row_sum) - k11;
+            let k21 = u64::from(column_sums[other_item as usize]) - k11;
+            let k22 = num_cooccurrences_observed + k11 - k12 - k21;
+
+            let table = ContingencyTable { k11, k12, k21, k22 };
+
+            if !selection.polarity.admits(&table) {
+                continue;
+            }
+
+            // Compute association score, negative associations are ranked by their magnitude
+            let score = match selection.polarity {
+                Polarity::Negative => measure.score(&table).abs(),
+                _ => measure.score(&table),
+            };
+
+            if score < selection.min_score {
+                continue;
+            }
+
+            // Update heap holding top-n scored items for this item
+            let scored_item = ScoredItem { item: other_item, score };
+
+            if top_indicators.len() < n {
+                top_indicators.push(scored_item);
+            } else {
+                let mut top = top_indicators.peek_mut().unwrap();
+                if scored_item < *top {
+                    *top = scored_item;
+                }
+            }
+        }
+    }
+
+    // Due to the reversed ordering of ScoredItem, this gives us descending scores
+    top_indicators.into_sorted_vec()
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use config::IndicatorConfig;
+    use error::Error;
+    use model::{history_of, IndicatorModel};
+    use measures::{Measure, Polarity};
+    use weights::Weighting;
+
+    #[test]
+    fn invalid_tunables_are_rejected() {
+
+        match IndicatorModel::new(0, 0, 10, 100_000, 100_000, 42) {
+            Err(Error::Overflow(_)) => {},
+            _ => panic!("Expected an overflow"),
+        }
+
+        match IndicatorModel::new(0, 0, 0, 500, 500, 42) {
+            Err(Error::Config(_)) => {},
+            _ => panic!("Expected a configuration error"),
+        }
+
+        let mut model = IndicatorModel::new(0, 0, 10, 500, 500, 42).unwrap();
+        model.set_weighting(Weighting::TopK(2)).unwrap();
+        model.update(0, 0);
+
+        match model.set_weighting(Weighting::Unweighted) {
+            Err(Error::Config(_)) => {},
+            other => panic!("Expected a configuration error, got {:?}", other),
+        }
+        assert_eq!(model.weighting(), Weighting::TopK(2));
+    }
+
+    #[test]
+    fn only_dirty_items_are_rescored() {
+
+        let mut model = IndicatorModel::new(2, 4, 10, 500, 500, 42).unwrap();
+
+        model.update(0, 0);
+        model.update(0, 1);
+        model.update(1, 2);
+        model.update(1, 3);
+
+        assert_eq!(model.num_dirty_items(), 4);
+        assert_eq!(model.refresh(), 4);
+        assert_eq!(model.num_dirty_items(), 0);
+
+        // A new interaction only touches the items in the history of its user
+        model.update(1, 0);
+
+        assert_eq!(model.refresh(), 3);
+        assert_eq!(model.indicators().num_items(), 4);
+        assert!(model.indicators().indicators_for(2).iter().any(|scored| scored.item == 0));
+        assert!(model.indicators().indicators_for(1).iter().all(|scored| scored.item != 2));
+    }
+
+    #[test]
+    fn grows_for_unseen_users_and_items() {
+
+        let mut model = IndicatorModel::new(0, 0, 10, 500, 500, 42).unwrap();
+
+        model.update(3, 5);
+        model.update(3, 7);
+        model.refresh();
+
+        assert_eq!(model.num_users(), 4);
+        assert_eq!(model.num_items(), 8);
+        assert_eq!(model.num_cooccurrences_observed(), 2);
+
+        assert_eq!(model.indicators().num_items(), 8);
+        assert_eq!(model.indicators().indicators_for(5)[0].item, 7);
+        assert_eq!(model.indicators().indicators_for(7)[0].item, 5);
+        assert!(model.indicators().indicators_for(6).is_empty());
+    }
+
+    #[test]
+    fn measure_can_be_switched() {
+
+        let mut model = IndicatorModel::new(0, 0, 10, 500, 500, 42).unwrap();
+
+        model.update(0, 0);
+        model.update(0, 1);
+        model.update(1, 0);
+        model.update(1, 2);
+        model.refresh();
+
+        // Item 0 occurs with both other items, so item 1 always occurs with item 0
+        model.set_measure(Measure::ConditionalProbability);
+        model.update(2, 1);
+        model.update(2, 0);
+        model.refresh();
+
+        let indicators_for_item_1 = model.indicators().indicators_for(1);
+        assert_eq!(indicators_for_item_1[0].item, 0);
+        assert!((indicators_for_item_1[0].score - 1.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn configuration_can_be_applied_later() {
+
+        let config = IndicatorConfig::builder()
+            .measure(Measure::Jaccard)
+            .polarity(Polarity::Positive)
+            .min_cooccurrences(2)
+            .build()
+            .unwrap();
+
+        let interactions: Vec<(u32, u32)> = (0..500)
+            .map(|n| (n % 23, (n * 3 + n / 23) % 17))
+            .collect();
+
+        let mut configured = IndicatorModel::with_config(0, 0, &config);
+        let mut restored = IndicatorModel::new(0, 0, 10, 500, 500, ::DEFAULT_SEED).unwrap();
+
+        for (user, item) in interactions {
+            configured.update(user, item);
+            restored.update(user, item);
+        }
+        configured.refresh();
+        restored.refresh();
+
+        // Applying the configuration re-scores the items of an existing model
+        restored.configure(&config);
+        assert!(restored.refresh() > 0);
+        assert_eq!(restored.indicators(), configured.indicators());
+
+        // Nothing changes if the configuration is applied again
+        restored.configure(&config);
+        assert_eq!(restored.refresh(), 0);
+    }
+
+    #[test]
+    fn negative_associations_are_separated() {
+
+        let mut model = IndicatorModel::new(0, 0, 10, 500, 500, 42).unwrap();
+
+        // Items 0 and 1 both frequently occur with item 2, but only once with each other
+        for user in 0..10 {
+            model.update(user, 0);
+            model.update(user, 2);
+            model.update(user + 10, 1);
+            model.update(user + 10, 2);
+        }
+        model.update(20, 0);
+        model.update(20, 1);
+        model.refresh();
+
+        let indicated_items = |indicators: &[::types::ScoredItem]| -> Vec<u32> {
+            indicators.iter().map(|scored| scored.item).collect()
+        };
+
+        assert_eq!(indicated_items(model.indicators().indicators_for(0)), vec![2, 1]);
+
+        model.set_polarity(Polarity::Positive);
+        model.set_negative_indicators(true);
+        assert_eq!(model.refresh(), 3);
+
+        assert_eq!(indicated_items(model.indicators().indicators_for(0)), vec![2]);
+        let negative_indicators = model.negative_indicators().unwrap();
+        assert_eq!(indicated_items(negative_indicators.indicators_for(0)), vec![1]);
+        assert!(negative_indicators.indicators_for(2).is_empty());
+    }
+
+    #[test]
+    fn weak_associations_are_not_selected() {
+
+        let mut model = IndicatorModel::new(0, 0, 10, 500, 500, 42).unwrap();
+
+        // Items 0 and 1 cooccur for ten users, items 0 and 2 only for a single user
+        for user in 0..10 {
+            model.update(user, 0);
+            model.update(user, 1);
+        }
+        model.update(10, 0);
+        model.update(10, 2);
+        for user in 11..30 {
+            model.update(user, 3);
+            model.update(user, 4);
+        }
+        model.refresh();
+
+        assert_eq!(model.indicators().indicators_for(0).len(), 2);
+
+        model.set_min_cooccurrences(2);
+        model.refresh();
+
+        assert_eq!(model.indicators().indicators_for(0).len(), 1);
+        assert_eq!(model.indicators().indicators_for(0)[0].item, 1);
+        assert!(model.indicators().indicators_for(2).is_empty());
+
+        model.set_min_cooccurrences(1);
+        model.set_min_score(::measures::llr_for_p_value(0.001));
+        model.refresh();
+
+        assert_eq!(model.indicators().indicators_for(0).len(), 1);
+        assert!(model.indicators().indicators_for(2).is_empty());
+    }
+
+    #[test]
+    fn cooccurrence_counts_past_u16() {
+
+        let mut model = IndicatorModel::new(0, 0, 10, 100_000, 3, 42).unwrap();
+
+        // 70,000 users who interact with items 0 and 1 give 70,000 cooccurrences of the items
+        for user in 0..70_000 {
+            model.update(user, 0);
+            model.update(user, 1);
+        }
+
+        assert_eq!(model.c.incremental()[0].get(1), 70_000);
+        assert_eq!(model.c.incremental()[1].get(0), 70_000);
+        assert!(model.c.incremental()[0].is_wide());
+
+        // Replacements in the full histories of the users adjust the counts without wrapping around
+        for user in 0..70_000 {
+            model.update(user, 2);
+            model.update(user, 3);
+        }
+
+        let histories = &model.samples_of_a.samples;
+        let cooccurrences = |item, other_item| histories
+            .iter()
+            .filter(|history| history.contains(&item) && history.contains(&other_item))
+            .count() as u32;
+        assert_eq!(model.c.incremental()[0].get(1), cooccurrences(0, 1));
+        assert_eq!(model.c.incremental()[2].get(0), cooccurrences(0, 2));
+
+        model.refresh();
+        assert_eq!(model.indicators().indicators_for(0).len(), 3);
+    }
+
+    #[test]
+    fn largest_caps_bound_the_logarithms_table() {
+
+        let config = IndicatorConfig::builder()
+            .max_interactions_per_user(65_535)
+            .max_interactions_per_item(65_537)
+            .build()
+            .unwrap();
+
+        // Would allocate 32 GB for a table of all possible sums
+        let mut model = IndicatorModel::with_config(0, 0, &config);
+        for &(user, item) in &[(0, 0), (0, 1), (1, 0), (1, 1), (2, 2)] {
+            model.update(user, item);
+        }
+        model.refresh();
+
+        assert_eq!(model.indicators().indicators_for(0)[0].item, 1);
+    }
+
+    #[test]
+    fn repeated_interactions_are_sampled_once() {
+
+        let mut model = IndicatorModel::new(0, 0, 10, 500, 500, 42).unwrap();
+        for &(user, item) in &[(0, 0), (0, 0), (0, 0), (0, 1)] {
+            model.update(user, item);
+        }
+
+        assert_eq!(model.samples_of_a.history(0), &[0, 1]);
+        assert_eq!(model.num_repeated_interactions(), 2);
+        assert_eq!(model.num_cooccurrences_observed(), 2);
+        assert_eq!(model.row_sums_of_c, vec![1, 1]);
+        assert_eq!(model.c.incremental()[0].get(0), 0);
+        assert_eq!(model.c.incremental()[0].get(1), 1);
+    }
+
+    #[test]
+    fn cross_occurrences_match_downsampled_histories() {
+
+        let config = IndicatorConfig::builder()
+            .max_interactions_per_item(4)
+            .max_interactions_per_user(3)
+            .build()
+            .unwrap();
+
+        let mut model = IndicatorModel::cross_with_config(0, 0, &config);
+
+        // Interleaved primary and secondary interactions, so that histories get replaced
+        for n in 0..600 {
+            let (user, item) = (n % 17, (n * 7 + n / 17) % 13);
+            if n % 3 == 0 {
+                model.update_secondary(user, item).unwrap();
+            } else {
+                model.update(user, item);
+            }
+        }
+
+        let num_items = model.num_items();
+        let mut expected = vec![vec![0u32; num_items]; num_items];
+        let mut row_sums = vec![0u32; num_items];
+        let mut column_sums = vec![0u32; num_items];
+        let mut observed = 0u64;
+
+        let cross = model.cross.as_ref().unwrap();
+        for user in 0..model.num_users() as u32 {
+            let history_of_a = history_of(&model.samples_of_a, user);
+            for item_b in history_of(&cross.samples_of_b, user) {
+                for item_a in history_of_a {
+                    expected[*item_b as usize][*item_a as usize] += 1;
+                    row_sums[*item_b as usize] += 1;
+                    column_sums[*item_a as usize] += 1;
+                    observed += 1;
+                }
+            }
+        }
+
+        assert_eq!(model.row_sums_of_c, row_sums);
+        assert_eq!(model.cross.as_ref().unwrap().column_sums_of_c, column_sums);
+        assert_eq!(model.num_cooccurrences_observed(), observed);
+
+        for (item_b, expected_row) in expected.iter().enumerate() {
+            for (item_a, expected_count) in expected_row.iter().enumerate() {
+                assert_eq!(model.c.incremental()[item_b].get(item_a as u32), *expected_count);
+            }
+        }
+    }
+
+    #[test]
+    fn secondary_interactions_require_a_cross_model() {
+        let mut model = IndicatorModel::new(0, 0, 10, 500, 500, 42).unwrap();
+        match model.update_secondary(0, 0) {
+            Err(Error::Config(_)) => {},
+            _ => panic!("Secondary interactions were accepted"),
+        }
+    }
+
+    #[test]
+    fn partitioned_updates_match_sequential_updates() {
+
+        let interactions: Vec<(u32, u32)> = (0..2000)
+            .map(|n| (n % 97, (n * 7 + n / 97) % 31))
+            .collect();
+
+        let mut sequential = IndicatorModel::new(0, 0, 10, 500, 500, 42).unwrap();
+        for (user, item) in interactions.iter() {
+            sequential.update(*user, *item);
+        }
+
+        let mut partitioned = IndicatorModel::new(0, 0, 10, 500, 500, 42).unwrap();
+        partitioned.update_all_partitioned(interactions.into_iter(), 4).unwrap();
+
+        assert_eq!(partitioned.num_users(), sequential.num_users());
+        assert_eq!(partitioned.num_items(), sequential.num_items());
+        assert_eq!(
+            partitioned.num_cooccurrences_observed(),
+            sequential.num_cooccurrences_observed(),
+        );
+        assert_eq!(partitioned.row_sums_of_c, sequential.row_sums_of_c);
+        assert_eq!(partitioned.c.incremental(), sequential.c.incremental());
+        assert_eq!(partitioned.samples_of_a.samples, sequential.samples_of_a.samples);
+        assert_eq!(partitioned.refresh(), sequential.refresh());
+    }
+
+    #[test]
+    fn partitioned_updates_limit_interactions_per_item_and_user() {
+
+        // More partitions than interactions per item, both limits are reached
+        let interactions: Vec<(u32, u32)> = (0..64)
+            .flat_map(|user| (0..3).map(move |item| (user, item)))
+            .collect();
+
+        let mut sequential = IndicatorModel::new(0, 0, 10, 2, 3, 42).unwrap();
+        for (user, item) in interactions.iter() {
+            sequential.update(*user, *item);
+        }
+
+        let mut partitioned = IndicatorModel::new(0, 0, 10, 2, 3, 42).unwrap();
+        partitioned.update_all_partitioned(interactions.into_iter(), 8).unwrap();
+
+        let samples_of_a = &partitioned.samples_of_a;
+        assert!(samples_of_a.item_interaction_counts.iter().all(|count| *count <= 2));
+        assert!(samples_of_a.samples.iter().all(|history| history.len() <= 3));
+
+        assert_eq!(
+            partitioned.num_cooccurrences_observed(),
+            sequential.num_cooccurrences_observed(),
+        );
+        assert_eq!(partitioned.row_sums_of_c, sequential.row_sums_of_c);
+        assert_eq!(partitioned.c.incremental(), sequential.c.incremental());
+        assert_eq!(partitioned.refresh(), sequential.refresh());
+        assert_eq!(ranking(&partitioned), ranking(&sequential));
+    }
+
+    #[test]
+    fn budgeted_updates_match_sequential_updates() {
+
+        // Some users exceed their share of interactions, so that histories get replaced
+        let interactions: Vec<(u32, u32)> = (0..3000)
+            .map(|n| (n % 37, (n * 7 + n / 37) % 53))
+            .collect();
+
+        let mut sequential = IndicatorModel::new(0, 0, 10, 50, 40, 42).unwrap();
+        for (user, item) in interactions.iter() {
+            sequential.update(*user, *item);
+        }
+        sequential.refresh();
+
+        // A budget of a few hundred counts forces several spills
+        let mut budgeted = IndicatorModel::new(0, 0, 10, 50, 40, 42).unwrap();
+        let num_spills = budgeted
+            .update_all_with_memory_budget(interactions.into_iter(), 10_000)
+            .unwrap();
+        budgeted.refresh();
+
+        assert!(num_spills > 1);
+        assert_eq!(budgeted.num_items(), sequential.num_items());
+        assert_eq!(budgeted.num_cooccurrences_observed(), sequential.num_cooccurrences_observed());
+        assert_eq!(budgeted.row_sums_of_c, sequential.row_sums_of_c);
+
+        assert_eq!(ranking(&budgeted), ranking(&sequential));
+
+        // The compact matrix is expanded for further updates
+        assert_eq!(budgeted.c.incremental(), sequential.c.incremental());
+        budgeted.update(40, 1);
+        budgeted.update(40, 2);
+        sequential.update(40, 1);
+        sequential.update(40, 2);
+        assert_eq!(budgeted.c.incremental(), sequential.c.incremental());
+    }
+
+    #[test]
+    fn out_of_core_updates_match_sequential_updates() {
+
+        let interactions: Vec<(u32, u32)> = (0..3000)
+            .map(|n| (n % 37, (n * 7 + n / 37) % 53))
+            .collect();
+
+        let mut sequential = IndicatorModel::new(0, 0, 10, 50, 40, 42).unwrap();
+        for (user, item) in interactions.iter() {
+            sequential.update(*user, *item);
+        }
+
+        let mut out_of_core = IndicatorModel::new(0, 0, 10, 50, 40, 42).unwrap();
+        out_of_core.update_all_out_of_core(interactions.into_iter(), 4).unwrap();
+
+        assert_eq!(out_of_core.row_sums_of_c, sequential.row_sums_of_c);
+        assert_eq!(super::partition_items(&out_of_core.row_sums_of_c, 4).len(), 4);
+
+        assert_eq!(out_of_core.refresh(), sequential.refresh());
+        assert_eq!(
+            out_of_core.num_cooccurrences_observed(),
+            sequential.num_cooccurrences_observed(),
+        );
+
+        assert_eq!(ranking(&out_of_core), ranking(&sequential));
+
+        // The whole matrix is materialised for further updates
+        out_of_core.update(40, 1);
+        sequential.update(40, 1);
+        assert_eq!(out_of_core.c.incremental(), sequential.c.incremental());
+    }
+
+    #[test]
+    fn out_of_core_updates_require_partitions() {
+        let mut model = IndicatorModel::new(0, 0, 10, 500, 500, 42).unwrap();
+        match model.update_all_out_of_core(vec![(0, 0)].into_iter(), 0) {
+            Err(Error::Config(_)) => {},
+            other => panic!("Expected a configuration error, got {:?}", other),
+        }
+    }
+
+    /// The indicators of every item, with the indicated items and their scores
+    fn ranking(model: &IndicatorModel) -> Vec<Vec<(u32, f64)>> {
+        model.indicators()
+            .iter()
+            .map(|(_, row)| row.iter().map(|scored| (scored.item, scored.score)).collect())
+            .collect()
+    }
+}
@@ -0,0 +1,118 @@
+//! ## Recommendations for users
+//!
+//! The indicators tell us which items are highly associated with an item. In order to recommend
+//! items to a user, we look up the indicators of every item in the history of the user, and sum
+//! up the scores of each indicated item across these indicator lists. The items with the highest
+//! aggregated scores are recommended, items from the history of the user are excluded by default.
+//!
+/*
+ * RecoReco
+ * Copyright (C) 2018 Sebastian Schelter
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use fnv::{FnvHashMap, FnvHashSet};
+
+use types::{IndicatorMatrix, ScoredItem};
+
+/// Recommends items to users based on the items in their histories and precomputed indicators.
+pub struct Recommender<'a> {
+    indicators: &'a IndicatorMatrix,
+    exclude_seen: bool,
+}
+
+impl<'a> Recommender<'a> {
+
+    /// Creates a recommender from the `indicators`, which excludes already seen items
+    pub fn new(indicators: &'a IndicatorMatrix) -> Self {
+        Recommender { indicators, exclude_seen: true }
+    }
+
+    /// Whether to exclude the items from the history of a user from the recommendations
+    pub fn set_exclude_seen(&mut self, exclude_seen: bool) {
+        self.exclude_seen = exclude_seen;
+    }
+
+    /// Returns the top-`n` items for a user with the given `history` of internal item indexes,
+    /// sorted by descending aggregated score. Ties are broken by the internal index of the items.
+    /// Items which are not covered by the indicators are ignored.
+    pub fn recommend(&self, history: &[u32], n: usize) -> Vec<ScoredItem> {
+
+        let seen_items: FnvHashSet<u32> = history.iter().cloned().collect();
+
+        let mut scores: FnvHashMap<u32, f64> = FnvHashMap::default();
+
+        for item in &seen_items {
+            if *item as usize >= self.indicators.num_items() {
+                continue;
+            }
+            for indicator in self.indicators.indicators_for(*item) {
+                if self.exclude_seen && seen_items.contains(&indicator.item) {
+                    continue;
+                }
+                *scores.entry(indicator.item).or_insert(0.0) += indicator.score;
+            }
+        }
+
+        let mut recommendations: Vec<ScoredItem> = scores
+            .into_iter()
+            .map(|(item, score)| ScoredItem { item, score })
+            .collect();
+
+        // Highest score first, ties broken by the item index
+        recommendations.sort();
+        recommendations.truncate(n);
+
+        recommendations
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use recommend::Recommender;
+    use types::{IndicatorMatrix, ScoredItem};
+
+    fn scored(item: u32, score: f64) -> ScoredItem {
+        ScoredItem { item, score }
+    }
+
+    #[test]
+    fn aggregate_scores_across_indicators() {
+
+        let mut indicators = IndicatorMatrix::new(5);
+        indicators.set_indicators_for(0, vec![scored(2, 3.0), scored(1, 2.0), scored(3, 1.0)]);
+        indicators.set_indicators_for(1, vec![scored(3, 2.5), scored(0, 1.0)]);
+        indicators.set_indicators_for(2, vec![scored(4, 9.0)]);
+
+        let mut recommender = Recommender::new(&indicators);
+
+        assert_eq!(
+            recommender.recommend(&[0, 1, 1], 10),
+            vec![scored(3, 3.5), scored(2, 3.0)],
+        );
+        assert_eq!(recommender.recommend(&[0, 1], 1), vec![scored(3, 3.5)]);
+
+        // Items unknown to the indicators are ignored
+        assert_eq!(recommender.recommend(&[7], 10), vec![]);
+
+        recommender.set_exclude_seen(false);
+        assert_eq!(
+            recommender.recommend(&[0, 1], 10),
+            vec![scored(3, 3.5), scored(2, 3.0), scored(1, 2.0), scored(0, 1.0)],
+        );
+    }
+}
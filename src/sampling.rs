@@ -0,0 +1,378 @@
+/*
+ * RecoReco
+ * Copyright (C) 2018 Sebastian Schelter
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::num::Wrapping as w;
+
+use rand::Rng;
+
+use types;
+use types::DenseVector;
+use weights::Weighting;
+
+/// Outcome of offering an interaction to the `Downsampler`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sample {
+    /// The interaction was not sampled
+    Skipped,
+    /// The item was appended to the history of the user
+    Appended,
+    /// The item replaced `previous_item` at `position` in the history of the user
+    Replaced { position: usize, previous_item: u32 },
+}
+
+/// Downsamples a stream of interactions: we account for at most `f_max` interactions per item, and
+/// keep a uniform sample of at most `k_max` interactions per user via reservoir sampling. For a
+/// prioritised `Weighting`, we instead keep the interactions with the highest priorities per user,
+/// which are retained in `priorities` alongside the samples.
+/// Repetitions of an interaction whose item is already in the history of the user are skipped and
+/// counted in `num_repetitions`, so that a history never holds an item twice. Note that an item
+/// which was replaced in the history can enter it again with a later repetition.
+pub struct Downsampler {
+    pub f_max: u32,
+    pub k_max: u32,
+    pub weighting: Weighting,
+    pub rng: SamplingRng,
+    pub user_non_sampled_interaction_counts: DenseVector,
+    pub user_interaction_counts: DenseVector,
+    pub item_interaction_counts: DenseVector,
+    pub samples: Vec<Vec<u32>>,
+    pub priorities: Vec<Vec<f64>>,
+    pub num_repetitions: u64,
+}
+
+impl Downsampler {
+
+    pub fn new(num_users: usize, num_items: usize, f_max: u32, k_max: u32, seed: u64) -> Self {
+        Downsampler {
+            f_max,
+            k_max,
+            weighting: Weighting::Unweighted,
+            rng: SamplingRng::from_seed(seed),
+            user_non_sampled_interaction_counts: types::new_dense_vector(num_users),
+            user_interaction_counts: types::new_dense_vector(num_users),
+            item_interaction_counts: types::new_dense_vector(num_items),
+            samples: vec![Vec::new(); num_users],
+            priorities: vec![Vec::new(); num_users],
+            num_repetitions: 0,
+        }
+    }
+
+    /// Returns the current sample of the interactions of `user`
+    pub fn history(&self, user: u32) -> &[u32] {
+        &self.samples[user as usize]
+    }
+
+    /// Offers an interaction between `user` and `item` to the sample, and returns how the history
+    /// of the user changed. The interaction has a weight of one.
+    pub fn sample(&mut self, user: u32, item: u32) -> Sample {
+        self.sample_weighted(user, item, 1.0)
+    }
+
+    /// Offers an interaction between `user` and `item` with the given `weight` to the sample, and
+    /// returns how the history of the user changed.
+    pub fn sample_weighted(&mut self, user: u32, item: u32, weight: f64) -> Sample {
+
+        if !self.weighting.admits(weight) {
+            return Sample::Skipped;
+        }
+
+        self.ensure_user(user);
+        self.ensure_item(item);
+
+        let item_idx = item as usize;
+        let user_idx = user as usize;
+
+        // Repeated interactions with an item in the history of the user are not sampled again
+        if self.samples[user_idx].contains(&item) {
+            self.num_repetitions += 1;
+            return Sample::Skipped;
+        }
+
+        // Update number of observed interactions for user
+        self.user_non_sampled_interaction_counts[user_idx] += 1;
+
+        // Check whether we have seen enough interactions for this item yet
+        if self.item_interaction_counts[item_idx] >= self.f_max {
+            return Sample::Skipped;
+        }
+
+        let priority = self.priority(weight);
+
+        let max_interactions_per_user = match self.weighting {
+            // A k which does not fit into a u32 exceeds k_max anyway, truncating it would shrink it
+            Weighting::TopK(k) => u32::try_from(k).map_or(self.k_max, |k| self.k_max.min(k)),
+            _ => self.k_max,
+        };
+
+        // Retrieve current history sample for interacting user
+        let user_history = &mut self.samples[user_idx];
+
+        // Check whether we have seen enough interactions for this user yet
+        if self.user_interaction_counts[user_idx] < max_interactions_per_user {
+
+            // Add item to user history
+            user_history.push(item);
+            if let Some(priority) = priority {
+                self.priorities[user_idx].push(priority);
+            }
+
+            // Update statistics for user and item interaction counts
+            self.user_interaction_counts[user_idx] += 1;
+            self.item_interaction_counts[item_idx] += 1;
+
+            Sample::Appended
+
+        } else if let Some(priority) = priority {
+
+            // Replace the item with the lowest priority, if the new one has a higher priority
+            let user_priorities = &mut self.priorities[user_idx];
+            let lowest = user_priorities
+                .iter()
+                .cloned()
+                .enumerate()
+                .min_by(|(_, first), (_, second)| {
+                    first.partial_cmp(second).unwrap_or(Ordering::Equal)
+                });
+
+            match lowest {
+                Some((position, lowest_priority)) if priority > lowest_priority => {
+                    let previous_item = user_history[position];
+
+                    user_history[position] = item;
+                    user_priorities[position] = priority;
+
+                    self.item_interaction_counts[item_idx] += 1;
+                    self.item_interaction_counts[previous_item as usize] -= 1;
+
+                    Sample::Replaced { position, previous_item }
+                },
+                _ => Sample::Skipped,
+            }
+
+        } else {
+
+            let num_interactions_seen_by_user = self.user_non_sampled_interaction_counts[user_idx];
+
+            let k: usize = self.rng.gen_range(0, num_interactions_seen_by_user as usize);
+
+            if k < user_history.len() {
+                let previous_item = user_history[k];
+
+                // Replace previous item in user history
+                user_history[k] = item;
+
+                // Adjust item statistics
+                self.item_interaction_counts[item_idx] += 1;
+                self.item_interaction_counts[previous_item as usize] -= 1;
+
+                Sample::Replaced { position: k, previous_item }
+            } else {
+                Sample::Skipped
+            }
+        }
+    }
+
+    /// Priority of an interaction with the given `weight` for a prioritised weighting. For
+    /// logarithmic priorities, we use the keys of weighted reservoir sampling (Efraimidis and
+    /// Spirakis), so that once the history of a user is full, interactions remain in it with a
+    /// probability proportional to the logarithm of their weight.
+    fn priority(&mut self, weight: f64) -> Option<f64> {
+        match self.weighting {
+            Weighting::TopK(_) => Some(weight),
+            Weighting::LogPriority => {
+                let uniform: f64 = self.rng.gen();
+                Some(uniform.powf(1.0 / weight.ln_1p()))
+            },
+            Weighting::Unweighted | Weighting::Threshold(_) => None,
+        }
+    }
+
+    /// Grows the internal data structures to accommodate `user`
+    pub fn ensure_user(&mut self, user: u32) {
+        let num_users = user as usize + 1;
+        if num_users > self.samples.len() {
+            self.user_non_sampled_interaction_counts.resize(num_users, 0);
+            self.user_interaction_counts.resize(num_users, 0);
+            self.samples.resize(num_users, Vec::new());
+            self.priorities.resize(num_users, Vec::new());
+        }
+    }
+
+    /// Grows the internal data structures to accommodate `item`
+    pub fn ensure_item(&mut self, item: u32) {
+        let num_items = item as usize + 1;
+        if num_items > self.item_interaction_counts.len() {
+            self.item_interaction_counts.resize(num_items, 0);
+        }
+    }
+}
+
+/// Xorshift random number generator used for downsampling. This is the same algorithm as
+/// `rand::XorShiftRng`, but we need access to its state in order to checkpoint and restore it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SamplingRng {
+    x: w<u32>,
+    y: w<u32>,
+    z: w<u32>,
+    w: w<u32>,
+}
+
+impl SamplingRng {
+
+    /// Creates the generator from a seed. We expand the seed via splitmix64 to fill the state of
+    /// the generator, which must not be all zeros.
+    pub fn from_seed(seed: u64) -> Self {
+        let mut state = seed;
+        let mut next_word = || {
+            state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^ (z >> 31)
+        };
+
+        let (first, second) = (next_word(), next_word());
+        let mut words = [first as u32, (first >> 32) as u32, second as u32, (second >> 32) as u32];
+
+        if words.iter().all(|word| *word == 0) {
+            words[0] = 1;
+        }
+
+        SamplingRng::from_state(words)
+    }
+
+    /// Restores the generator from a state obtained via `state()`
+    pub fn from_state(state: [u32; 4]) -> Self {
+        SamplingRng { x: w(state[0]), y: w(state[1]), z: w(state[2]), w: w(state[3]) }
+    }
+
+    /// Returns the current state of the generator
+    pub fn state(&self) -> [u32; 4] {
+        [self.x.0, self.y.0, self.z.0, self.w.0]
+    }
+}
+
+impl Rng for SamplingRng {
+    #[inline]
+    fn next_u32(&mut self) -> u32 {
+        let x = self.x;
+        let t = x ^ (x << 11);
+        self.x = self.y;
+        self.y = self.z;
+        self.z = self.w;
+        let w_ = self.w;
+        self.w = w_ ^ (w_ >> 19) ^ (t ^ (t >> 8));
+        self.w.0
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use sampling::{Downsampler, Sample, SamplingRng};
+    use weights::Weighting;
+
+    #[test]
+    fn same_sequence_as_xorshift() {
+        let state = [1, 2, 3, 4];
+
+        let mut ours = SamplingRng::from_state(state);
+        let mut theirs = XorShiftRng::from_seed(state);
+
+        for _ in 0..100 {
+            assert_eq!(ours.gen_range(0, 1000), theirs.gen_range(0, 1000));
+        }
+    }
+
+    #[test]
+    fn restore_from_state() {
+        let mut rng = SamplingRng::from_seed(42);
+        rng.next_u32();
+
+        let mut restored = SamplingRng::from_state(rng.state());
+
+        assert_eq!(rng.next_u32(), restored.next_u32());
+    }
+
+    #[test]
+    fn downsampling_respects_limits() {
+
+        let mut downsampler = Downsampler::new(0, 0, 2, 3, 42);
+
+        assert_eq!(downsampler.sample(0, 0), Sample::Appended);
+        assert_eq!(downsampler.sample(0, 1), Sample::Appended);
+        assert_eq!(downsampler.sample(0, 2), Sample::Appended);
+        assert_eq!(downsampler.history(0), &[0, 1, 2]);
+
+        // The history of user 0 is full, new items can only replace existing ones
+        for item in 3..20 {
+            match downsampler.sample(0, item) {
+                Sample::Appended => panic!("User history exceeds k_max"),
+                Sample::Replaced { position, .. } => {
+                    assert_eq!(downsampler.history(0)[position], item)
+                },
+                Sample::Skipped => {},
+            }
+            assert_eq!(downsampler.history(0).len(), 3);
+        }
+
+        // Item 30 can be sampled for at most two users
+        assert_eq!(downsampler.sample(1, 30), Sample::Appended);
+        assert_eq!(downsampler.sample(2, 30), Sample::Appended);
+        assert_eq!(downsampler.sample(3, 30), Sample::Skipped);
+    }
+
+    #[test]
+    fn downsampling_by_weight() {
+
+        let mut downsampler = Downsampler::new(0, 0, 10, 10, 42);
+        downsampler.weighting = Weighting::TopK(2);
+
+        assert_eq!(downsampler.sample_weighted(0, 0, 3.0), Sample::Appended);
+        assert_eq!(downsampler.sample_weighted(0, 1, 1.0), Sample::Appended);
+        assert_eq!(downsampler.sample_weighted(0, 2, 0.5), Sample::Skipped);
+        assert_eq!(
+            downsampler.sample_weighted(0, 3, 5.0),
+            Sample::Replaced { position: 1, previous_item: 1 },
+        );
+        assert_eq!(downsampler.history(0), &[0, 3]);
+        assert_eq!(downsampler.item_interaction_counts, vec![1, 0, 0, 1]);
+
+        downsampler.weighting = Weighting::Threshold(4.0);
+        assert_eq!(downsampler.sample_weighted(1, 0, 3.0), Sample::Skipped);
+        assert_eq!(downsampler.sample_weighted(1, 0, 4.0), Sample::Appended);
+    }
+
+    #[test]
+    fn top_k_beyond_u32() {
+
+        let mut downsampler = Downsampler::new(0, 0, 10, 3, 42);
+
+        // Truncating this k to 32 bits would only keep two items per user
+        downsampler.weighting = Weighting::TopK((u32::MAX as usize).saturating_add(3));
+
+        for item in 0..3 {
+            assert_eq!(downsampler.sample_weighted(0, item, 1.0), Sample::Appended);
+        }
+        assert_eq!(downsampler.history(0).len(), 3);
+    }
+}
@@ -0,0 +1,336 @@
+//! ## Serving indicators and recommendations over HTTP
+//!
+//! Services which are not written in Rust can query the indicators via a small HTTP server, which
+//! answers requests for the indicators of an item and for recommendations based on a history of
+//! items with JSON:
+//!
+//! * `GET /items/{item}/indicators?n=10` returns the top-`n` indicators of the (percent-encoded)
+//!   `item` in the same format as the lines of the output file of **recoreco**
+//! * `POST /recommend?n=10` expects a JSON list of the items in the history of a user, and returns
+//!   the top-`n` recommended items, see `recoreco::recommend::Recommender`
+//!
+//! If the indicators were loaded from a file, the server watches this file and reloads the
+//! indicators whenever the file is replaced, e.g., by a new run of **recoreco**.
+//!
+/*
+ * RecoReco
+ * Copyright (C) 2018 Sebastian Schelter
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use serde_json;
+use serde_json::Value;
+use tiny_http::{Header, Method, Response, Server};
+
+use error::Error;
+use index::IndicatorIndex;
+use io::read_indicators;
+
+/// Number of indicators or recommendations per response, unless requested otherwise via `n`
+pub const DEFAULT_NUM_RESULTS: usize = 10;
+
+/// Largest request body in bytes which we accept, larger bodies are answered with status 413
+pub const MAX_BODY_SIZE: u64 = 1 << 20;
+
+/// How often we check whether the indicators file has been replaced
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Indicators which can be replaced while requests are being answered
+type SharedIndex = Arc<RwLock<Arc<IndicatorIndex>>>;
+
+/// Answers HTTP requests at `address` (e.g., `127.0.0.1:8080`) from the `index`, and never returns
+/// unless the server cannot be started. If an `indicators_path` is supplied, the index is
+/// reloaded from this file whenever the file changes.
+pub fn serve(
+    address: &str,
+    index: IndicatorIndex,
+    indicators_path: Option<&str>,
+) -> Result<(), Error> {
+
+    let server = Server::http(address)
+        .map_err(|error| Error::Io(io::Error::other(error.to_string())))?;
+
+    let shared_index: SharedIndex = Arc::new(RwLock::new(Arc::new(index)));
+
+    if let Some(path) = indicators_path {
+        let path = path.to_owned();
+        let shared_index = Arc::clone(&shared_index);
+        thread::spawn(move || reload_on_change(&path, &shared_index));
+    }
+
+    for mut request in server.incoming_requests() {
+
+        // Requests in flight keep answering from the index they started with
+        let index = Arc::clone(&shared_index.read().unwrap());
+
+        let (status, json) = match read_body(request.as_reader(), MAX_BODY_SIZE) {
+            Ok(body) => respond(&index, request.method(), request.url(), &body),
+            Err(failure) => failure,
+        };
+
+        let content_type = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+            .unwrap();
+        let response = Response::from_string(json.to_string())
+            .with_status_code(status)
+            .with_header(content_type);
+
+        // A failure to answer a single request, e.g., a closed connection, does not stop the server
+        let _ = request.respond(response);
+    }
+
+    Ok(())
+}
+
+/// Polls the modification time and the size of the file at `path`, and swaps in freshly loaded
+/// indicators whenever one of them changed. We only reload a changed file once its modification
+/// time and size are the same in two consecutive polls, so that we do not load a file which is
+/// still being written. We keep the current indicators if the file cannot be loaded, and retry
+/// after its next change.
+///
+/// Note that a writer which pauses for longer than the polling interval can still be caught in
+/// the middle of writing, such writers should write to a temporary file and atomically rename it
+/// to `path` instead.
+fn reload_on_change(path: &str, shared_index: &SharedIndex) {
+
+    let version = |path: &str| -> Option<(SystemTime, u64)> {
+        let metadata = fs::metadata(path).ok()?;
+        Some((metadata.modified().ok()?, metadata.len()))
+    };
+
+    let mut loaded_version = version(path);
+    let mut previous_version = loaded_version;
+
+    loop {
+        thread::sleep(RELOAD_INTERVAL);
+
+        let latest_version = version(path);
+        let is_stable = latest_version == previous_version;
+        previous_version = latest_version;
+
+        if latest_version.is_none() || latest_version == loaded_version || !is_stable {
+            continue;
+        }
+        loaded_version = latest_version;
+
+        match read_indicators(path) {
+            Ok(index) => {
+                eprintln!("Reloaded indicators for {} items from {}", index.num_items(), path);
+                *shared_index.write().unwrap() = Arc::new(index);
+            },
+            Err(failure) => {
+                eprintln!("Keeping the current indicators, failed to reload {}: {}", path, failure);
+            },
+        }
+    }
+}
+
+/// Reads the request body from `reader`, but at most `limit` bytes of it, so that a client cannot
+/// make us buffer arbitrarily large bodies. Returns the HTTP status code and the JSON response
+/// for bodies which are too large or not valid UTF-8.
+fn read_body<R: Read>(reader: R, limit: u64) -> Result<String, (u16, Value)> {
+
+    // We read one byte more than allowed to detect bodies which exceed the limit
+    let mut bytes = Vec::new();
+    if reader.take(limit + 1).read_to_end(&mut bytes).is_err() {
+        return Err(error(400, "Request body could not be read"));
+    }
+
+    if bytes.len() as u64 > limit {
+        return Err(error(413, &format!("Request body is larger than {} bytes", limit)));
+    }
+
+    String::from_utf8(bytes).map_err(|_| error(400, "Request body is not valid UTF-8"))
+}
+
+/// Answers a request with the given `method`, `url` and `body` from the `index`, returns the HTTP
+/// status code and the JSON response
+pub(crate) fn respond(
+    index: &IndicatorIndex,
+    method: &Method,
+    url: &str,
+    body: &str,
+) -> (u16, Value) {
+
+    let (path, query) = match url.find('?') {
+        Some(position) => (&url[..position], &url[position + 1..]),
+        None => (url, ""),
+    };
+
+    let n = match query_parameter(query, "n") {
+        None => DEFAULT_NUM_RESULTS,
+        Some(value) => match value.parse() {
+            Ok(n) => n,
+            Err(_) => return error(400, "Parameter 'n' must be a non-negative number"),
+        },
+    };
+
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    match (method, segments.as_slice()) {
+        (&Method::Get, ["items", item, "indicators"]) => {
+            let for_item = match percent_decode(item) {
+                Some(for_item) => for_item,
+                None => return error(400, "Item is not properly percent-encoded"),
+            };
+
+            match index.indicators_for(&for_item) {
+                Some(indicators) => {
+                    let (indicated_items, scores): (Vec<&str>, Vec<f64>) =
+                        indicators.into_iter().take(n).unzip();
+                    (200, json!({
+                        "for_item": for_item,
+                        "indicated_items": indicated_items,
+                        "scores": scores,
+                    }))
+                },
+                None => error(404, &format!("Unknown item '{}'", for_item)),
+            }
+        },
+        (&Method::Post, ["recommend"]) => {
+            let history: Vec<String> = match serde_json::from_str(body) {
+                Ok(history) => history,
+                Err(_) => return error(400, "Expected a JSON list of items as request body"),
+            };
+
+            let (recommended_items, scores): (Vec<&str>, Vec<f64>) = index
+                .recommend(history.iter().map(String::as_str), n)
+                .into_iter()
+                .unzip();
+
+            (200, json!({ "recommended_items": recommended_items, "scores": scores }))
+        },
+        (_, ["items", _, "indicators"]) | (_, ["recommend"]) => error(405, "Method not allowed"),
+        _ => error(404, "Not found"),
+    }
+}
+
+fn error(status: u16, message: &str) -> (u16, Value) {
+    (status, json!({ "error": message }))
+}
+
+/// Returns the raw value of the parameter `name` from a query string like `n=5&foo=bar`
+fn query_parameter<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) if key == name => Some(value),
+                _ => None,
+            }
+        })
+        .next()
+}
+
+/// Decodes `%XX` escapes in a component of a URL path, returns `None` for malformed escapes or
+/// invalid UTF-8
+fn percent_decode(component: &str) -> Option<String> {
+
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut position = 0;
+    while position < bytes.len() {
+        if bytes[position] == b'%' {
+            let hex = component.get(position + 1..position + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            position += 3;
+        } else {
+            decoded.push(bytes[position]);
+            position += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use tiny_http::Method;
+
+    use index;
+    use index::IndicatorIndex;
+    use server::{percent_decode, read_body, respond};
+
+    fn index() -> IndicatorIndex {
+        let (indicators, renaming) = index::example();
+        IndicatorIndex::new(&indicators, &renaming).unwrap()
+    }
+
+    #[test]
+    fn answer_indicator_requests() {
+
+        let index = index();
+
+        let (status, json) =
+            respond(&index, &Method::Get, "/items/michael%20jackson/indicators?n=1", "");
+
+        assert_eq!(status, 200);
+        assert_eq!(json["for_item"], "michael jackson");
+        assert_eq!(json["indicated_items"], json!(["justin timberlake"]));
+        assert_eq!(json["scores"], json!([1523.4]));
+
+        assert_eq!(respond(&index, &Method::Get, "/items/abba/indicators", "").0, 404);
+        assert_eq!(respond(&index, &Method::Get, "/items/queen/indicators?n=x", "").0, 400);
+        assert_eq!(respond(&index, &Method::Post, "/items/queen/indicators", "").0, 405);
+        assert_eq!(respond(&index, &Method::Get, "/items", "").0, 404);
+    }
+
+    #[test]
+    fn answer_recommendation_requests() {
+
+        let index = index();
+
+        let (status, json) = respond(
+            &index,
+            &Method::Post,
+            "/recommend",
+            "[\"michael jackson\", \"justin timberlake\"]",
+        );
+
+        assert_eq!(status, 200);
+        assert_eq!(json["recommended_items"], json!(["queen"]));
+        assert_eq!(json["scores"], json!([2187.1]));
+
+        assert_eq!(respond(&index, &Method::Post, "/recommend", "{}").0, 400);
+        assert_eq!(respond(&index, &Method::Get, "/recommend", "").0, 405);
+    }
+
+    #[test]
+    fn limit_request_bodies() {
+
+        assert_eq!(read_body(&b"[\"queen\"]"[..], 9), Ok(String::from("[\"queen\"]")));
+        assert_eq!(read_body(&b""[..], 0), Ok(String::new()));
+
+        assert_eq!(read_body(&b"[\"queen\"]"[..], 8).unwrap_err().0, 413);
+        assert_eq!(read_body(&[0xff, 0xfe][..], 9).unwrap_err().0, 400);
+    }
+
+    #[test]
+    fn decode_percent_encoded_items() {
+        assert_eq!(percent_decode("ac%2Fdc"), Some(String::from("ac/dc")));
+        assert_eq!(percent_decode("bj%C3%B6rk"), Some(String::from("björk")));
+        assert_eq!(percent_decode("broken%2"), None);
+    }
+}
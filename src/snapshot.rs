@@ -0,0 +1,455 @@
+//! ## Checkpointing and resuming the training state
+//!
+//! Computing the cooccurrence matrix requires a pass over the whole interaction history. This
+//! module allows us to write the training state (the `DataDictionary` and the state of an
+//! `IndicatorModel`) to a binary snapshot on disk, and to restore it exactly in a later run, which
+//! can then continue to ingest new interactions from where the previous run stopped.
+//!
+//! Snapshots start with a versioned header, snapshots written by an incompatible version of
+//! recoreco are rejected when reading them.
+//!
+/*
+ * RecoReco
+ * Copyright (C) 2018 Sebastian Schelter
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use std::process;
+
+use model::IndicatorModel;
+use stats::DataDictionary;
+use weights::Weighting;
+
+/// Magic bytes at the beginning of every snapshot
+const MAGIC: &[u8; 8] = b"RECORECO";
+
+/// Version of the snapshot format, needs to be increased on every incompatible change
+pub const FORMAT_VERSION: u32 = 6;
+
+/// Writes a snapshot of the data dictionary and the model state to the file at `path`. The
+/// snapshot is written to a temporary file next to `path` first, which is then renamed to `path`,
+/// so that a crash while writing never leaves a truncated snapshot behind, and a previous snapshot
+/// at `path` is only replaced by a complete one.
+pub fn write_snapshot(
+    path: &str,
+    model: &IndicatorModel,
+    data_dict: &DataDictionary,
+) -> io::Result<()> {
+
+    // The temporary file must be in the same directory, a rename is only atomic within a filesystem
+    let temporary_path = temporary_path_for(path);
+
+    let written = write_snapshot_to(&temporary_path, model, data_dict)
+        .and_then(|_| fs::rename(&temporary_path, path));
+
+    if written.is_err() {
+        let _ = fs::remove_file(&temporary_path);
+    }
+
+    written
+}
+
+/// Path of the temporary file to which we write a snapshot for `path`
+fn temporary_path_for(path: &str) -> String {
+    format!("{}.tmp-{}", path, process::id())
+}
+
+fn write_snapshot_to(
+    path: &str,
+    model: &IndicatorModel,
+    data_dict: &DataDictionary,
+) -> io::Result<()> {
+
+    let mut out = BufWriter::new(File::create(Path::new(path))?);
+
+    out.write_all(MAGIC)?;
+    write_u32(&mut out, FORMAT_VERSION)?;
+
+    data_dict.write_state(&mut out)?;
+    model.write_state(&mut out)?;
+
+    // The data needs to be on disk before the rename makes it visible under the final path
+    let file = out.into_inner().map_err(|failure| failure.into_error())?;
+    file.sync_all()
+}
+
+/// Restores the data dictionary and the model state from a snapshot written via
+/// `write_snapshot`. Fails with an error of kind `InvalidData` if the file is not a snapshot or
+/// was written with an incompatible format version.
+pub fn read_snapshot(path: &str) -> io::Result<(IndicatorModel, DataDictionary)> {
+
+    let mut input = BufReader::new(File::open(Path::new(path))?);
+
+    let mut magic = [0u8; 8];
+    input.read_exact(&mut magic)
+        .map_err(|_| invalid_data(format!("{} is not a recoreco snapshot", path)))?;
+
+    if &magic != MAGIC {
+        return Err(invalid_data(format!("{} is not a recoreco snapshot", path)));
+    }
+
+    let version = read_u32(&mut input)?;
+    if version != FORMAT_VERSION {
+        return Err(invalid_data(format!(
+            "Snapshot {} has format version {}, but this version of recoreco only supports \
+            format version {}",
+            path,
+            version,
+            FORMAT_VERSION,
+        )));
+    }
+
+    let data_dict = DataDictionary::read_state(&mut input)?;
+    let model = IndicatorModel::read_state(&mut input)?;
+
+    Ok((model, data_dict))
+}
+
+/// Upper bound for the number of elements which we preallocate for a collection read from a
+/// snapshot. Longer collections grow while we read them, so that a corrupt length results in an
+/// error at the end of the file instead of a huge allocation.
+const MAX_PREALLOCATED_LENGTH: usize = 4096;
+
+/// The capacity to preallocate for a collection with the given `length` from a snapshot
+pub(crate) fn capacity_for(length: usize) -> usize {
+    length.min(MAX_PREALLOCATED_LENGTH)
+}
+
+pub(crate) fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub(crate) fn write_u32<W: Write>(out: &mut W, value: u32) -> io::Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+pub(crate) fn write_u64<W: Write>(out: &mut W, value: u64) -> io::Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+pub(crate) fn write_f64<W: Write>(out: &mut W, value: f64) -> io::Result<()> {
+    out.write_all(&value.to_bits().to_le_bytes())
+}
+
+pub(crate) fn write_string<W: Write>(out: &mut W, value: &str) -> io::Result<()> {
+    write_u32(out, value.len() as u32)?;
+    out.write_all(value.as_bytes())
+}
+
+pub(crate) fn write_items<W: Write>(out: &mut W, items: &[u32]) -> io::Result<()> {
+    write_u32(out, items.len() as u32)?;
+    for item in items {
+        write_u32(out, *item)?;
+    }
+    Ok(())
+}
+
+/// Writes the kind of the weighting, followed by its argument as eight bytes: the bits of the
+/// threshold, the number of interactions to keep for top-k, and zero otherwise
+pub(crate) fn write_weighting<W: Write>(out: &mut W, weighting: Weighting) -> io::Result<()> {
+    let (kind, argument) = match weighting {
+        Weighting::Unweighted => (0, 0),
+        Weighting::Threshold(threshold) => (1, threshold.to_bits()),
+        Weighting::LogPriority => (2, 0),
+        Weighting::TopK(k) => (3, k as u64),
+    };
+    write_u32(out, kind)?;
+    write_u64(out, argument)
+}
+
+pub(crate) fn read_u32<R: Read>(input: &mut R) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub(crate) fn read_u64<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub(crate) fn read_f64<R: Read>(input: &mut R) -> io::Result<f64> {
+    Ok(f64::from_bits(read_u64(input)?))
+}
+
+pub(crate) fn read_string<R: Read>(input: &mut R) -> io::Result<String> {
+    let length = u64::from(read_u32(input)?);
+    let mut bytes = Vec::with_capacity(capacity_for(length as usize));
+    input.by_ref().take(length).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != length {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated identifier"));
+    }
+    String::from_utf8(bytes).map_err(|_| invalid_data(String::from("Invalid identifier")))
+}
+
+pub(crate) fn read_weighting<R: Read>(input: &mut R) -> io::Result<Weighting> {
+    let kind = read_u32(input)?;
+    let argument = read_u64(input)?;
+    match (kind, argument) {
+        (0, _) => Ok(Weighting::Unweighted),
+        (1, _) => Ok(Weighting::Threshold(f64::from_bits(argument))),
+        (2, _) => Ok(Weighting::LogPriority),
+        (3, k) if k > 0 && k <= usize::MAX as u64 => Ok(Weighting::TopK(k as usize)),
+        _ => Err(invalid_data(String::from("Invalid weighting"))),
+    }
+}
+
+pub(crate) fn read_items<R: Read>(input: &mut R) -> io::Result<Vec<u32>> {
+    let length = read_u32(input)? as usize;
+    let mut items = Vec::with_capacity(capacity_for(length));
+    for _ in 0..length {
+        items.push(read_u32(input)?);
+    }
+    Ok(items)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+    use std::process;
+
+    use model::IndicatorModel;
+    use stats::DataDictionary;
+    use snapshot::{read_weighting, write_weighting, write_snapshot, read_snapshot};
+    use snapshot::temporary_path_for;
+    use weights::Weighting;
+
+    /// A path in the temporary directory which is unique to this test and process
+    fn temp_path(test: &str) -> PathBuf {
+        env::temp_dir().join(format!("recoreco-snapshot-{}-{}.bin", test, process::id()))
+    }
+
+    fn interactions(users: usize, offset: usize) -> Vec<(String, String)> {
+        let mut interactions = Vec::new();
+        for user in 0..users {
+            for item in 0..(user % 7 + 2) {
+                let item = (item * 3 + user + offset) % 11;
+                interactions.push((format!("user_{}", user), format!("item_{}", item)));
+            }
+        }
+        interactions
+    }
+
+    #[test]
+    fn resume_from_snapshot() {
+
+        let path = temp_path("resume");
+        let path = path.to_str().unwrap();
+
+        let first = interactions(30, 0);
+        let second = interactions(40, 5);
+
+        // Process both batches without interruption
+        let mut data_dict = DataDictionary::from(first.iter());
+        data_dict.extend(second.clone().into_iter()).unwrap();
+
+        let mut model = IndicatorModel::new(0, 0, 20, 500, 4, 42).unwrap();
+        model.update_all(first.clone().into_iter(), &data_dict).unwrap();
+        model.refresh();
+        model.update_all(second.clone().into_iter(), &data_dict).unwrap();
+        model.refresh();
+
+        // Process the first batch, checkpoint, restore and continue with the second batch
+        let partial_data_dict = DataDictionary::from(first.iter());
+        let mut partial_model = IndicatorModel::new(0, 0, 20, 500, 4, 42).unwrap();
+        partial_model.update_all(first.into_iter(), &partial_data_dict).unwrap();
+        partial_model.refresh();
+
+        write_snapshot(path, &partial_model, &partial_data_dict).unwrap();
+        let (mut restored_model, mut restored_data_dict) = read_snapshot(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(restored_data_dict.num_interactions(), partial_data_dict.num_interactions());
+        assert_eq!(restored_model.indicators(), partial_model.indicators());
+        assert_eq!(restored_model.seed(), 42);
+
+        restored_data_dict.extend(second.clone().into_iter()).unwrap();
+        restored_model.update_all(second.into_iter(), &restored_data_dict).unwrap();
+        restored_model.refresh();
+
+        assert_eq!(restored_data_dict.num_interactions(), data_dict.num_interactions());
+        assert_eq!(restored_model.num_cooccurrences_observed(), model.num_cooccurrences_observed());
+        assert_eq!(restored_model.indicators().to_binary(), model.indicators().to_binary());
+    }
+
+    #[test]
+    fn replace_snapshots_atomically() {
+
+        let path = temp_path("replace");
+        let path = path.to_str().unwrap();
+
+        fs::write(path, b"previous snapshot").unwrap();
+
+        let data_dict = DataDictionary::from(interactions(10, 0).iter());
+        let mut model = IndicatorModel::new(0, 0, 20, 500, 4, 42).unwrap();
+        model.update_all(interactions(10, 0).into_iter(), &data_dict).unwrap();
+        model.refresh();
+
+        write_snapshot(path, &model, &data_dict).unwrap();
+
+        assert!(!Path::new(&temporary_path_for(path)).exists());
+        let (restored_model, _) = read_snapshot(path).unwrap();
+        assert_eq!(restored_model.seed(), 42);
+        fs::remove_file(path).unwrap();
+
+        // A failed write leaves neither a snapshot nor a temporary file behind
+        let missing_directory = temp_path("missing-directory").join("snapshot.bin");
+        let missing_directory = missing_directory.to_str().unwrap();
+
+        assert!(write_snapshot(missing_directory, &model, &data_dict).is_err());
+        assert!(!Path::new(missing_directory).exists());
+        assert!(!Path::new(&temporary_path_for(missing_directory)).exists());
+    }
+
+    #[test]
+    fn reject_incompatible_snapshots() {
+
+        let path = temp_path("incompatible");
+        let path = path.to_str().unwrap();
+
+        let mut file = fs::File::create(path).unwrap();
+        file.write_all(b"RECORECO").unwrap();
+        file.write_all(&999u32.to_le_bytes()).unwrap();
+        drop(file);
+
+        let error = read_snapshot(path).err().unwrap();
+        assert!(error.to_string().contains("format version 999"));
+
+        fs::write(path, b"something else").unwrap();
+        let error = read_snapshot(path).err().unwrap();
+        assert!(error.to_string().contains("not a recoreco snapshot"));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn weightings_round_trip() {
+
+        let weightings = [
+            Weighting::Unweighted,
+            Weighting::Threshold(3.5),
+            Weighting::LogPriority,
+            Weighting::TopK((1 << 53) + 1),
+        ];
+
+        for weighting in weightings.iter() {
+            let mut state = Vec::new();
+            write_weighting(&mut state, *weighting).unwrap();
+            assert_eq!(read_weighting(&mut &state[..]).unwrap(), *weighting);
+        }
+    }
+
+    #[test]
+    fn reject_corrupt_snapshots() {
+
+        let path = temp_path("corrupt");
+        let path = path.to_str().unwrap();
+
+        let interactions = interactions(5, 0);
+        let data_dict = DataDictionary::from(interactions.iter());
+        let mut model = IndicatorModel::new(0, 0, 10, 500, 500, 42).unwrap();
+        model.update_all(interactions.into_iter(), &data_dict).unwrap();
+        model.refresh();
+
+        write_snapshot(path, &model, &data_dict).unwrap();
+        let snapshot = fs::read(path).unwrap();
+
+        // The model state starts with the number of indicators per item, f_max and k_max
+        let model_start = snapshot.len() - model_state_len(&model);
+        let corruptions: Vec<(usize, &[u8])> = vec![
+            // No indicators per item
+            (model_start, &[0u8; 8]),
+            // An f_max and k_max whose product overflows
+            (model_start + 8, &[0xFFu8; 8]),
+            // A huge length of the first name in the data dictionary
+            (8 + 4 + 8 + 8 + 8 + 4, &[0xFFu8; 4]),
+            // A number of observed cooccurrences which does not match the counts, followed by no
+            // dirty items
+            (snapshot.len() - 12, &[1u8; 8]),
+        ];
+
+        for (offset, bytes) in corruptions {
+            let mut corrupt = snapshot.clone();
+            corrupt[offset..offset + bytes.len()].copy_from_slice(bytes);
+            fs::write(path, &corrupt).unwrap();
+
+            let error = read_snapshot(path).err().unwrap();
+            assert!(
+                error.kind() == io::ErrorKind::InvalidData
+                    || error.kind() == io::ErrorKind::UnexpectedEof,
+                "{:?}",
+                error,
+            );
+        }
+
+        // A truncated snapshot
+        fs::write(path, &snapshot[..snapshot.len() - 3]).unwrap();
+        assert!(read_snapshot(path).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    fn model_state_len(model: &IndicatorModel) -> usize {
+        let mut state = Vec::new();
+        model.write_state(&mut state).unwrap();
+        state.len()
+    }
+
+    #[test]
+    fn resume_weighted_model() {
+
+        let path = temp_path("weighted");
+        let path = path.to_str().unwrap();
+
+        let mut data_dict = DataDictionary::default();
+        let mut model = IndicatorModel::new(0, 0, 10, 500, 500, 42).unwrap();
+        model.set_weighting(Weighting::TopK(2)).unwrap();
+
+        for (user, item, weight) in [("a", "x", 3.0), ("a", "y", 1.0), ("a", "z", 2.0)] {
+            let (user, item) =
+                data_dict.register_weighted(user.into(), item.into(), weight).unwrap();
+            model.update_weighted(user, item, weight);
+        }
+        model.refresh();
+
+        write_snapshot(path, &model, &data_dict).unwrap();
+        let (mut restored_model, restored_data_dict) = read_snapshot(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(restored_model.weighting(), Weighting::TopK(2));
+        assert!((restored_data_dict.total_weight() - 6.0).abs() < f64::EPSILON);
+
+        // The restored priorities decide which item is replaced next
+        restored_model.update_weighted(0, 3, 2.5);
+        model.update_weighted(0, 3, 2.5);
+        restored_model.refresh();
+        model.refresh();
+
+        assert_eq!(restored_model.indicators(), model.indicators());
+    }
+}
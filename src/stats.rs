@@ -5,7 +5,7 @@
 //! keep track of the string identifiers of users and items as well as the overall number of
 //! interactions in order to map back and forth between the two representations.
 //!
-/**
+/*
  * RecoReco
  * Copyright (C) 2018 Sebastian Schelter
  *
@@ -26,13 +26,25 @@
 extern crate fnv;
 extern crate csv;
 
+use std::collections::hash_map::Entry;
+use std::io;
+use std::io::prelude::*;
+
 use fnv::FnvHashMap;
 
+use dedup::Deduplicator;
+use error::Error;
+use snapshot;
+use weights::Weighting;
+
 /// Mapping from original string based identifiers to internal `u32` indexes.
+#[derive(Default)]
 pub struct DataDictionary {
     user_dict: FnvHashMap<String, u32>,
     item_dict: FnvHashMap<String, u32>,
     num_interactions: u64,
+    num_duplicates: u64,
+    total_weight: f64,
 }
 
 impl DataDictionary {
@@ -52,55 +64,165 @@ impl DataDictionary {
         self.num_interactions
     }
 
-    /// Returns the internal index for the user with the string identifier `name`
-    pub fn user_index(&self, name: &str) -> &u32 {
-        &self.user_dict[name]
+    /// Returns the number of repeated interactions of users with the same item which were
+    /// registered via `register_deduplicated`, and are not included in the number of
+    /// interactions, see `recoreco::dedup`.
+    pub fn num_duplicates(&self) -> u64 {
+        self.num_duplicates
     }
 
-    /// Returns the internal index for the item with the string identifier `name`
-    pub fn item_index(&self, name: &str) -> &u32 {
-        &self.item_dict[name]
+    /// Returns the overall weight of the interactions in the dataset, which equals the number of
+    /// interactions for unweighted data.
+    pub fn total_weight(&self) -> f64 {
+        self.total_weight
     }
 
-    /// Builds up a `DataDictionary` by consuming an iterator over string tuples representing
-    /// user-item interactions. We assume that the first string in the tuple identifies a user and
-    /// the second string identifies an item
-    pub fn from_owned<T>(interactions: T) -> Self
-    where
-        T: Iterator<Item = (String, String)>
-    {
-        let mut user_index: u32 = 0;
-        let mut user_dict: FnvHashMap<String, u32> = FnvHashMap::default();
+    /// Returns the internal index for the user with the string identifier `name`, fails with
+    /// `Error::UnknownUser` if the user is unknown
+    pub fn user_index(&self, name: &str) -> Result<u32, Error> {
+        self.find_user_index(name).ok_or_else(|| Error::UnknownUser(name.to_owned()))
+    }
 
-        let mut item_index: u32 = 0;
-        let mut item_dict: FnvHashMap<String, u32> = FnvHashMap::default();
+    /// Returns the internal index for the item with the string identifier `name`, fails with
+    /// `Error::UnknownItem` if the item is unknown
+    pub fn item_index(&self, name: &str) -> Result<u32, Error> {
+        self.find_item_index(name).ok_or_else(|| Error::UnknownItem(name.to_owned()))
+    }
 
-        let mut num_interactions: u64 = 0;
+    /// Returns the internal index for the user with the string identifier `name`, or `None` if the
+    /// user is unknown
+    pub fn find_user_index(&self, name: &str) -> Option<u32> {
+        self.user_dict.get(name).cloned()
+    }
 
-        for (user, item) in interactions {
+    /// Returns the internal index for the item with the string identifier `name`, or `None` if the
+    /// item is unknown
+    pub fn find_item_index(&self, name: &str) -> Option<u32> {
+        self.item_dict.get(name).cloned()
+    }
 
-            user_dict.entry(user).or_insert_with(|| {
-                let current_user_index = user_index;
-                user_index += 1;
-                current_user_index
-            });
+    /// Registers an interaction between the user with the string identifier `user` and the item
+    /// with the string identifier `item`. Previously unseen users and items are assigned the next
+    /// free internal index. Returns the internal indexes of the user and the item, or fails with
+    /// `Error::Overflow` if there are no free internal indexes left. Every call counts as an
+    /// interaction, `register_deduplicated` counts repetitions of a pair as duplicates instead.
+    pub fn register(&mut self, user: String, item: String) -> Result<(u32, u32), Error> {
+        self.register_weighted(user, item, 1.0)
+    }
 
-            item_dict.entry(item).or_insert_with(|| {
-                let current_item_index = item_index;
-                item_index += 1;
-                current_item_index
-            });
+    /// Registers an interaction with the given `weight`, see `register`.
+    pub fn register_weighted(
+        &mut self,
+        user: String,
+        item: String,
+        weight: f64,
+    ) -> Result<(u32, u32), Error> {
+
+        let (user_index, item_index) = self.index(user, item)?;
+
+        self.num_interactions += 1;
+        self.total_weight += weight;
+
+        Ok((user_index, item_index))
+    }
+
+    /// Assigns the next free internal indexes to previously unseen users and items
+    fn index(&mut self, user: String, item: String) -> Result<(u32, u32), Error> {
+        let user_index = next_free_index(&mut self.user_dict, user, "users")?;
+        let item_index = next_free_index(&mut self.item_dict, item, "items")?;
+
+        Ok((user_index, item_index))
+    }
+
+    /// Registers an interaction with the given `weight` and hands it to the `deduplicator`.
+    /// Repetitions of a previously registered interaction are only counted as duplicates. Returns
+    /// the internal indexes of the user and the item for the first interaction of the pair.
+    pub fn register_deduplicated(
+        &mut self,
+        user: String,
+        item: String,
+        weight: f64,
+        deduplicator: &mut Deduplicator,
+    ) -> Result<Option<(u32, u32)>, Error> {
+
+        let (user_index, item_index) = self.index(user, item)?;
+        self.total_weight += weight;
+
+        if deduplicator.observe(user_index, item_index, weight) {
+            self.num_interactions += 1;
+            Ok(Some((user_index, item_index)))
+        } else {
+            self.num_duplicates += 1;
+            Ok(None)
+        }
+    }
+
+    /// Registers all weighted interactions which are admitted by the `weighting` and hands them
+    /// to the `deduplicator`, which combines the weights of repeated interactions for a second
+    /// pass over the interactions, see `recoreco::dedup::Deduplicator::take`.
+    pub fn extend_deduplicated<T>(
+        &mut self,
+        interactions: T,
+        weighting: &Weighting,
+        deduplicator: &mut Deduplicator,
+    ) -> Result<(), Error>
+    where
+        T: Iterator<Item = (String, String, f64)>
+    {
+        for (user, item, weight) in interactions {
+            if weighting.admits(weight) {
+                self.register_deduplicated(user, item, weight, deduplicator)?;
+            }
+        }
+        Ok(())
+    }
 
-            num_interactions += 1;
+    /// Registers all weighted interactions from an iterator over string tuples with weights,
+    /// which are admitted by the `weighting`. Interactions which are not admitted are skipped, so
+    /// that the statistics describe the interactions that we account for. Repetitions of a
+    /// user-item pair count as interactions, see `extend_deduplicated` to count them as duplicates.
+    pub fn extend_weighted<T>(
+        &mut self,
+        interactions: T,
+        weighting: &Weighting,
+    ) -> Result<(), Error>
+    where
+        T: Iterator<Item = (String, String, f64)>
+    {
+        for (user, item, weight) in interactions {
+            if weighting.admits(weight) {
+                self.register_weighted(user, item, weight)?;
+            }
         }
+        Ok(())
+    }
 
-        DataDictionary { user_dict, item_dict, num_interactions }
+    /// Registers all interactions from an iterator over string tuples representing user-item
+    /// interactions, e.g., to add new interactions to a restored `DataDictionary`.
+    pub fn extend<T>(&mut self, interactions: T) -> Result<(), Error>
+    where
+        T: Iterator<Item = (String, String)>
+    {
+        let interactions = interactions.map(|(user, item)| (user, item, 1.0));
+        self.extend_weighted(interactions, &Weighting::Unweighted)
+    }
+
+    /// Builds up a `DataDictionary` by consuming an iterator over string tuples representing
+    /// user-item interactions. We assume that the first string in the tuple identifies a user and
+    /// the second string identifies an item. Fails with `Error::Overflow` if there are too many
+    /// users or items for `u32` indexes.
+    pub fn try_from_owned<T>(interactions: T) -> Result<Self, Error>
+    where
+        T: Iterator<Item = (String, String)>
+    {
+        let mut data_dict = DataDictionary::default();
+        data_dict.extend(interactions)?;
+        Ok(data_dict)
     }
 
     /// Builds up a `DataDictionary` by reading an iterator over references to string tuples
-    /// representing user-item interactions. We assume that the first string in the tuple
-    /// identifies a user and the second string identifies an item
-    pub fn from<'a,T>(interactions: T) -> DataDictionary
+    /// representing user-item interactions, see `try_from_owned`.
+    pub fn try_from<'a, T>(interactions: T) -> Result<Self, Error>
     where
         T: Iterator<Item = &'a(String, String)>
     {
@@ -108,56 +230,126 @@ impl DataDictionary {
         let owned = interactions
             .map(|(user, item)| (user.to_owned(), item.to_owned()));
 
-        DataDictionary::from_owned(owned)
+        DataDictionary::try_from_owned(owned)
     }
-}
 
-/// Builds up a `DataDictionary` by reading an iterator over string tuples representing
-/// user-item interactions. We assume that the first string in the tuple identifies a user and
-/// the second string identifies an item
-impl <T> From<T> for DataDictionary
-where
-    T: Iterator<Item = (String, String)>
-{
-    fn from(iter: T) -> Self {
-        let mut user_index: u32 = 0;
-        let mut user_dict: FnvHashMap<String, u32> = FnvHashMap::default();
+    /// Builds up a `DataDictionary` like `try_from_owned`, but panics if there are too many users
+    /// or items for `u32` indexes.
+    pub fn from_owned<T>(interactions: T) -> Self
+    where
+        T: Iterator<Item = (String, String)>
+    {
+        DataDictionary::try_from_owned(interactions).unwrap_or_else(|error| panic!("{}", error))
+    }
 
-        let mut item_index: u32 = 0;
-        let mut item_dict: FnvHashMap<String, u32> = FnvHashMap::default();
+    /// Builds up a `DataDictionary` like `try_from`, but panics if there are too many users or
+    /// items for `u32` indexes.
+    pub fn from<'a,T>(interactions: T) -> DataDictionary
+    where
+        T: Iterator<Item = &'a(String, String)>
+    {
+        DataDictionary::try_from(interactions).unwrap_or_else(|error| panic!("{}", error))
+    }
 
-        let mut num_interactions: u64 = 0;
+    /// Writes the dictionary to a snapshot, see `recoreco::snapshot`
+    pub(crate) fn write_state<W: Write>(&self, out: &mut W) -> io::Result<()> {
 
-        for (user, item) in iter {
+        snapshot::write_u64(out, self.num_interactions)?;
+        snapshot::write_u64(out, self.num_duplicates)?;
+        snapshot::write_f64(out, self.total_weight)?;
 
-            user_dict.entry(user).or_insert_with(|| {
-                let current_user_index = user_index;
-                user_index += 1;
-                current_user_index
-            });
+        for dict in &[&self.user_dict, &self.item_dict] {
+            let mut names: Vec<(&String, &u32)> = dict.iter().collect();
+            names.sort_by_key(|(_, index)| **index);
 
-            item_dict.entry(item).or_insert_with(|| {
-                let current_item_index = item_index;
-                item_index += 1;
-                current_item_index
-            });
+            snapshot::write_u32(out, names.len() as u32)?;
+            for (name, _) in names {
+                snapshot::write_string(out, name)?;
+            }
+        }
+
+        Ok(())
+    }
 
-            num_interactions += 1;
+    /// Restores the dictionary from a snapshot, see `recoreco::snapshot`
+    pub(crate) fn read_state<R: Read>(input: &mut R) -> io::Result<Self> {
+
+        let num_interactions = snapshot::read_u64(input)?;
+        let num_duplicates = snapshot::read_u64(input)?;
+        let total_weight = snapshot::read_f64(input)?;
+
+        let mut dicts = Vec::with_capacity(2);
+        for _ in 0..2 {
+            let num_names = snapshot::read_u32(input)?;
+            let mut dict: FnvHashMap<String, u32> = FnvHashMap::default();
+            for index in 0..num_names {
+                let name = snapshot::read_string(input)?;
+                if dict.insert(name, index).is_some() {
+                    return Err(snapshot::invalid_data(String::from("Duplicate identifier")));
+                }
+            }
+            dicts.push(dict);
         }
 
-        DataDictionary { user_dict, item_dict, num_interactions }
+        let item_dict = dicts.pop().unwrap();
+        let user_dict = dicts.pop().unwrap();
+
+        Ok(DataDictionary { user_dict, item_dict, num_interactions, num_duplicates, total_weight })
+    }
+}
+
+/// Assigns the next free internal index to `name` if it is unknown to `dict`, and returns its
+/// internal index. Indexes are `u32` values, and `u32::MAX` is reserved so that the number of
+/// users and items also fits into a `u32`.
+fn next_free_index(
+    dict: &mut FnvHashMap<String, u32>,
+    name: String,
+    kind: &str,
+) -> Result<u32, Error> {
+
+    let next_index = dict.len();
+
+    match dict.entry(name) {
+        Entry::Occupied(entry) => Ok(*entry.get()),
+        Entry::Vacant(entry) => {
+            if next_index >= u32::MAX as usize {
+                return Err(Error::Overflow(format!("More than {} {}", u32::MAX - 1, kind)));
+            }
+            Ok(*entry.insert(next_index as u32))
+        },
     }
 }
 
-/// Allows to remap the internal item indexes to the original string identifiers
+/// Builds up a `DataDictionary` by reading an iterator over string tuples representing
+/// user-item interactions. We assume that the first string in the tuple identifies a user and
+/// the second string identifies an item. Panics if there are too many users or items for `u32`
+/// indexes, `DataDictionary::try_from_owned` fails with an error instead.
+impl <T> From<T> for DataDictionary
+where
+    T: Iterator<Item = (String, String)>
+{
+    fn from(iter: T) -> Self {
+        DataDictionary::from_owned(iter)
+    }
+}
+
+/// Allows to remap the internal user and item indexes to the original string identifiers
 pub struct Renaming {
+    user_names: FnvHashMap<u32, String>,
     item_names: FnvHashMap<u32, String>,
 }
 
 impl Renaming {
-    /// Return original string identifier for the internal index `item_index`
-    pub fn item_name(&self, item_index: u32) -> &str {
-        &self.item_names[&item_index]
+    /// Return original string identifier for the internal index `user_index`, or `None` if there
+    /// is no user with this index
+    pub fn user_name(&self, user_index: u32) -> Option<&str> {
+        self.user_names.get(&user_index).map(String::as_str)
+    }
+
+    /// Return original string identifier for the internal index `item_index`, or `None` if there
+    /// is no item with this index
+    pub fn item_name(&self, item_index: u32) -> Option<&str> {
+        self.item_names.get(&item_index).map(String::as_str)
     }
 }
 
@@ -165,13 +357,19 @@ impl Renaming {
 impl From<DataDictionary> for Renaming {
 
     fn from(data_dict: DataDictionary) -> Self {
+        let user_names: FnvHashMap<u32, String> = data_dict
+            .user_dict
+            .into_iter()
+            .map(|(name, user_id)| (user_id, name))
+            .collect();
+
         let item_names: FnvHashMap<u32, String> = data_dict
             .item_dict
             .into_iter()
             .map(|(name, item_id)| (item_id, name))
             .collect(); // Checked that size_hint() gives correct bounds
 
-        Renaming { item_names }
+        Renaming { user_names, item_names }
     }
 }
 
@@ -182,12 +380,14 @@ mod tests {
     extern crate fnv;
 
     use fnv::FnvHashMap;
+    use dedup::{Deduplication, Deduplicator};
     use stats::{DataDictionary, Renaming};
+    use weights::Weighting;
 
     #[test]
     fn dict_from_tuple_iterator() {
 
-        let interactions = vec![
+        let interactions = [
             (String::from("user_a"), String::from("item_a")),
             (String::from("user_a"), String::from("item_b")),
             (String::from("user_b"), String::from("item_b")),
@@ -200,11 +400,18 @@ mod tests {
         assert_eq!(data_dict.num_items(), 2);
         assert_eq!(data_dict.num_interactions(), 4);
 
-        assert_eq!(*data_dict.user_index("user_a"), 0);
-        assert_eq!(*data_dict.user_index("user_c"), 2);
+        assert_eq!(data_dict.user_index("user_a").unwrap(), 0);
+        assert_eq!(data_dict.user_index("user_c").unwrap(), 2);
+
+        assert_eq!(data_dict.item_index("item_a").unwrap(), 0);
+        assert_eq!(data_dict.item_index("item_b").unwrap(), 1);
+
+        assert!(data_dict.user_index("user_d").is_err());
+        assert_eq!(data_dict.find_item_index("item_c"), None);
 
-        assert_eq!(*data_dict.item_index("item_a"), 0);
-        assert_eq!(*data_dict.item_index("item_b"), 1);
+        let checked = DataDictionary::try_from(interactions.iter()).unwrap();
+        assert_eq!(checked.num_users(), 3);
+        assert_eq!(checked.item_index("item_b").unwrap(), 1);
 
         // Make sure we don't lose ownership of interactions
         assert_eq!(interactions.len(), 4);
@@ -226,11 +433,53 @@ mod tests {
         assert_eq!(data_dict.num_items(), 2);
         assert_eq!(data_dict.num_interactions(), 4);
 
-        assert_eq!(*data_dict.user_index("user_a"), 0);
-        assert_eq!(*data_dict.user_index("user_c"), 2);
+        assert_eq!(data_dict.user_index("user_a").unwrap(), 0);
+        assert_eq!(data_dict.user_index("user_c").unwrap(), 2);
+
+        assert_eq!(data_dict.item_index("item_a").unwrap(), 0);
+        assert_eq!(data_dict.item_index("item_b").unwrap(), 1);
+    }
+
+    #[test]
+    fn dict_from_weighted_interactions() {
+
+        let interactions = vec![
+            (String::from("user_a"), String::from("item_a"), 5.0),
+            (String::from("user_a"), String::from("item_b"), 1.0),
+            (String::from("user_b"), String::from("item_a"), 4.0),
+        ];
+
+        let mut data_dict = DataDictionary::default();
+        data_dict.extend_weighted(interactions.into_iter(), &Weighting::Threshold(4.0)).unwrap();
+
+        assert_eq!(data_dict.num_users(), 2);
+        assert_eq!(data_dict.num_items(), 1);
+        assert_eq!(data_dict.num_interactions(), 2);
+        assert!((data_dict.total_weight() - 9.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn dict_counts_duplicates() {
+
+        let interactions = vec![
+            (String::from("user_a"), String::from("item_a"), 1.0),
+            (String::from("user_a"), String::from("item_b"), 1.0),
+            (String::from("user_a"), String::from("item_a"), 1.0),
+            (String::from("user_b"), String::from("item_a"), 1.0),
+            (String::from("user_a"), String::from("item_a"), 1.0),
+        ];
 
-        assert_eq!(*data_dict.item_index("item_a"), 0);
-        assert_eq!(*data_dict.item_index("item_b"), 1);
+        let mut deduplicator = Deduplicator::new(Deduplication::SumWeights);
+        let mut data_dict = DataDictionary::default();
+        data_dict.extend_deduplicated(
+            interactions.into_iter(),
+            &Weighting::Unweighted,
+            &mut deduplicator,
+        ).unwrap();
+
+        assert_eq!(data_dict.num_interactions(), 3);
+        assert_eq!(data_dict.num_duplicates(), 2);
+        assert_eq!(deduplicator.take(0, 0), Some(3.0));
     }
 
     #[test]
@@ -250,12 +499,20 @@ mod tests {
         let user_dict: FnvHashMap<String, u32> = user_mapping.into_iter().collect();
         let item_dict: FnvHashMap<String, u32> = item_mapping.into_iter().collect();
 
-        let data_dict = DataDictionary { user_dict, item_dict, num_interactions: 10 };
+        let data_dict = DataDictionary {
+            user_dict,
+            item_dict,
+            num_interactions: 10,
+            num_duplicates: 0,
+            total_weight: 10.0,
+        };
 
         let renaming: Renaming = data_dict.into();
 
-        assert_eq!(renaming.item_name(0), "item_a");
-        assert_eq!(renaming.item_name(1), "item_b");
-        assert_eq!(renaming.item_name(2), "item_c");
+        assert_eq!(renaming.user_name(1), Some("user_b"));
+        assert_eq!(renaming.item_name(0), Some("item_a"));
+        assert_eq!(renaming.item_name(1), Some("item_b"));
+        assert_eq!(renaming.item_name(2), Some("item_c"));
+        assert_eq!(renaming.item_name(3), None);
     }
 }
\ No newline at end of file
@@ -4,7 +4,7 @@
 //! This module defines the internal representation (e.g., the Rust collections) used for these
 //! matrices.
 //!
-/**
+/*
  * RecoReco
  * Copyright (C) 2018 Sebastian Schelter
  *
@@ -24,15 +24,195 @@
 
 extern crate fnv;
 
+use std::collections::hash_map;
+use std::slice;
+
 use fnv::{FnvHashMap, FnvHashSet};
 
+pub use llr::ScoredItem;
+
 /// 32 bit integer vector, backed by a `Vec<u32>`
 pub type DenseVector = Vec<u32>;
 
-/// Sparse 16 bit integer vector, backed by a `FnvHashMap<u32, u16>`
-pub type SparseVector = FnvHashMap<u32, u16>;
+/// Sparse vector of counts. We store the counts compactly as 16 bit integers, and promote the
+/// whole vector to 32 bit integers as soon as a count exceeds `u16::MAX`. Counts never silently
+/// wrap: a count exceeding `u32::MAX` or decremented below zero results in a panic.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SparseVector {
+    counts: Counts,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Counts {
+    Compact(FnvHashMap<u32, u16>),
+    Wide(FnvHashMap<u32, u32>),
+}
+
+impl Default for SparseVector {
+    fn default() -> Self {
+        SparseVector::with_capacity(0)
+    }
+}
+
+impl SparseVector {
+
+    /// Allocates an empty vector with compact counts, which can hold `capacity` entries without
+    /// reallocating
+    pub fn with_capacity(capacity: usize) -> Self {
+        let counts = FnvHashMap::with_capacity_and_hasher(capacity, Default::default());
+        SparseVector { counts: Counts::Compact(counts) }
+    }
+
+    /// Returns the number of non-zero entries
+    pub fn len(&self) -> usize {
+        match self.counts {
+            Counts::Compact(ref counts) => counts.len(),
+            Counts::Wide(ref counts) => counts.len(),
+        }
+    }
+
+    /// Returns true if the vector has no non-zero entries
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns true if the counts have been promoted to 32 bit integers
+    pub fn is_wide(&self) -> bool {
+        match self.counts {
+            Counts::Compact(_) => false,
+            Counts::Wide(_) => true,
+        }
+    }
+
+    /// Returns the count at `index`, which is zero for absent entries
+    pub fn get(&self, index: u32) -> u32 {
+        match self.counts {
+            Counts::Compact(ref counts) => counts.get(&index).map_or(0, |count| u32::from(*count)),
+            Counts::Wide(ref counts) => counts.get(&index).cloned().unwrap_or(0),
+        }
+    }
+
+    /// Increments the count at `index` by one
+    pub fn increment(&mut self, index: u32) {
+        let overflows = match self.counts {
+            Counts::Compact(ref mut counts) => {
+                let count = counts.entry(index).or_insert(0);
+                match count.checked_add(1) {
+                    Some(incremented) => {
+                        *count = incremented;
+                        false
+                    },
+                    None => true,
+                }
+            },
+            Counts::Wide(ref mut counts) => {
+                let count = counts.entry(index).or_insert(0);
+                *count = count.checked_add(1).expect("Count exceeds u32::MAX");
+                false
+            },
+        };
+
+        if overflows {
+            self.promote();
+            self.increment(index);
+        }
+    }
+
+    /// Decrements the count at `index` by one, entries which reach zero are removed
+    pub fn decrement(&mut self, index: u32) {
+        let reaches_zero = match self.counts {
+            Counts::Compact(ref mut counts) => decrement_entry(counts, index),
+            Counts::Wide(ref mut counts) => decrement_entry(counts, index),
+        };
+
+        if reaches_zero {
+            self.insert(index, 0);
+        }
+    }
+
+    /// Sets the count at `index`, promoting the vector if necessary
+    pub fn insert(&mut self, index: u32, count: u32) {
+        if count == 0 {
+            match self.counts {
+                Counts::Compact(ref mut counts) => counts.remove(&index).map(u32::from),
+                Counts::Wide(ref mut counts) => counts.remove(&index),
+            };
+            return;
+        }
+
+        if count > u32::from(u16::MAX) {
+            self.promote();
+        }
+
+        match self.counts {
+            Counts::Compact(ref mut counts) => counts.insert(index, count as u16).map(u32::from),
+            Counts::Wide(ref mut counts) => counts.insert(index, count),
+        };
+    }
+
+    /// Adds the counts of `other` to this vector
+    pub fn add(&mut self, other: &SparseVector) {
+        for (index, count) in other.iter() {
+            let sum = self.get(index).checked_add(count).expect("Count exceeds u32::MAX");
+            self.insert(index, sum);
+        }
+    }
 
-/// Sparse 16 bit integer matrix, row-wise representation, backed by a `Vec<FnvHashMap<u32, u16>>`
+    /// Iterates over the non-zero entries as pairs of index and count, in arbitrary order
+    pub fn iter<'a>(&'a self) -> SparseVectorIter<'a> {
+        match self.counts {
+            Counts::Compact(ref counts) => SparseVectorIter::Compact(counts.iter()),
+            Counts::Wide(ref counts) => SparseVectorIter::Wide(counts.iter()),
+        }
+    }
+
+    fn promote(&mut self) {
+        let promoted = match self.counts {
+            Counts::Compact(ref counts) => {
+                counts.iter().map(|(index, count)| (*index, u32::from(*count))).collect()
+            },
+            Counts::Wide(_) => return,
+        };
+        self.counts = Counts::Wide(promoted);
+    }
+}
+
+/// Decrements an entry and returns whether it reached zero. Absent entries are zero, so we panic
+/// instead of wrapping around. Present entries are always positive.
+fn decrement_entry<T>(counts: &mut FnvHashMap<u32, T>, index: u32) -> bool
+where
+    T: Copy + PartialEq + From<u8> + ::std::ops::Sub<Output = T>
+{
+    let count = counts.get_mut(&index).expect("Decremented a count below zero");
+    *count = *count - T::from(1);
+    *count == T::from(0)
+}
+
+/// Iterator over the non-zero entries of a `SparseVector`
+pub enum SparseVectorIter<'a> {
+    #[doc(hidden)]
+    Compact(hash_map::Iter<'a, u32, u16>),
+    #[doc(hidden)]
+    Wide(hash_map::Iter<'a, u32, u32>),
+}
+
+impl<'a> Iterator for SparseVectorIter<'a> {
+    type Item = (u32, u32);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        match *self {
+            SparseVectorIter::Compact(ref mut entries) => {
+                entries.next().map(|(index, count)| (*index, u32::from(*count)))
+            },
+            SparseVectorIter::Wide(ref mut entries) => {
+                entries.next().map(|(index, count)| (*index, *count))
+            },
+        }
+    }
+}
+
+/// Sparse count matrix, row-wise representation, backed by a `Vec<SparseVector>`
 pub type SparseMatrix = Vec<SparseVector>;
 
 /// Sparse binary matrix, row-wise representation, backed by a `Vec<FnvHashSet<u32>>`
@@ -43,7 +223,256 @@ pub fn new_dense_vector(dimensions: usize) -> DenseVector {
     vec![0; dimensions]
 }
 
-/// Allocates a sparse binary matrix with empty rows
+/// Allocates a sparse count matrix with empty rows
 pub fn new_sparse_matrix(num_rows: usize) -> SparseMatrix {
-    vec![FnvHashMap::with_capacity_and_hasher(0, Default::default()); num_rows]
+    vec![SparseVector::default(); num_rows]
+}
+
+/// Compact sparse count matrix in compressed sparse row (CSR) layout. The entries of all rows are
+/// stored consecutively in two arrays of column indexes and counts, sorted by row and column, and
+/// `row_offsets` holds the position of the first entry of every row. This requires 8 bytes per
+/// entry, but the matrix cannot be updated incrementally.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CsrMatrix {
+    row_offsets: Vec<usize>,
+    columns: Vec<u32>,
+    counts: Vec<u32>,
+}
+
+impl CsrMatrix {
+
+    /// Builds a matrix with `num_rows` rows from an iterator over entries of row, column and
+    /// count, which must be sorted by row and column. Panics if the entries are unsorted or
+    /// reference a row beyond `num_rows`.
+    pub fn from_sorted_entries<T>(num_rows: usize, entries: T) -> Self
+    where
+        T: Iterator<Item = (u32, u32, u32)>
+    {
+        let mut row_offsets = Vec::with_capacity(num_rows + 1);
+        let mut columns = Vec::new();
+        let mut counts = Vec::new();
+
+        row_offsets.push(0);
+        let mut previous: Option<(u32, u32)> = None;
+
+        for (row, column, count) in entries {
+            assert!(previous < Some((row, column)), "Entries must be sorted by row and column");
+            assert!((row as usize) < num_rows, "Entry beyond the last row");
+            previous = Some((row, column));
+
+            // Close all rows up to the row of this entry
+            while row_offsets.len() <= row as usize {
+                row_offsets.push(columns.len());
+            }
+
+            columns.push(column);
+            counts.push(count);
+        }
+
+        while row_offsets.len() <= num_rows {
+            row_offsets.push(columns.len());
+        }
+
+        CsrMatrix { row_offsets, columns, counts }
+    }
+
+    /// Copies the rows of a sparse count matrix into the compact layout
+    pub fn from_sparse_matrix(matrix: &[SparseVector]) -> Self {
+        let entries = matrix.iter().enumerate().flat_map(|(row, counts)| {
+            let mut entries: Vec<(u32, u32, u32)> = counts
+                .iter()
+                .map(|(column, count)| (row as u32, column, count))
+                .collect();
+            entries.sort_unstable();
+            entries
+        });
+
+        CsrMatrix::from_sorted_entries(matrix.len(), entries)
+    }
+
+    /// Expands the matrix into a sparse count matrix, which supports incremental updates
+    pub fn to_sparse_matrix(&self) -> SparseMatrix {
+        (0..self.num_rows() as u32)
+            .map(|row| {
+                let mut counts = SparseVector::with_capacity(self.row_len(row));
+                for (column, count) in self.row(row) {
+                    counts.insert(column, count);
+                }
+                counts
+            })
+            .collect()
+    }
+
+    /// Returns the number of rows
+    pub fn num_rows(&self) -> usize {
+        self.row_offsets.len() - 1
+    }
+
+    /// Returns the overall number of non-zero entries
+    pub fn num_entries(&self) -> usize {
+        self.columns.len()
+    }
+
+    /// Returns the number of non-zero entries in `row`
+    pub fn row_len(&self, row: u32) -> usize {
+        self.row_offsets[row as usize + 1] - self.row_offsets[row as usize]
+    }
+
+    /// Returns the count at `row` and `column`, which is zero for absent entries
+    pub fn get(&self, row: u32, column: u32) -> u32 {
+        let start = self.row_offsets[row as usize];
+        let end = self.row_offsets[row as usize + 1];
+        match self.columns[start..end].binary_search(&column) {
+            Ok(position) => self.counts[start + position],
+            Err(_) => 0,
+        }
+    }
+
+    /// Iterates over the non-zero entries of `row` as pairs of column and count, in ascending
+    /// order of the columns
+    pub fn row<'a>(&'a self, row: u32) -> CsrRowIter<'a> {
+        let start = self.row_offsets[row as usize];
+        let end = self.row_offsets[row as usize + 1];
+        CsrRowIter {
+            columns: self.columns[start..end].iter(),
+            counts: self.counts[start..end].iter(),
+        }
+    }
+}
+
+/// Iterator over the non-zero entries of a row of a `CsrMatrix`
+pub struct CsrRowIter<'a> {
+    columns: slice::Iter<'a, u32>,
+    counts: slice::Iter<'a, u32>,
+}
+
+impl<'a> Iterator for CsrRowIter<'a> {
+    type Item = (u32, u32);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        match (self.columns.next(), self.counts.next()) {
+            (Some(column), Some(count)) => Some((*column, *count)),
+            _ => None,
+        }
+    }
+}
+
+/// Indicator matrix, row-wise representation indexed by the internal item index. Row `i` holds the
+/// highly associated items of item `i`, sorted by descending score. There is a row for every item,
+/// which is empty if the item has no indicators.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IndicatorMatrix {
+    rows: Vec<Vec<ScoredItem>>,
+}
+
+impl IndicatorMatrix {
+
+    /// Allocates an indicator matrix with an empty row for each of `num_items` items
+    pub fn new(num_items: usize) -> Self {
+        IndicatorMatrix { rows: vec![Vec::new(); num_items] }
+    }
+
+    /// Returns the number of items (rows) covered by this matrix
+    pub fn num_items(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Returns the ranked indicators of the item with internal index `item`
+    pub fn indicators_for(&self, item: u32) -> &[ScoredItem] {
+        &self.rows[item as usize]
+    }
+
+    /// Grows the matrix to cover `num_items` items, new items get an empty row
+    pub fn resize(&mut self, num_items: usize) {
+        if num_items > self.rows.len() {
+            self.rows.resize(num_items, Vec::new());
+        }
+    }
+
+    /// Replaces the indicators of the item with internal index `item`
+    pub fn set_indicators_for(&mut self, item: u32, indicators: Vec<ScoredItem>) {
+        self.rows[item as usize] = indicators;
+    }
+
+    /// Iterates over all items in order of their internal index, together with their indicators
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (u32, &'a [ScoredItem])> + 'a {
+        self.rows
+            .iter()
+            .enumerate()
+            .map(|(item_index, row)| (item_index as u32, row.as_slice()))
+    }
+
+    /// Projects the indicators onto a sparse binary matrix, dropping the scores
+    pub fn to_binary(&self) -> SparseBinaryMatrix {
+        self.rows
+            .iter()
+            .map(|row| row.iter().map(|scored_item| scored_item.item).collect())
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use types::{new_sparse_matrix, CsrMatrix, SparseVector};
+
+    #[test]
+    fn counts_are_promoted_on_overflow() {
+
+        let mut counts = SparseVector::default();
+        counts.increment(3);
+
+        for _ in 0..70_000 {
+            counts.increment(7);
+        }
+
+        assert!(counts.is_wide());
+        assert_eq!(counts.get(7), 70_000);
+        assert_eq!(counts.get(3), 1);
+        assert_eq!(counts.get(5), 0);
+
+        for _ in 0..69_999 {
+            counts.decrement(7);
+        }
+
+        assert_eq!(counts.get(7), 1);
+        counts.decrement(7);
+        assert_eq!(counts.len(), 1);
+
+        let mut entries: Vec<(u32, u32)> = counts.iter().collect();
+        entries.sort();
+        assert_eq!(entries, vec![(3, 1)]);
+    }
+
+    #[test]
+    fn csr_matrix_from_sparse_matrix() {
+
+        let mut matrix = new_sparse_matrix(4);
+        matrix[0].insert(3, 2);
+        matrix[0].insert(1, 5);
+        matrix[2].insert(0, 70_000);
+
+        let csr = CsrMatrix::from_sparse_matrix(&matrix);
+
+        assert_eq!(csr.num_rows(), 4);
+        assert_eq!(csr.num_entries(), 3);
+        assert_eq!(csr.row(0).collect::<Vec<(u32, u32)>>(), vec![(1, 5), (3, 2)]);
+        assert_eq!(csr.row_len(1), 0);
+        assert_eq!(csr.get(2, 0), 70_000);
+        assert_eq!(csr.get(2, 1), 0);
+        assert_eq!(csr.row(3).count(), 0);
+
+        assert_eq!(csr.to_sparse_matrix(), matrix);
+    }
+
+    #[test]
+    #[should_panic(expected = "below zero")]
+    fn counts_do_not_underflow() {
+        let mut counts = SparseVector::default();
+        counts.increment(1);
+        counts.decrement(1);
+        counts.decrement(1);
+    }
 }
@@ -1,4 +1,4 @@
-/**
+/*
  * RecoReco
  * Copyright (C) 2018 Sebastian Schelter
  *
@@ -19,7 +19,11 @@
 #[cfg(test)]
 mod tests {
 
-    use super::super::indicators;
+    use super::super::{indicators, scored_indicators, single_pass_indicators, cross_indicators};
+    use super::super::{indicators_with_config, cross_indicators_with_config, DEFAULT_SEED};
+    use config::IndicatorConfig;
+    use error::Error;
+    use measures::Measure;
     use stats::{DataDictionary, Renaming};
 
     #[test]
@@ -41,7 +45,7 @@ mod tests {
            statistics of the data for efficient allocation. Therefore, we read the interaction data
            once to compute a data dictionary that helps us map from string to integer identifiers
            and has basic statistics of the data */
-        let data_dict = DataDictionary::from(interactions.iter());
+        let data_dict = DataDictionary::try_from(interactions.iter()).unwrap();
 
         println!(
             "Found {} interactions between {} users and {} items.",
@@ -59,7 +63,8 @@ mod tests {
             10, // The number of highly associated items to compute per item
             500, // The maximum number of interactions to account for per user (use 500 as default)
             500, // The maximum number of interactions to account for per item (use 500 as default)
-        );
+            DEFAULT_SEED, // The seed for the random downsampling of the interactions
+        ).unwrap();
 
         /* The renaming data structure helps us map the integer ids back to the original
            string ids. */
@@ -67,15 +72,338 @@ mod tests {
 
         /* We print the resulting highly associated pairs of items. */
         for (item_index, indicated_items_for_item) in indicated_items.iter().enumerate() {
-            let item_name = renaming.item_name(item_index as u32);
+            let item_name = renaming.item_name(item_index as u32).unwrap();
             println!("Items highly associated with {}:", item_name);
 
             for indicated_item_index in indicated_items_for_item.iter() {
-                let indicated_item_name = renaming.item_name(*indicated_item_index as u32);
+                let indicated_item_name = renaming.item_name(*indicated_item_index).unwrap();
                 println!("\t{}", indicated_item_name);
             }
         }
 
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn scored_indicators_are_ranked() {
+
+        let interactions = vec![
+            (String::from("alice"), String::from("apple")),
+            (String::from("alice"), String::from("dog")),
+            (String::from("alice"), String::from("pony")),
+            (String::from("bob"), String::from("apple")),
+            (String::from("bob"), String::from("pony")),
+            (String::from("charles"), String::from("pony")),
+            (String::from("charles"), String::from("bike")),
+        ];
+
+        let data_dict = DataDictionary::from(interactions.iter());
+
+        let scored_items = scored_indicators(
+            interactions.into_iter(),
+            &data_dict,
+            10,
+            500,
+            500,
+            DEFAULT_SEED,
+        ).unwrap();
+
+        for (_, indicators_for_item) in scored_items.iter() {
+            for pair in indicators_for_item.windows(2) {
+                assert!(pair[0].score >= pair[1].score);
+            }
+        }
+    }
+
+    #[test]
+    fn indicators_are_indexed_by_item() {
+
+        let interactions = vec![
+            (String::from("alice"), String::from("apple")),
+            (String::from("alice"), String::from("dog")),
+            (String::from("bob"), String::from("apple")),
+            (String::from("bob"), String::from("dog")),
+            (String::from("charles"), String::from("bike")),
+        ];
+
+        let data_dict = DataDictionary::from(interactions.iter());
+        let num_items = data_dict.num_items();
+
+        let apple = data_dict.item_index("apple").unwrap();
+        let dog = data_dict.item_index("dog").unwrap();
+        let bike = data_dict.item_index("bike").unwrap();
+
+        let scored_items = scored_indicators(
+            interactions.into_iter(),
+            &data_dict,
+            10,
+            500,
+            500,
+            DEFAULT_SEED,
+        ).unwrap();
+
+        // Every item has a row, even if it never cooccurred with another item
+        assert_eq!(scored_items.num_items(), num_items);
+        assert!(scored_items.indicators_for(bike).is_empty());
+
+        assert_eq!(scored_items.indicators_for(apple)[0].item, dog);
+        assert_eq!(scored_items.indicators_for(dog)[0].item, apple);
+
+        assert_eq!(scored_items.to_binary().len(), num_items);
+    }
+
+    #[test]
+    fn downsampling_is_reproducible() {
+
+        let mut interactions = Vec::new();
+        for user in 0..20 {
+            for item in 0..10 {
+                if (user + item) % 3 != 0 {
+                    interactions.push((format!("user_{}", user), format!("item_{}", item)));
+                }
+            }
+        }
+
+        let data_dict = DataDictionary::from(interactions.iter());
+
+        // We only keep three interactions per user, so the result depends on the sampling
+        let compute = |seed| {
+            scored_indicators(interactions.clone().into_iter(), &data_dict, 5, 500, 3, seed)
+                .unwrap()
+        };
+
+        assert_eq!(compute(DEFAULT_SEED), compute(DEFAULT_SEED));
+        assert_eq!(compute(42), compute(42));
+        assert_ne!(compute(DEFAULT_SEED), compute(42));
+    }
+
+    #[test]
+    fn single_pass_matches_two_passes() {
+
+        let mut interactions = Vec::new();
+        for user in 0..20 {
+            for item in 0..10 {
+                if (user * item) % 4 != 1 {
+                    interactions.push((format!("user_{}", user), format!("item_{}", item)));
+                }
+            }
+        }
+
+        let data_dict = DataDictionary::from(interactions.iter());
+        let two_passes =
+            scored_indicators(interactions.clone().into_iter(), &data_dict, 20, 500, 4, 42)
+                .unwrap();
+
+        let (single_pass_data_dict, single_pass) =
+            single_pass_indicators(interactions.into_iter(), 20, 500, 4, 42).unwrap();
+
+        assert_eq!(single_pass_data_dict.num_interactions(), data_dict.num_interactions());
+        assert_eq!(single_pass_data_dict.num_items(), data_dict.num_items());
+        assert_eq!(single_pass.num_items(), two_passes.num_items());
+        assert_eq!(single_pass.to_binary(), two_passes.to_binary());
+    }
+
+    #[test]
+    fn repeated_interactions_count_once() {
+
+        let interactions = vec![
+            (String::from("alice"), String::from("apple")),
+            (String::from("alice"), String::from("dog")),
+            (String::from("alice"), String::from("pony")),
+            (String::from("bob"), String::from("apple")),
+            (String::from("bob"), String::from("pony")),
+            (String::from("charles"), String::from("pony")),
+            (String::from("charles"), String::from("bike")),
+        ];
+
+        let mut repeated_interactions = interactions.clone();
+        repeated_interactions.push((String::from("alice"), String::from("dog")));
+        repeated_interactions.push((String::from("bob"), String::from("pony")));
+        repeated_interactions.push((String::from("alice"), String::from("dog")));
+
+        let data_dict = DataDictionary::from(interactions.iter());
+
+        let unique = interactions.clone().into_iter();
+        let expected = scored_indicators(unique, &data_dict, 10, 500, 500, 42);
+        let repeated = repeated_interactions.clone().into_iter();
+        let scored_items = scored_indicators(repeated, &data_dict, 10, 500, 500, 42);
+
+        assert_eq!(scored_items.unwrap(), expected.unwrap());
+
+        let (single_pass_data_dict, single_pass) =
+            single_pass_indicators(repeated_interactions.into_iter(), 10, 500, 500, 42).unwrap();
+        let expected = indicators(interactions.into_iter(), &data_dict, 10, 500, 500, 42);
+
+        assert_eq!(single_pass_data_dict.num_interactions(), 10);
+        assert_eq!(single_pass.to_binary(), expected.unwrap());
+    }
+
+    #[test]
+    fn configured_indicators_match_positional_tunables() {
+
+        let mut interactions = Vec::new();
+        for user in 0..20 {
+            for item in 0..10 {
+                if (user + 2 * item) % 5 != 0 {
+                    interactions.push((format!("user_{}", user), format!("item_{}", item)));
+                }
+            }
+        }
+
+        let data_dict = DataDictionary::from(interactions.iter());
+
+        // Three interactions per user and ten per item
+        let config = IndicatorConfig::builder()
+            .num_indicators_per_item(5)
+            .max_interactions_per_user(3)
+            .max_interactions_per_item(10)
+            .seed(42)
+            .build()
+            .unwrap();
+
+        let configured =
+            indicators_with_config(interactions.clone().into_iter(), &data_dict, &config).unwrap();
+        let positional =
+            scored_indicators(interactions.into_iter(), &data_dict, 5, 10, 3, 42).unwrap();
+
+        assert_eq!(configured, positional);
+    }
+
+    #[test]
+    fn cross_indicators_for_views_and_purchases() {
+
+        let purchases = vec![
+            (String::from("alice"), String::from("pony")),
+            (String::from("bob"), String::from("pony")),
+            (String::from("charles"), String::from("bike")),
+        ];
+
+        let views = vec![
+            (String::from("alice"), String::from("pony")),
+            (String::from("alice"), String::from("apple")),
+            (String::from("bob"), String::from("apple")),
+            (String::from("charles"), String::from("dog")),
+        ];
+
+        let data_dict = DataDictionary::from(purchases.iter().chain(views.iter()));
+
+        let scored_items = cross_indicators(
+            purchases.into_iter(),
+            views.into_iter(),
+            &data_dict,
+            10,
+            500,
+            500,
+            DEFAULT_SEED,
+        ).unwrap();
+
+        let pony = data_dict.item_index("pony").unwrap();
+        let apple = data_dict.item_index("apple").unwrap();
+        let dog = data_dict.item_index("dog").unwrap();
+        let bike = data_dict.item_index("bike").unwrap();
+
+        // Items can indicate themselves across interaction types
+        assert!(scored_items.indicators_for(pony).iter().any(|scored| scored.item == pony));
+        assert!(scored_items.indicators_for(apple).iter().all(|scored| scored.item == pony));
+        assert_eq!(scored_items.indicators_for(dog)[0].item, bike);
+        // Bike was never viewed
+        assert!(scored_items.indicators_for(bike).is_empty());
+    }
+
+    #[test]
+    fn cross_occurrences_reach_maximum_sums() {
+
+        // With f_max = k_max = 2, the row sum of item 2 is exactly f_max * k_max
+        let primary = vec![
+            (String::from("0"), String::from("0")),
+            (String::from("0"), String::from("1")),
+            (String::from("1"), String::from("0")),
+            (String::from("1"), String::from("1")),
+        ];
+
+        let secondary = vec![
+            (String::from("0"), String::from("2")),
+            (String::from("1"), String::from("2")),
+        ];
+
+        let data_dict = DataDictionary::from(primary.iter().chain(secondary.iter()));
+
+        let scored_items = cross_indicators(
+            primary.into_iter(),
+            secondary.into_iter(),
+            &data_dict,
+            10,
+            2,
+            2,
+            DEFAULT_SEED,
+        ).unwrap();
+
+        let item = data_dict.item_index("2").unwrap();
+        assert_eq!(scored_items.indicators_for(item).len(), 2);
+    }
+
+    #[test]
+    fn configured_cross_indicators() {
+
+        let purchases = vec![
+            (String::from("alice"), String::from("pony")),
+            (String::from("bob"), String::from("pony")),
+            (String::from("charles"), String::from("bike")),
+        ];
+
+        let views = vec![
+            (String::from("alice"), String::from("pony")),
+            (String::from("alice"), String::from("apple")),
+            (String::from("bob"), String::from("apple")),
+            (String::from("charles"), String::from("dog")),
+        ];
+
+        let data_dict = DataDictionary::from(purchases.iter().chain(views.iter()));
+
+        let config = IndicatorConfig::builder()
+            .measure(Measure::Jaccard)
+            .min_cooccurrences(2)
+            .build()
+            .unwrap();
+
+        let scored_items = cross_indicators_with_config(
+            purchases.into_iter(),
+            views.into_iter(),
+            &data_dict,
+            &config,
+        ).unwrap();
+
+        let pony = data_dict.item_index("pony").unwrap();
+        let apple = data_dict.item_index("apple").unwrap();
+        let dog = data_dict.item_index("dog").unwrap();
+
+        // Two of the three cross-occurrences of apple and pony are shared
+        assert_eq!(scored_items.indicators_for(apple).len(), 1);
+        assert_eq!(scored_items.indicators_for(apple)[0].item, pony);
+        assert_eq!(scored_items.indicators_for(apple)[0].score, 2.0 / 3.0);
+        // Pony and dog only cross-occur once with their indicators
+        assert!(scored_items.indicators_for(pony).is_empty());
+        assert!(scored_items.indicators_for(dog).is_empty());
+    }
+
+    #[test]
+    fn failures_instead_of_panics() {
+
+        let interactions = vec![
+            (String::from("alice"), String::from("apple")),
+            (String::from("bob"), String::from("pony")),
+        ];
+
+        let data_dict = DataDictionary::from(interactions[..1].iter());
+
+        match scored_indicators(interactions.clone().into_iter(), &data_dict, 10, 500, 500, 42) {
+            Err(Error::UnknownItem(item)) => assert_eq!(item, "pony"),
+            other => panic!("Expected an unknown item, got {:?}", other),
+        }
+
+        match scored_indicators(interactions.into_iter(), &data_dict, 10, 0, 500, 42) {
+            Err(Error::Config(_)) => {},
+            other => panic!("Expected a configuration error, got {:?}", other),
+        }
+    }
+
+}
@@ -0,0 +1,134 @@
+//! ## Weighted interactions
+//!
+//! Many interaction datasets carry a weight per interaction, e.g., a rating or a play count. By
+//! default, every interaction counts once, regardless of its weight. A `Weighting` decides how the
+//! weights influence which interactions we keep when downsampling the history of a user. Note that
+//! the weights never scale the cooccurrence counts: every kept interaction counts once.
+//!
+/*
+ * RecoReco
+ * Copyright (C) 2018 Sebastian Schelter
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License
+ * along with this program. If not, see <http://www.gnu.org/licenses/>.
+ */
+
+use std::fmt;
+use std::str::FromStr;
+
+/// How the weights of the interactions influence the downsampling of the user histories.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Weighting {
+    /// Every interaction counts once and we keep a uniform sample per user (the default)
+    #[default]
+    Unweighted,
+    /// Only interactions with a weight of at least the threshold count, e.g., ratings of 4 or more
+    Threshold(f64),
+    /// The logarithm of the weight (`ln(1 + weight)`) is the sampling priority of an interaction,
+    /// e.g., for play counts. All interactions with a positive weight are kept until the history
+    /// of a user reaches `k_max` interactions. Afterwards, a new interaction replaces a kept one
+    /// via weighted reservoir sampling, so that interactions with larger weights are more likely
+    /// to remain in the history. Interactions with a weight of zero or less are ignored.
+    LogPriority,
+    /// Only the given number of interactions with the highest weights count per user
+    TopK(usize),
+}
+
+impl Weighting {
+
+    /// Whether an interaction with the given weight is accounted for at all
+    pub fn admits(&self, weight: f64) -> bool {
+        match *self {
+            Weighting::Unweighted | Weighting::TopK(_) => true,
+            Weighting::Threshold(threshold) => weight >= threshold,
+            Weighting::LogPriority => weight > 0.0,
+        }
+    }
+
+    /// Whether the downsampling has to retain the weights of the sampled interactions
+    pub fn is_prioritised(&self) -> bool {
+        match *self {
+            Weighting::LogPriority | Weighting::TopK(_) => true,
+            Weighting::Unweighted | Weighting::Threshold(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for Weighting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Weighting::Unweighted => write!(f, "none"),
+            Weighting::Threshold(threshold) => write!(f, "threshold:{}", threshold),
+            Weighting::LogPriority => write!(f, "log-priority"),
+            Weighting::TopK(k) => write!(f, "top-k:{}", k),
+        }
+    }
+}
+
+impl FromStr for Weighting {
+    type Err = String;
+
+    /// Parses `none`, `threshold:<weight>`, `log-priority` or `top-k:<number>`, and accepts `log`
+    /// as the former name of `log-priority`
+    fn from_str(description: &str) -> Result<Self, Self::Err> {
+
+        let mut parts = description.splitn(2, ':');
+        let name = parts.next().unwrap_or("");
+        let argument = parts.next();
+
+        let invalid = || {
+            format!(
+                "Unknown weighting '{}', expected one of none, threshold:<weight>, log-priority \
+                or top-k:<number>",
+                description,
+            )
+        };
+
+        match (name, argument) {
+            ("none", None) => Ok(Weighting::Unweighted),
+            ("log-priority", None) | ("log", None) => Ok(Weighting::LogPriority),
+            ("threshold", Some(threshold)) => {
+                threshold.parse().map(Weighting::Threshold).map_err(|_| invalid())
+            },
+            ("top-k", Some(k)) => match k.parse() {
+                Ok(k) if k > 0 => Ok(Weighting::TopK(k)),
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use weights::Weighting;
+
+    #[test]
+    fn parse_weightings() {
+        assert_eq!("none".parse(), Ok(Weighting::Unweighted));
+        assert_eq!("threshold:3.5".parse(), Ok(Weighting::Threshold(3.5)));
+        assert_eq!("log-priority".parse(), Ok(Weighting::LogPriority));
+        assert_eq!("log".parse(), Ok(Weighting::LogPriority));
+        assert_eq!("top-k:50".parse(), Ok(Weighting::TopK(50)));
+
+        assert!("top-k:0".parse::<Weighting>().is_err());
+        assert!("threshold".parse::<Weighting>().is_err());
+        assert!("log:2".parse::<Weighting>().is_err());
+
+        for weighting in &[Weighting::Threshold(2.0), Weighting::LogPriority, Weighting::TopK(3)] {
+            assert_eq!(weighting.to_string().parse(), Ok(*weighting));
+        }
+    }
+}